@@ -1,7 +1,24 @@
 use clap::Parser;
+use core::safety::SafetyLevel;
+use core::shell::{Shell, TargetOs};
+use std::fmt;
+use std::io;
+
+const EXIT_CODES_HELP: &str = "Exit codes:
+    0  Success
+    1  No command could be generated (or another error)
+    2  The command ran but failed
+    3  The command was blocked by policy or a rate limit
+    4  Invalid configuration, arguments or files
+  130  Interactive mode was quit with Ctrl-C";
 
 #[derive(Parser, Debug)]
-#[clap(author, version, about = "A natural language shell command assistant")]
+#[clap(
+    author,
+    version,
+    about = "A natural language shell command assistant",
+    after_help = EXIT_CODES_HELP
+)]
 pub struct CliArgs {
     /// Natural language input for the shell command
     #[clap(value_parser)]
@@ -11,18 +28,113 @@ pub struct CliArgs {
     #[clap(short, long, action)]
     pub dry_run: bool,
 
+    /// Only show and explain commands, never run or copy them. Stronger than --dry-run.
+    #[clap(long, action)]
+    pub read_only: bool,
+
+    /// Generate commands for another OS (`windows` or `unix`). They can be copied but not run.
+    #[clap(long, value_parser, value_name = "OS")]
+    pub target_os: Option<TargetOs>,
+
+    /// Write commands for this shell (`bash`, `zsh`, `fish`, `powershell` or `cmd`) instead of
+    /// the one detected from $SHELL
+    #[clap(long, value_parser, value_name = "SHELL")]
+    pub shell: Option<Shell>,
+
+    /// Show each suggested command and its explanation on one line, without the banner
+    #[clap(long, action)]
+    pub compact: bool,
+
     /// Show command history
     #[clap(short = 'H', long, action)]
     pub history: bool,
 
+    /// Run the most recent history entry's command again, with the usual safety prompt
+    #[clap(long, action, conflicts_with_all = ["input", "interactive"])]
+    pub repeat_last: bool,
+
+    /// Show the last command that failed when run through the assistant, and offer to ask
+    /// the LLM how to fix it
+    #[clap(long, action, conflicts_with_all = ["input", "interactive"])]
+    pub last_error: bool,
+
+    /// When a command runs and fails, offer to generate a command that fixes it, using the
+    /// error output and exit code as context, and run the fix the same way
+    #[clap(long, action)]
+    pub auto_remediate: bool,
+
+    /// Save the most recent history entry's command as a favorite under this name
+    #[clap(long, value_parser, value_name = "NAME")]
+    pub favorite_add: Option<String>,
+
+    /// List the favorite commands
+    #[clap(long, action)]
+    pub favorites: bool,
+
+    /// Run a favorite command, with the usual safety prompt
+    #[clap(long, value_parser, value_name = "NAME", conflicts_with_all = ["input", "interactive"])]
+    pub run_favorite: Option<String>,
+
+    /// Include what each command printed when showing the history
+    #[clap(long, action, requires = "history")]
+    pub with_output: bool,
+
+    /// Show this page of the history or audit report. In a terminal, lists longer than a
+    /// page start on the last page, with the newest entries.
+    #[clap(long, value_parser, value_name = "N")]
+    pub page: Option<usize>,
+
+    /// Entries per page of the history or audit report
+    #[clap(long, value_parser, value_name = "M", default_value_t = 20)]
+    pub page_size: usize,
+
+    /// Write the history's input→command pairs to a local JSONL file, with the home
+    /// directory, username, hostname and secrets replaced by placeholders
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub export_corpus: Option<String>,
+
+    /// Compare the history with another history file, e.g. from another machine
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub diff_history: Option<String>,
+
+    /// Print machine-readable JSON instead of formatted text (supported by --history and
+    /// --diff-history)
+    #[clap(long, action)]
+    pub json: bool,
+
     /// List available plugins
     #[clap(short = 'L', long, action)]
     pub list_plugins: bool,
 
+    /// Print a plugin as a declarative `*.toml` definition, as a template for writing one
+    #[clap(long, value_parser, value_name = "NAME")]
+    pub dump_plugin: Option<String>,
+
+    /// Keep reading requests until EOF or `exit`
+    #[clap(short, long, action)]
+    pub interactive: bool,
+
+    /// Directory of declarative `*.toml` plugins
+    /// Default: ~/.shell-assistant/plugins
+    #[clap(long, value_parser)]
+    pub plugin_dir: Option<String>,
+
+    /// Reload declarative plugins when their files change (interactive mode only)
+    #[clap(long, action, requires = "interactive")]
+    pub watch_plugins: bool,
+
     /// Path to config file
     #[clap(short, long, value_parser)]
     pub config: Option<String>,
 
+    /// Named config profile to load from ~/.shell-assistant/profiles/<name>.yaml
+    #[clap(long, value_parser, conflicts_with = "config")]
+    pub profile: Option<String>,
+
+    /// List available config profiles
+    #[clap(long, action)]
+    pub list_profiles: bool,
+
     /// LLM backend to use (ollama, llm-rs, openai)
     /// - ollama: Uses the Ollama API (http://localhost:11434) with codellama model
     /// - llm-rs: Uses the llm-rs crate with a local GGUF model
@@ -43,6 +155,11 @@ pub struct CliArgs {
     #[clap(long, action)]
     pub debug: bool,
 
+    /// Show how long each phase of handling a request took, such as plugin matching, LLM
+    /// generation and execution (also shown with --debug)
+    #[clap(long, action)]
+    pub timings: bool,
+
     /// Force execution without safety prompts
     #[clap(long, action)]
     pub force: bool,
@@ -51,6 +168,19 @@ pub struct CliArgs {
     #[clap(long, value_parser)]
     pub plugin: Option<String>,
 
+    /// Have plugins prefer JSON/porcelain variants of the commands they generate
+    #[clap(long, action)]
+    pub machine_readable: bool,
+
+    /// Have the LLM explain plugin commands instead of using the plugin's short explanation
+    #[clap(long, action)]
+    pub enrich_explanations: bool,
+
+    /// Print the exact prompt that would be sent to the LLM for the request and exit,
+    /// without contacting any backend
+    #[clap(long, action)]
+    pub print_prompt: bool,
+
     /// Path to local LLM model for llm-rs backend
     /// Default: "models/tinyllama.gguf"
     #[clap(long, value_parser)]
@@ -61,6 +191,23 @@ pub struct CliArgs {
     #[clap(long, value_parser)]
     pub openai_model: Option<String>,
 
+    /// Seconds to wait for Ollama or OpenAI to respond before giving up, overriding
+    /// `llm.timeout_secs` (default: 30)
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), value_name = "SECS")]
+    pub timeout: Option<u64>,
+
+    /// Attach a text file as context for the request (repeatable)
+    #[clap(long = "file", value_name = "PATH", value_parser)]
+    pub files: Vec<String>,
+
+    /// Record each LLM prompt and response to a JSON file
+    #[clap(long, value_parser, conflicts_with = "replay")]
+    pub record: Option<String>,
+
+    /// Serve LLM responses recorded with --record instead of calling a model
+    #[clap(long, value_parser)]
+    pub replay: Option<String>,
+
     /// Path to history file
     /// Default: ~/.shell-assistant/history.json
     #[clap(long, value_parser)]
@@ -69,11 +216,240 @@ pub struct CliArgs {
     /// Disable feedback prompts
     #[clap(long, action)]
     pub no_feedback: bool,
+
+    /// Interactively test commands against the safety rules (reads commands from stdin)
+    #[clap(long, action)]
+    pub test_safety: bool,
+
+    /// Time the same request on each available backend and check the responses parse.
+    /// Generated commands are never executed.
+    #[clap(long, value_parser, value_name = "PROMPT")]
+    pub bench: Option<String>,
+
+    /// Generate a command for each request in a file (one per line) and print them in order.
+    /// Up to `llm.max_concurrent_requests` requests run at once; commands are never executed.
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub batch: Option<String>,
+
+    /// Interactively choose a backend, check its prerequisites and write a starting config
+    #[clap(long, action)]
+    pub setup: bool,
+
+    /// Print the JSON Schema of config.yaml and exit (requires the config-schema feature)
+    #[clap(long, action)]
+    pub config_schema: bool,
+
+    /// Print the audit log entries matching the --audit-* filters and exit
+    #[clap(long, action)]
+    pub audit_report: bool,
+
+    /// Only report entries of this user
+    #[clap(long, value_parser, value_name = "USER", requires = "audit_report")]
+    pub audit_user: Option<String>,
+
+    /// Only report entries with this safety level (repeatable)
+    #[clap(long, value_parser, value_name = "LEVEL", requires = "audit_report")]
+    pub audit_level: Vec<SafetyLevel>,
+
+    /// Only report commands produced by this plugin or LLM backend
+    #[clap(long, value_parser, value_name = "BACKEND", requires = "audit_report")]
+    pub audit_backend: Option<String>,
+
+    /// Only report commands that were executed
+    #[clap(long, action, requires = "audit_report")]
+    pub audit_executed_only: bool,
+
+    /// Only report commands containing this text
+    #[clap(long, value_parser, value_name = "TEXT", requires = "audit_report")]
+    pub audit_contains: Option<String>,
+
+    /// Check that a history file can be read, reporting malformed and out-of-order entries,
+    /// and exit. The file is not modified.
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub validate_history: Option<String>,
+
+    /// Check that an audit log can be read, reporting malformed lines and out-of-order
+    /// entries, and exit. The file is not modified.
+    #[clap(long, value_parser, value_name = "FILE")]
+    pub validate_audit: Option<String>,
+
+    /// Start a new audit session instead of continuing the recent one
+    #[clap(long, action)]
+    pub new_session: bool,
+
+    /// Disable colored output
+    #[clap(long, action)]
+    pub no_color: bool,
+
+    /// Show the model's response as it's generated (OpenAI and Ollama; other backends show
+    /// it once it's complete). Not used with llm.fallback_chain.
+    #[clap(long, action)]
+    pub stream: bool,
+
+    /// Keep trailing `# comments` in generated commands instead of stripping them
+    #[clap(long, action)]
+    pub keep_comments: bool,
+
+    /// Path to a .env file to load (e.g. for OPENAI_API_KEY)
+    /// Default: the nearest .env found walking up from the current directory
+    #[clap(long, value_parser)]
+    pub env_file: Option<String>,
+}
+
+/// Restore the default SIGPIPE handler so that writing to a closed pipe (as in
+/// `shell-assistant --history | head`) ends the process quietly, like other Unix tools.
+/// Rust ignores SIGPIPE by default, which turns those writes into a `println!` panic.
+pub fn reset_sigpipe() {
+    #[cfg(unix)]
+    // SAFETY: called at startup before any other threads exist, and SIG_DFL is a valid handler
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+}
+
+/// Why the assistant failed, as a stable exit code scripts can branch on (see
+/// `EXIT_CODES_HELP`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// No command could be generated
+    Generation = 1,
+    /// The command ran but failed
+    Execution = 2,
+    /// The command was blocked by policy or a rate limit
+    Blocked = 3,
+    /// Invalid configuration, arguments or files
+    Config = 4,
+    /// Interactive mode was quit with Ctrl-C, the shells' code for SIGINT
+    Interrupted = 130,
 }
 
+// The error carried inside the `io::Error` made by `ExitReason::error`
+#[derive(Debug)]
+struct ExitError {
+    reason: ExitReason,
+    message: String,
+}
+
+impl fmt::Display for ExitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ExitError {}
+
+impl ExitReason {
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// An error that makes the assistant exit with this reason's code
+    pub fn error(self, message: impl Into<String>) -> io::Error {
+        io::Error::other(ExitError { reason: self, message: message.into() })
+    }
+
+    /// The reason an error was made with, if it was made by `ExitReason::error`
+    pub fn of(error: &io::Error) -> Option<ExitReason> {
+        error.get_ref()?.downcast_ref::<ExitError>().map(|error| error.reason)
+    }
+}
+
+/// One page of a long listing, such as the history
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    /// 1-based page number
+    pub number: usize,
+    /// How many pages there are
+    pub pages: usize,
+    /// The range of entries on this page
+    pub range: std::ops::Range<usize>,
+}
+
+/// Split `total` entries into pages of `page_size` and pick page `number` (1-based).
+/// Numbers past either end pick the first or last page.
+pub fn paginate(total: usize, number: usize, page_size: usize) -> Page {
+    let page_size = page_size.max(1);
+    let pages = total.div_ceil(page_size).max(1);
+    let number = number.clamp(1, pages);
+    let start = (number - 1) * page_size;
+    Page { number, pages, range: start..(start + page_size).min(total) }
+}
+
+/// Copy text to the system clipboard. Without one, as over SSH or on a headless Linux box
+/// with no display server, the text is printed with a hint so it can be copied by hand. On
+/// Linux it's printed too, since the clipboard loses it when the program exits.
 pub fn copy_to_clipboard(text: &str) -> Result<(), String> {
-    // This is a placeholder function - in a real application we would use
-    // a clipboard library like `clipboard` or `arboard`
-    println!("Text copied to clipboard: {}", text);
+    let mut clipboard = match arboard::Clipboard::new() {
+        Ok(clipboard) => clipboard,
+        Err(e) => {
+            println!("\nNo clipboard is available here, so copy the command yourself:");
+            println!("{}", text);
+            println!(
+                "(Over SSH, select it in your terminal. On Linux, a display server is needed.)"
+            );
+            return Err(format!("could not open the clipboard: {}", e));
+        }
+    };
+    clipboard.set_text(text).map_err(|e| format!("could not copy to the clipboard: {}", e))?;
+    keep_clipboard(clipboard, text);
     Ok(())
 }
+
+// On Linux the clipboard's content is served by the program that set it, and is lost once the
+// clipboard is dropped. A thread keeps serving it until something else is copied, but that
+// ends with the program too, so the command is printed as well for copying by hand.
+#[cfg(target_os = "linux")]
+fn keep_clipboard(mut clipboard: arboard::Clipboard, text: &str) {
+    use arboard::SetExtLinux;
+
+    let owned = text.to_string();
+    std::thread::spawn(move || {
+        let _ = clipboard.set().wait().text(owned);
+    });
+    println!("\n(The clipboard only keeps it while this program runs. The command is:)");
+    println!("{}", text);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn keep_clipboard(_clipboard: arboard::Clipboard, _text: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_reason_round_trip() {
+        let error = ExitReason::Blocked.error("Command refused");
+        assert_eq!(error.to_string(), "Command refused");
+        assert_eq!(ExitReason::of(&error), Some(ExitReason::Blocked));
+        assert_eq!(ExitReason::of(&error).unwrap().code(), 3);
+
+        assert_eq!(ExitReason::of(&io::Error::other("unexpected")), None);
+    }
+
+    #[test]
+    fn test_paginate() {
+        let page = paginate(45, 2, 20);
+        assert_eq!(page, Page { number: 2, pages: 3, range: 20..40 });
+
+        // The last page is shorter, and numbers out of range are clamped
+        assert_eq!(paginate(45, 3, 20).range, 40..45);
+        assert_eq!(paginate(45, 9, 20).number, 3);
+        assert_eq!(paginate(45, 0, 20).range, 0..20);
+        assert_eq!(paginate(0, 1, 20), Page { number: 1, pages: 1, range: 0..0 });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reset_sigpipe_restores_default_handler() {
+        reset_sigpipe();
+
+        // SAFETY: only queries the current SIGPIPE action
+        let handler = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(libc::SIGPIPE, std::ptr::null(), &mut action);
+            action.sa_sigaction
+        };
+        assert_eq!(handler, libc::SIG_DFL);
+    }
+}
@@ -1,102 +1,610 @@
-use chrono;
 use clap::Parser;
-use cli::{copy_to_clipboard, CliArgs};
+use cli::{copy_to_clipboard, paginate, reset_sigpipe, CliArgs, ExitReason, Page};
 use colored::*;
 use console::Term;
-use core::llm::{LLMEngine, LlmRsProvider, OllamaProvider, OpenAIProvider};
-use core::{construct_prompt, generate_command, LLMError, LLMProvider};
-use executor::shell::{FeedbackAction, ShellExecutor, UserAction};
-use plugins::{DockerPlugin, GitPlugin, PluginManager};
-use std::io::{self, Write};
+use core::audit::current_user;
+use core::config::{BackendKind, LLMConfig, RoutingPolicy};
+use core::llm::{
+    build_chain, generate_with_chain_progress, load_env_file, LLMEngine, LlmRsProvider,
+    OllamaProvider, OpenAIProvider, RecordingProvider, ReplayProvider, DEFAULT_MODEL_PATH,
+};
+use core::safety::{CommandSafetyChecker, SafetyLevel};
+use core::session::{Session, SESSION_TTL};
+use core::{
+    audit_notes, construct_explanation_prompt, construct_prompt, construct_prompt_with_context,
+    detect_os_version, dry_run_outcome, generate_command, generate_command_with_chain,
+    generate_commands, home_dir, parse_response, parse_response_with_options, preprocess_input,
+    template_for_model, AbbreviationExpander, AttachedFile, AuditEntry, AuditLogger, AuditQuery,
+    ChainAttempt, Correction, EnterpriseConfig, EntryCheck, FailedAttempt, InputPreprocessor,
+    IntegrityReport, LLMError, LLMProvider, ParseOptions, PromptContext, Shell, TargetOs,
+    DEFAULT_RETRY_MESSAGE,
+};
+use dialoguer::{theme::ColorfulTheme, Confirm, Select};
+use executor::shell::{
+    run_blocking, CommandFailed, CommandOutput, FeedbackAction, ShellExecutor, UserAction,
+};
+use executor::{Theme, DEFAULT_MAX_COMMAND_LINES};
+use plugins::{CommandResult, PluginContext, PluginManager, PluginWatcher, ToolAvailability};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use storage::anonymize::redact_secrets;
 use storage::persistence::FeedbackType;
-use storage::CommandHistory;
+use storage::{
+    CommandHistory, ExplanationCache, Favorites, HistoryDiff, LastError, PersistentHistory,
+};
+use table::Table;
+use timings::Timings;
 
+mod setup;
+mod table;
+mod timings;
+mod warnings;
+
+// Fixes offered in a row with --auto-remediate, so a fix that keeps failing can't loop forever
+const MAX_REMEDIATIONS: usize = 3;
+
+// Errors have been reported by the time they get here, except unexpected I/O errors
 #[tokio::main]
-async fn main() -> Result<(), io::Error> {
-    let args = CliArgs::parse();
-    let executor = ShellExecutor::new();
+async fn main() {
+    if let Err(e) = run().await {
+        let reason = ExitReason::of(&e);
+        if reason.is_none() {
+            eprintln!("Error: {}", e);
+        }
+        std::process::exit(reason.map_or(1, ExitReason::code));
+    }
+}
+
+async fn run() -> io::Result<()> {
+    // Exit quietly when piped into something like `head` that stops reading early
+    reset_sigpipe();
+
+    let mut args = CliArgs::parse();
     let _term = Term::stdout();
 
-    // Initialize command history with persistence
-    let mut history = if let Some(custom_path) = &args.history_file {
-        CommandHistory::with_persistence(custom_path.clone())
+    // Colors are disabled entirely with --no-color; otherwise the configured theme
+    // takes over once the config has been loaded
+    if args.no_color {
+        colored::control::set_override(false);
+    }
+    let theme = if args.no_color { Theme::plain() } else { Theme::default() };
+
+    // Load environment variables from --env-file or the nearest .env file
+    let env_file = match load_env_file(args.env_file.as_deref().map(Path::new)) {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+            return Err(ExitReason::Config.error(e.to_string()));
+        }
+    };
+
+    // Read the attached files up front so a bad path fails before any work is done
+    let mut attachments = Vec::new();
+    for path in &args.files {
+        match AttachedFile::read(Path::new(path)) {
+            Ok(file) => attachments.push(file),
+            Err(e) => {
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+                return Err(ExitReason::Config.error(e.to_string()));
+            }
+        }
+    }
+
+    if args.config_schema {
+        return print_config_schema(&theme);
+    }
+
+    if args.setup {
+        let config_path = args.config.as_deref().map(Path::new);
+        return setup::run_setup(&theme, config_path, args.env_file.as_deref().map(Path::new))
+            .await;
+    }
+
+    // Handle list profiles command
+    if args.list_profiles {
+        match EnterpriseConfig::list_profiles() {
+            Ok(profiles) if profiles.is_empty() => {
+                println!("{}", theme.warning("No config profiles found."));
+            }
+            Ok(profiles) => {
+                println!("\n{}", theme.info("🗂️ Available Profiles:"));
+                println!("{}", theme.info("--------------------"));
+                for name in profiles {
+                    println!("{}", theme.success(&name));
+                }
+            }
+            Err(e) => {
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+                return Err(ExitReason::Config.error(e.to_string()));
+            }
+        }
+        return Ok(());
+    }
+
+    // Load the system and user configuration, overridden by --profile or --config
+    let loaded_config = match &args.profile {
+        Some(name) => EnterpriseConfig::profile_path(name).map(Some),
+        None => Ok(args.config.as_ref().map(PathBuf::from)),
+    }
+    .and_then(EnterpriseConfig::load_layered);
+    let mut config = match loaded_config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+            return Err(ExitReason::Config.error(e.to_string()));
+        }
+    };
+    let theme = if args.no_color {
+        theme
     } else {
-        match CommandHistory::default_history_path() {
-            Ok(path) => CommandHistory::with_persistence(path),
+        match Theme::from_config(&config.theme) {
+            Ok(theme) => theme,
             Err(e) => {
-                eprintln!(
-                    "{} {}",
-                    "⚠️ Warning:".yellow(),
-                    format!("Could not determine history file path: {}", e).yellow()
-                );
-                CommandHistory::new()
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e));
+                return Err(ExitReason::Config.error(e));
             }
         }
     };
+    let offline = args.offline || config.privacy.offline_only;
+
+    // Sessions that make many requests print each repeated warning, such as an LLM
+    // fallback, once and summarize the repeats at the end
+    let warnings = warnings::init(&theme, args.debug, args.interactive || args.batch.is_some());
+
+    // Commands generated for another OS are only shown or copied, never run here
+    config.llm.target_os = args.target_os.or(config.llm.target_os);
+    config.llm.timeout_secs = args.timeout.or(config.llm.timeout_secs);
+    if let Some(target) = config.llm.target_os.filter(|target| *target != TargetOs::host()) {
+        println!(
+            "{} Generating commands for {}, not this machine, so they won't be run (dry run).",
+            theme.warning("⚠️ Target OS:"),
+            target.name()
+        );
+        args.dry_run = true;
+    }
 
-    // Initialize plugin manager and register plugins
-    let mut plugin_manager = PluginManager::new();
-    plugin_manager.register_plugin(GitPlugin::new());
-    plugin_manager.register_plugin(DockerPlugin::new());
+    // Commands are written for --shell, `llm.shell` or the user's own shell.
+    // Commands for another OS are written for its usual shell.
+    let shell = args.shell.or(config.llm.shell).unwrap_or_else(|| match config.llm.target_os {
+        Some(target) if target != TargetOs::host() => Shell::default_for(target),
+        _ => Shell::detect(),
+    });
+    config.llm.shell = Some(shell);
+
+    let executor = ShellExecutor::with_safety_checker(config.safety_checker())
+        .with_theme(theme.clone())
+        .with_read_only(args.read_only || config.security.read_only)
+        .with_compact(args.compact)
+        .with_max_command_lines(
+            config.display.max_command_lines.unwrap_or(DEFAULT_MAX_COMMAND_LINES),
+        );
+
+    // Handle the audit report
+    if args.audit_report {
+        return print_audit_report(&args, &config, &theme);
+    }
+
+    // Handle the file integrity checks, which only read the file
+    if let Some(path) = &args.validate_history {
+        let checks = PersistentHistory::check_file(path).map_err(|e| e.to_string());
+        return print_integrity_report(path, checks, &theme);
+    }
+    if let Some(path) = &args.validate_audit {
+        let checks = AuditLogger::new(path).check_entries().map_err(|e| e.to_string());
+        return print_integrity_report(path, checks, &theme);
+    }
+
+    // Handle the interactive safety rule tester
+    if args.test_safety {
+        return run_safety_tester(executor.safety_checker(), &theme);
+    }
+
+    // Handle the backend benchmark
+    if let Some(request) = &args.bench {
+        return run_bench(&args, &config.llm, request, offline, &theme).await;
+    }
+
+    // Initialize command history with persistence
+    let history = if let Some(custom_path) = &args.history_file {
+        CommandHistory::with_persistence(custom_path.clone())
+    } else {
+        let path = config.get_history_path();
+        if let Err(e) = EnterpriseConfig::app_dir() {
+            eprintln!(
+                "{} {}",
+                theme.warning("⚠️ Warning:"),
+                theme.warning(&format!("{}. Storing history in {}", e, path.display()))
+            );
+        }
+        CommandHistory::with_persistence(path.to_string_lossy().into_owned())
+    }
+    .with_exclude_patterns(config.privacy.exclude_patterns_for_home(home_dir().as_deref()));
+
+    // Initialize plugin manager with the built-in plugins
+    let mut plugin_manager = PluginManager::with_default_plugins();
+
+    // Rewrite requests before plugins and the LLM see them; history keeps the original
+    let preprocessors: Vec<Box<dyn InputPreprocessor>> =
+        vec![Box::new(AbbreviationExpander::new(config.input.abbreviations.clone()))];
+
+    // Load declarative plugins from --plugin-dir or ~/.shell-assistant/plugins
+    let plugin_dir = match &args.plugin_dir {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => EnterpriseConfig::plugins_dir().ok(),
+    };
+    if let Some(dir) = &plugin_dir {
+        load_plugins(&mut plugin_manager, dir, &theme);
+    }
 
     // Print debug info if requested
     if args.debug {
         println!(
             "{} {}",
-            "🔍 Debug:".bright_blue(),
-            format!("Command line arguments: {:?}", args).bright_blue()
+            theme.info("🔍 Debug:"),
+            theme.info(&format!("Command line arguments: {:?}", args))
         );
 
         if let Some(path) = history.get_file_path() {
             println!(
                 "{} {}",
-                "🔍 Debug:".bright_blue(),
-                format!("History path: {}", path).bright_blue()
+                theme.info("🔍 Debug:"),
+                theme.info(&format!("History path: {}", path))
+            );
+        }
+
+        if let Some(path) = &env_file {
+            println!(
+                "{} {}",
+                theme.info("🔍 Debug:"),
+                theme.info(&format!("Loaded env file: {}", path.display()))
             );
         }
     }
 
+    // Only the definition goes to stdout, so it can be redirected into a plugin file
+    if let Some(name) = &args.dump_plugin {
+        let Some(plugin) = plugin_manager.get_plugin(name) else {
+            eprintln!(
+                "{} {}",
+                theme.error("❌ Error:"),
+                theme.error(&format!("Plugin '{}' not found", name))
+            );
+            return Err(ExitReason::Config.error(format!("Plugin '{}' not found", name)));
+        };
+        let Some(definition) = plugin.to_declarative() else {
+            let message = format!("Plugin '{}' can't be written as a declarative plugin", name);
+            eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&message));
+            return Err(ExitReason::Config.error(message));
+        };
+        let toml = definition.to_toml().map_err(io::Error::other)?;
+        print!("{}", toml);
+        return Ok(());
+    }
+
     let plugins_list =
         plugin_manager.list_plugins().iter().map(|(name, _)| *name).collect::<Vec<&str>>();
     println!(
         "{} {} {}",
-        "✅ Initialized".green(),
-        plugin_manager.plugin_count().to_string().green(),
-        format!("plugins: {:?}", plugins_list).green()
+        theme.success("✅ Initialized"),
+        theme.success(&plugin_manager.plugin_count().to_string()),
+        theme.success(&format!("plugins: {:?}", plugins_list))
     );
 
     // Handle list plugins command
     if args.list_plugins {
-        println!("\n{}", "🔌 Available Plugins:".bright_cyan());
-        println!("{}", "-------------------".bright_cyan());
+        println!("\n{}", theme.info("🔌 Available Plugins:"));
+        println!("{}", theme.info("-------------------"));
 
+        let mut table = Table::new(&["Plugin", "Description"]);
         for (name, description) in plugin_manager.list_plugins() {
-            println!("{}  {}", name.bright_green(), description);
+            table.add_row(vec![theme.success(name).to_string(), description.to_string()]);
         }
+        print_table(&table, &theme);
 
         return Ok(());
     }
 
+    if let Some(path) = &args.export_corpus {
+        match history.export_anonymized(Path::new(path)) {
+            Ok(count) => println!(
+                "{} {}",
+                theme.success(&format!("✅ Exported {} anonymized entries to", count)),
+                path
+            ),
+            Err(e) => {
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+                return Err(ExitReason::Config.error(e.to_string()));
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = &args.diff_history {
+        let other = match PersistentHistory::load_from_file(path) {
+            Ok(other) => other,
+            Err(e) => {
+                let message = format!("Could not read {}: {}", path, e);
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&message));
+                return Err(ExitReason::Config.error(message));
+            }
+        };
+        let diff = history.diff(&other.entries);
+        if !args.json {
+            display_history_diff(&diff, path, &theme);
+            return Ok(());
+        }
+        match diff.to_json() {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+                return Err(ExitReason::Config.error(e.to_string()));
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(name) = &args.favorite_add {
+        let Some(entry) = history.entries().back() else {
+            println!("{}", theme.warning("No command history found. Nothing to save."));
+            return Ok(());
+        };
+        let saved = Favorites::load(config.get_favorites_path()).and_then(|mut favorites| {
+            favorites.add_favorite(name, entry.command.clone(), entry.explanation.clone())
+        });
+        if let Err(e) = saved {
+            eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+            return Err(ExitReason::Config.error(e.to_string()));
+        }
+        println!(
+            "{} {}",
+            theme.success(&format!("⭐ Saved as '{}':", name.trim())),
+            theme.command(&entry.command)
+        );
+        return Ok(());
+    }
+
+    if args.favorites {
+        match Favorites::load(config.get_favorites_path()) {
+            Ok(favorites) => display_favorites(&favorites, &theme),
+            Err(e) => {
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+                return Err(ExitReason::Config.error(e.to_string()));
+            }
+        }
+        return Ok(());
+    }
+
     // Handle history display if requested
     if args.history {
-        display_history(&history);
+        if !args.json {
+            let page = listing_page(&args, history.entries().len());
+            display_history(&history, &theme, args.with_output, page.as_ref());
+            return Ok(());
+        }
+        match history.to_json() {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+                return Err(ExitReason::Config.error(e.to_string()));
+            }
+        }
         return Ok(());
     }
 
-    // Initialize the appropriate LLM provider based on arguments
-    let provider = match create_llm_provider(&args) {
-        Ok(p) => p,
-        Err(e) => {
-            eprintln!("{} {}", "❌ Error:".bright_red(), e.to_string().bright_red());
-            return Err(io::Error::new(io::ErrorKind::Other, e.to_string()));
+    // Print the prompt the LLM would get, without contacting any backend
+    if args.print_prompt {
+        let Some(input) = &args.input else {
+            let message = "--print-prompt needs a request to build the prompt for";
+            eprintln!("{} {}", theme.error("❌ Error:"), theme.error(message));
+            return Err(ExitReason::Config.error(message));
+        };
+        let mut context = prompt_context(
+            &history,
+            input,
+            None,
+            &attachments,
+            config.security.prefer_no_sudo,
+            config.llm.target_os,
+            config.llm.persona.as_deref(),
+        );
+        context.shell = config.llm.shell;
+        let model = first_backend(&args, &config.llm, input, offline)
+            .and_then(|backend| backend_model(&args, backend, offline));
+        context.template = model
+            .and_then(|model| template_for_model(&config.llm.prompt_templates, model))
+            .map(str::to_string);
+        let request = preprocess_input(&preprocessors, input);
+        println!("{}", construct_prompt_with_context(&request, &context));
+        return Ok(());
+    }
+
+    // Initialize the configured fallback chain, or the LLM provider selected by the arguments.
+    // --replay serves recorded responses instead. llm.routing picks a provider for each
+    // request handled on its own, so it doesn't apply to --batch, --record or --replay.
+    let use_chain = !config.llm.fallback_chain.is_empty();
+    let routes =
+        !use_chain && args.replay.is_none() && args.record.is_none() && args.batch.is_none();
+    let router = match &config.llm.routing {
+        Some(policy) if routes => {
+            let router = create_router(&args, &config.llm, policy, offline, &theme);
+            if router.is_none() {
+                let message = "No usable LLM providers in llm.routing";
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(message));
+                return Err(ExitReason::Config.error(message));
+            }
+            router
+        }
+        _ => None,
+    };
+    let providers = if let Some(path) = &args.replay {
+        match ReplayProvider::load(Path::new(path)) {
+            Ok(replay) => vec![Arc::new(LLMProvider::Replay(replay))],
+            Err(e) => {
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+                return Err(ExitReason::Config.error(e.to_string()));
+            }
+        }
+    } else if use_chain {
+        create_provider_chain(&args, &config.llm, &config.llm.fallback_chain, offline, &theme)
+    } else if let Some(router) = &router {
+        vec![router.short.clone(), router.long.clone()]
+    } else {
+        match create_llm_provider(&args, &config.llm, offline, &theme) {
+            Ok(p) => vec![Arc::new(p)],
+            Err(e) => {
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+                return Err(ExitReason::Config.error(e.to_string()));
+            }
+        }
+    };
+
+    if providers.is_empty() {
+        let message = "No usable LLM providers in llm.fallback_chain";
+        eprintln!("{} {}", theme.error("❌ Error:"), theme.error(message));
+        return Err(ExitReason::Config.error(message));
+    }
+
+    // Record every response of the providers with --record
+    let providers = match &args.record {
+        Some(path) => match RecordingProvider::new(providers, use_chain, PathBuf::from(path)) {
+            Ok(recorder) => vec![Arc::new(LLMProvider::Recording(Box::new(recorder)))],
+            Err(e) => {
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+                return Err(ExitReason::Config.error(e.to_string()));
+            }
+        },
+        None => providers,
+    };
+
+    if let Some(path) = &args.batch {
+        let context = BatchContext {
+            history: &history,
+            attachments: &attachments,
+            config: &config,
+            preprocessors: &preprocessors,
+        };
+        let result = run_batch(&args, path, &providers, use_chain, &context, &theme).await;
+        warnings.summarize();
+        return result;
+    }
+
+    // Audit commands if enabled, grouping invocations within the session TTL under one ID.
+    // The per-user rate limit is counted from the audit log, so it needs one too.
+    let audit_enabled = config.audit.enabled || config.security.max_commands_per_hour.is_some();
+    let audit = audit_enabled.then(|| {
+        let session_path = config.get_session_path();
+        let session = Session::resume_or_start(&session_path, SESSION_TTL, args.new_session);
+        Audit {
+            logger: AuditLogger::new(config.get_audit_log_path()),
+            session_id: session.id,
+            user: current_user(),
+            max_commands_per_hour: config.security.max_commands_per_hour,
+            dry_run: args.dry_run,
+        }
+    });
+
+    if args.debug {
+        if let Some(audit) = &audit {
+            println!(
+                "{} {}",
+                theme.info("🔍 Debug:"),
+                theme.info(&format!(
+                    "Audit log: {} (session {})",
+                    audit.logger.path().display(),
+                    audit.session_id
+                ))
+            );
         }
+    }
+
+    // A cache that can't be read is ignored rather than failing the request
+    let explanations = args.enrich_explanations.then(|| {
+        let path = config.get_explanation_cache_path();
+        ExplanationCache::load(&path).unwrap_or_else(|_| ExplanationCache::empty(path))
+    });
+
+    let mut app = App {
+        args,
+        theme,
+        executor,
+        history,
+        plugin_manager,
+        plugin_dir,
+        providers,
+        router,
+        use_chain,
+        offline,
+        last_failure: None,
+        last_error_path: config.get_last_error_path(),
+        remediation: None,
+        audit,
+        attachments,
+        prefer_no_sudo: config.security.prefer_no_sudo,
+        target_os: config.llm.target_os,
+        shell,
+        persona: config.llm.persona.clone(),
+        retry_message: config
+            .llm
+            .retry_message
+            .clone()
+            .unwrap_or_else(|| DEFAULT_RETRY_MESSAGE.to_string()),
+        prompt_templates: config.llm.prompt_templates.clone(),
+        explanations,
+        preprocessors,
+        tools: ToolAvailability::new(),
+        timings: Timings::default(),
     };
 
+    if app.args.interactive {
+        let result = run_interactive(&mut app).await;
+        warnings.summarize();
+        return result;
+    }
+
+    if app.args.repeat_last {
+        return app.repeat_last().await;
+    }
+
+    if app.args.last_error {
+        return app.last_error().await;
+    }
+
+    if let Some(name) = app.args.run_favorite.clone() {
+        let favorite = match Favorites::load(config.get_favorites_path()) {
+            Ok(favorites) => favorites.get_favorite(&name).cloned(),
+            Err(e) => {
+                eprintln!("{} {}", app.theme.error("❌ Error:"), app.theme.error(&e.to_string()));
+                return Err(ExitReason::Config.error(e.to_string()));
+            }
+        };
+        let Some(favorite) = favorite else {
+            let message = format!("No favorite named '{}' (see --favorites)", name);
+            eprintln!("{} {}", app.theme.error("❌ Error:"), app.theme.error(&message));
+            return Err(ExitReason::Config.error(message));
+        };
+
+        println!("\n{} {}", app.theme.info("⭐ Running favorite:"), name);
+        let explanation = favorite.explanation.unwrap_or_default();
+        return app
+            .act_on_command(
+                format!("favorite {}", name),
+                favorite.command,
+                explanation,
+                &[],
+                "favorite",
+            )
+            .await;
+    }
+
     // Get user input
-    let user_input = match args.input {
+    let user_input = match app.args.input.clone() {
         Some(input) => input,
         None => {
-            print!("{} ", "Enter your request:".bright_cyan());
+            print!("{} ", app.theme.info("Enter your request:"));
             io::stdout().flush()?;
             let mut input = String::new();
             io::stdin().read_line(&mut input)?;
@@ -105,290 +613,1420 @@ async fn main() -> Result<(), io::Error> {
     };
 
     if user_input.is_empty() {
-        println!("{}", "No input provided. Exiting.".red());
+        println!("{}", app.theme.error("No input provided. Exiting."));
         return Ok(());
     }
 
-    println!("\n{} {}", "💬 Processing:".bright_blue(), user_input);
+    app.handle_remediating(user_input).await
+}
+
+// Everything needed to handle a request, shared across the requests of an interactive session
+struct App {
+    args: CliArgs,
+    theme: Theme,
+    executor: ShellExecutor,
+    history: CommandHistory,
+    plugin_manager: PluginManager,
+    plugin_dir: Option<PathBuf>,
+    providers: Vec<Arc<LLMProvider>>,
+    /// Picks the provider for each request with `llm.routing`, instead of `providers`
+    router: Option<Router>,
+    use_chain: bool,
+    offline: bool,
+    /// The last command of an interactive session, if it failed when executed
+    last_failure: Option<FailedAttempt>,
+    /// Where the last failed command is kept for --last-error
+    last_error_path: PathBuf,
+    /// The command of the request just handled, if it ran and failed, for --auto-remediate
+    remediation: Option<LastError>,
+    audit: Option<Audit>,
+    /// Files attached with --file, included in every LLM prompt
+    attachments: Vec<AttachedFile>,
+    /// Ask the model to avoid sudo (`security.prefer_no_sudo`)
+    prefer_no_sudo: bool,
+    /// Generate commands for this OS instead of the host (--target-os, `llm.target_os`)
+    target_os: Option<TargetOs>,
+    /// The shell commands are written for (--shell, `llm.shell` or detected)
+    shell: Shell,
+    /// House style placed before the prompt (`llm.persona`)
+    persona: Option<String>,
+    /// Shown as the fallback chain moves on to the next backend (`llm.retry_message`)
+    retry_message: String,
+    /// Prompts for particular models, keyed by model name prefix (`llm.prompt_templates`)
+    prompt_templates: HashMap<String, String>,
+    /// Cached LLM explanations of plugin commands, with --enrich-explanations
+    explanations: Option<ExplanationCache>,
+    /// Applied to each request before it's matched against plugins and sent to the LLM
+    preprocessors: Vec<Box<dyn InputPreprocessor>>,
+    /// Which tools are installed, looked up once per run for plugins
+    tools: ToolAvailability,
+    /// How long the phases of the current request took, for --timings
+    timings: Timings,
+}
+
+// Writes audit entries for the current session
+struct Audit {
+    logger: AuditLogger,
+    session_id: String,
+    user: String,
+    /// `security.max_commands_per_hour`
+    max_commands_per_hour: Option<u32>,
+    /// Entries of a dry run are marked as such and never count as executed
+    dry_run: bool,
+}
+
+impl Audit {
+    // Record a command and what happened to it as `(executed, outcome)`. The notes add the
+    // safety decision to the outcome, which may quote the command's error output, so secrets
+    // are redacted from it. A failure to write is reported but doesn't stop the command.
+    fn record(
+        &self,
+        checker: &CommandSafetyChecker,
+        input: &str,
+        command: &str,
+        backend: &str,
+        (executed, outcome): (bool, Option<String>),
+        theme: &Theme,
+    ) {
+        let safety = checker.check_command_detailed(command);
+        let outcome = outcome.map(|outcome| redact_secrets(&outcome));
+        let (executed, outcome) = match self.dry_run {
+            true => dry_run_outcome(outcome.as_deref()),
+            false => (executed, outcome),
+        };
+        let notes = audit_notes(&safety, executed, outcome.as_deref());
+        let entry = AuditEntry {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            session_id: self.session_id.clone(),
+            user: self.user.clone(),
+            input: input.to_string(),
+            command: command.to_string(),
+            backend: backend.to_string(),
+            safety_level: safety.level,
+            reason_code: safety.reason_code,
+            executed,
+            notes,
+        };
+
+        if let Err(e) = self.logger.log(&entry) {
+            eprintln!("{} {}", theme.warning("⚠️ Warning:"), theme.warning(&e.to_string()));
+        }
+    }
+
+    // Why the user may not run the command they chose, once they've reached the hourly limit.
+    // Dry runs don't count. An unreadable audit log refuses too, since nothing can be counted.
+    fn rate_limited(&self, action: UserAction, dry_run: bool) -> Option<String> {
+        let limit = self.max_commands_per_hour?;
+        if action != UserAction::Run || dry_run {
+            return None;
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        self.logger.check_rate_limit(&self.user, limit, now).err().map(|e| e.to_string())
+    }
+}
+
+impl App {
+    // Handle a request, then show how long each phase took with --timings or --debug
+    async fn handle_request(&mut self, user_input: String) -> io::Result<()> {
+        self.timings = Timings::default();
+        let result = self.process_request(user_input).await;
+        if self.args.timings || self.args.debug {
+            self.timings.print(&self.theme);
+        }
+        result
+    }
+
+    // Turn a request into a command via a plugin or the LLM, then let the user act on it
+    async fn process_request(&mut self, user_input: String) -> io::Result<()> {
+        let args = &self.args;
+        let theme = &self.theme;
+        let executor = &self.executor;
+        let history = &mut self.history;
+        let plugin_manager = &self.plugin_manager;
+        let (use_chain, offline) = (self.use_chain, self.offline);
+        let previous_failure = self.last_failure.take();
+        let audit = self.audit.as_ref();
+
+        println!("\n{} {}", theme.info("💬 Processing:"), user_input);
+
+        // Plugins and the LLM get the preprocessed request, history and audit the original
+        let input = preprocess_input(&self.preprocessors, &user_input);
+        if args.debug && input != user_input {
+            println!("{} {}", theme.info("🔍 Debug - Preprocessed input:"), theme.info(&input));
+        }
+
+        // With llm.routing, the length of the request picks the provider
+        let routed;
+        let providers = match &self.router {
+            Some(router) => {
+                routed = vec![select_provider(&input, router)];
+                if args.debug {
+                    println!("{} {}", theme.info("🔍 Debug - Routed to:"), routed[0].name());
+                }
+                &routed
+            }
+            None => &self.providers,
+        };
+
+        let plugin_context = PluginContext {
+            machine_readable: args.machine_readable,
+            // Plugins never run anything themselves in read-only mode
+            dry_run: args.dry_run || executor.is_read_only(),
+            tools: self.tools.clone(),
+            os: None,
+            target_os: self.target_os,
+            shell: Some(self.shell),
+        };
+        let mut plugin_used = String::new();
+
+        // Try to process with plugins, which may produce several commands to run in order
+        let started = Instant::now();
+        let plugin_steps = if let Some(plugin_name) = &args.plugin {
+            // If a specific plugin is requested, use only that plugin
+            let plugin_name = plugin_name.to_lowercase();
+
+            // Find the requested plugin
+            if let Some(plugin) = plugin_manager.get_plugin(&plugin_name) {
+                // Process with the specified plugin
+                if plugin.can_handle(&input) {
+                    match plugin_manager.run_plugin(plugin, &input, &plugin_context) {
+                        Ok(steps) if !steps.is_empty() => {
+                            println!("{} {}", theme.success("🔌 Using plugin:"), plugin_name);
+                            plugin_used = plugin_name.clone();
+
+                            if args.debug {
+                                println!(
+                                    "{} {}",
+                                    theme.info("🔍 Debug - Plugin:"),
+                                    theme.info(&format!("Plugin '{}' matched input", plugin_name))
+                                );
+                            }
+
+                            steps
+                        }
+                        Ok(_) => {
+                            println!(
+                                "{} {} {}",
+                                theme.warning("⚠️ Warning:"),
+                                theme.warning(&format!(
+                                    "Plugin '{}' couldn't process the request",
+                                    plugin_name
+                                )),
+                                theme.warning("Falling back to LLM.")
+                            );
+                            Vec::new()
+                        }
+                        Err(reason) => {
+                            println!(
+                                "{} {} {}",
+                                theme.warning("⚠️ Warning:"),
+                                theme.warning(&format!(
+                                    "Plugin '{}' produced an incomplete command: {}.",
+                                    plugin_name, reason
+                                )),
+                                theme.warning("Falling back to LLM.")
+                            );
+                            Vec::new()
+                        }
+                    }
+                } else {
+                    println!(
+                        "{} {} {}",
+                        theme.warning("⚠️ Warning:"),
+                        theme.warning(&format!(
+                            "Plugin '{}' can't handle this request",
+                            plugin_name
+                        )),
+                        theme.warning("Falling back to LLM.")
+                    );
+                    Vec::new()
+                }
+            } else {
+                println!(
+                    "{} {}",
+                    theme.warning("⚠️ Warning:"),
+                    theme.warning(&format!("Plugin '{}' not found", plugin_name))
+                );
+                Vec::new()
+            }
+        } else {
+            // Try all plugins, letting the user choose when several can handle the request
+            let mut matches = Vec::new();
+
+            for (name, outcome) in plugin_manager.process_all(&input, &plugin_context) {
+                match outcome {
+                    Ok(steps) => matches.push((name, steps)),
+                    Err(reason) if args.debug => {
+                        println!(
+                            "{} {}",
+                            theme.info("🔍 Debug - Plugin:"),
+                            theme.info(&format!("Plugin '{}' skipped: {}", name, reason))
+                        );
+                    }
+                    Err(_) => {}
+                }
+            }
+
+            let chosen = if matches.len() > 1 { choose_plugin(&matches, theme).await? } else { 0 };
+            if matches.is_empty() {
+                Vec::new()
+            } else {
+                let (name, steps) = matches.swap_remove(chosen);
+                println!("{} {}", theme.success("🔌 Using plugin:"), name);
+                plugin_used = name.clone();
+
+                if args.debug {
+                    println!(
+                        "{} {}",
+                        theme.info("🔍 Debug - Plugin:"),
+                        theme.info(&format!("Plugin '{}' selected", name))
+                    );
+                }
+                steps
+            }
+        };
+        self.timings.record("Plugin matching", started);
+
+        // Process with plugin if we have a result
+        if let Some(mut plugin_result) = CommandResult::combine(plugin_steps.clone()) {
+            // With --enrich-explanations, have the LLM explain the plugin's command, keeping
+            // the plugin's own explanation when offline or if the LLM fails
+            let online_blocked = offline && providers.iter().any(|p| p.is_online());
+            if let Some(cache) = self.explanations.as_mut().filter(|_| !online_blocked) {
+                match enrich_explanation(providers, use_chain, cache, &plugin_result.command).await
+                {
+                    Ok(explanation) => plugin_result.explanation = explanation,
+                    Err(e) if args.debug => println!(
+                        "{} {}",
+                        theme.info("🔍 Debug - Plugin:"),
+                        theme.info(&format!("Could not enrich the explanation: {}", e))
+                    ),
+                    Err(_) => {}
+                }
+            }
+
+            if !args.compact {
+                println!("\n{}", theme.success("🤖 I'll help you with that!"));
+            }
+            if plugin_steps.len() > 1 {
+                println!("{}:", theme.success("Steps"));
+                for (i, step) in plugin_steps.iter().enumerate() {
+                    println!("  {}. {}", i + 1, theme.command(&step.command));
+                }
+            }
+            let compact = args
+                .compact
+                .then(|| executor.compact_line(&plugin_result.command, &plugin_result.explanation))
+                .flatten();
+            // In compact mode the action prompt shows the line, unless the plugin already ran
+            // the command and there is no prompt
+            let already_run = plugin_result.executed && plugin_result.output.is_some();
+            match compact {
+                Some(line) if already_run => println!("{}", line),
+                Some(_) => {}
+                None => {
+                    println!(
+                        "{}: {}",
+                        theme.success("Command"),
+                        theme.command(&plugin_result.command)
+                    );
+                    println!(
+                        "{}: {}",
+                        theme.success("Explanation"),
+                        theme.explanation(&plugin_result.explanation)
+                    );
+                }
+            }
+            if offline && plugin_result.requires_network {
+                println!(
+                    "{} {}",
+                    theme.warning("⚠️ Warning:"),
+                    theme.warning("This command needs network access, but offline mode is on.")
+                );
+            }
+
+            // If the plugin has already executed the command, just display the output
+            if plugin_result.executed {
+                if let Some(output) = plugin_result.output {
+                    println!("\n{}", theme.success("🚀 Command executed by plugin:"));
+                    println!("{}", output);
+                    if let Some(audit) = audit {
+                        audit.record(
+                            executor.safety_checker(),
+                            &user_input,
+                            &plugin_result.command,
+                            &plugin_used,
+                            (true, Some("Executed by plugin".to_string())),
+                            theme,
+                        );
+                    }
+
+                    // Add command to history
+                    let recorded = history.add_entry(
+                        user_input,
+                        plugin_result.command,
+                        Some(plugin_result.explanation.clone()),
+                    );
+                    if recorded {
+                        history.update_last_entry_output(&output);
+                    }
+                    return Ok(());
+                }
+            }
+
+            // Otherwise, prompt user for action
+            return self
+                .act_on_command(
+                    user_input,
+                    plugin_result.command,
+                    plugin_result.explanation,
+                    &plugin_steps,
+                    &plugin_used,
+                )
+                .await;
+        }
+
+        // If no plugin can handle it, use the LLM
+        let backend_names = providers.iter().map(|p| p.name()).collect::<Vec<&str>>().join(" → ");
+        println!("{} {}", theme.info("🧠 Using LLM backend:"), backend_names);
+
+        // Skip LLM if in offline mode and the LLM is online-only
+        if offline && providers.iter().any(|p| p.is_online()) {
+            let message = "Cannot use online LLM in offline mode";
+            println!("{}", theme.error(&format!("❌ {}. Exiting.", message)));
+            return Err(ExitReason::Config.error(message));
+        }
+
+        // Generate the shell command using the LLM, reusing corrections to similar past requests
+        // and, in interactive mode, telling it about the previous command if that failed
+        let started = Instant::now();
+        let mut prompt_context = prompt_context(
+            history,
+            &user_input,
+            previous_failure,
+            &self.attachments,
+            self.prefer_no_sudo,
+            self.target_os,
+            self.persona.as_deref(),
+        );
+        prompt_context.shell = Some(self.shell);
+        prompt_context.template = model_template(&self.prompt_templates, providers);
+        let prompt = construct_prompt_with_context(&input, &prompt_context);
+        self.timings.record("Prompt construction", started);
+
+        if args.debug {
+            println!("{} {}", theme.info("🔍 Debug - Prompt:"), theme.info(&prompt));
+        }
+
+        let parse_options =
+            ParseOptions { strip_comments: !args.keep_comments, ..Default::default() };
+        let retry_message = &self.retry_message;
+        let generation = async {
+            if use_chain {
+                // Show each fallback as it starts: on a status line in interactive mode, and
+                // through the log otherwise
+                let mut on_attempt = |attempt: &ChainAttempt| {
+                    if !attempt.is_retry() {
+                        return;
+                    }
+                    let message = attempt.describe(retry_message);
+                    if args.interactive {
+                        show_status(theme, &message);
+                    } else {
+                        tracing::info!("{}", message);
+                    }
+                };
+                let generated =
+                    generate_with_chain_progress(providers, &prompt, &mut on_attempt).await;
+                if args.interactive {
+                    clear_status();
+                }
+                generated
+            } else if args.stream {
+                println!("{}", theme.info("📡 Response:"));
+                let mut on_fragment = |fragment: &str| {
+                    print!("{}", theme.info(fragment));
+                    let _ = io::stdout().flush();
+                };
+                let generated =
+                    providers[0].generate_stream_with_fallback(&prompt, &mut on_fragment).await;
+                println!();
+                generated
+            } else {
+                providers[0].generate_with_fallback(&prompt).await
+            }
+        };
+
+        // In interactive mode Ctrl-C cancels a slow generation and returns to the prompt.
+        // Dropping the generation also drops its HTTP request.
+        let started = Instant::now();
+        let response = if args.interactive {
+            tokio::select! {
+                generated = generation => generated,
+                _ = tokio::signal::ctrl_c() => {
+                    println!("\n{}", theme.warning("⏹️ Generation cancelled."));
+                    return Ok(());
+                }
+            }
+        } else {
+            generation.await
+        };
+        self.timings.record("LLM generation", started);
+
+        let started = Instant::now();
+        let generated = response.and_then(|response| {
+            parse_response_with_options(&response, &parse_options).map_err(LLMError::ParsingError)
+        });
+        self.timings.record("Parsing", started);
+
+        if args.debug {
+            for provider in providers {
+                if let Some(usage) = match provider.as_ref() {
+                    LLMProvider::Ollama(ollama) => ollama.last_usage(),
+                    _ => None,
+                } {
+                    println!(
+                        "{} {}",
+                        theme.info("🔍 Debug - Usage:"),
+                        theme.info(&format!(
+                            "{:?} total, {} prompt tokens, {} response tokens",
+                            usage.total_duration.unwrap_or_default(),
+                            usage.prompt_eval_count.unwrap_or_default(),
+                            usage.eval_count.unwrap_or_default()
+                        ))
+                    );
+                }
+            }
+        }
+
+        let (command, explanation) = match generated {
+            Ok((cmd, exp)) => (cmd, exp),
+            Err(e) => {
+                eprintln!(
+                    "{} {}",
+                    theme.error("❌ Error generating command:"),
+                    theme.error(&e.to_string())
+                );
+                if matches!(e, LLMError::Timeout(_)) {
+                    eprintln!(
+                        "{}",
+                        theme.warning(
+                            "💡 The backend may still be loading the model. Wait longer with \
+                             --timeout <SECS> or llm.timeout_secs."
+                        )
+                    );
+                }
+                return Err(ExitReason::Generation.error(e.to_string()));
+            }
+        };
+
+        // Display command and explanation
+        if !args.compact {
+            println!("\n{}", theme.success("🤖 I'll help you with that!"));
+        }
+
+        self.act_on_command(user_input, command, explanation, &[], &backend_names).await
+    }
+
+    // Let the user run, copy or abort a command, then record it in history and the audit log.
+    // A plugin's `steps` are run one at a time in place of `command`, which combines them.
+    async fn act_on_command(
+        &mut self,
+        user_input: String,
+        command: String,
+        explanation: String,
+        steps: &[CommandResult],
+        backend: &str,
+    ) -> io::Result<()> {
+        let args = &self.args;
+        let theme = &self.theme;
+        let executor = &self.executor;
+        let history = &mut self.history;
+        let audit = self.audit.as_ref();
+
+        // Prompt user for action
+        time_safety_check(&mut self.timings, executor, &command);
+        let started = Instant::now();
+        let action = executor.prompt_for_action(&command, &explanation, args.force).await?;
+        self.timings.record("Confirmation", started);
+
+        // Refuse to run once the user has reached `security.max_commands_per_hour`
+        if let Some(audit) = audit {
+            if let Some(reason) = audit.rate_limited(action, args.dry_run) {
+                eprintln!("\n{} {}", theme.error("❌ Refused:"), theme.error(&reason));
+                audit.record(
+                    executor.safety_checker(),
+                    &user_input,
+                    &command,
+                    backend,
+                    (false, Some(format!("Refused: {}", reason))),
+                    theme,
+                );
+                return Err(ExitReason::Blocked.error(reason));
+            }
+        }
+
+        let mut result = Ok(());
+        let outcome = match action {
+            UserAction::Run => {
+                let started = Instant::now();
+                let executed = if steps.len() > 1 {
+                    execute_steps(executor, steps, args.dry_run, args.force).await
+                } else {
+                    executor.execute_command(&command, args.dry_run).await
+                };
+                self.timings.record("Execution", started);
+                match executed {
+                    Ok(output) => {
+                        println!("\n{}", theme.success("✅ Command executed successfully:"));
+                        println!("{}", output);
+
+                        // Add command to history
+                        let recorded = history.add_entry(
+                            user_input.clone(),
+                            command.clone(),
+                            Some(explanation.clone()),
+                        );
+                        if recorded && !args.dry_run {
+                            history.update_last_entry_output(&output.stdout);
+                            history.update_last_entry_exit_code(output.exit_code);
+                        }
+
+                        // Prompt for feedback if not disabled
+                        if recorded && !args.no_feedback {
+                            handle_feedback(history, executor, &command).await?;
+                        }
+
+                        (!args.dry_run, None)
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "\n{} {}",
+                            theme.error("❌ Error executing command:"),
+                            theme.error(&e.to_string())
+                        );
+                        self.remediation = save_last_error(
+                            &self.last_error_path,
+                            history,
+                            &user_input,
+                            &command,
+                            &e,
+                        );
+                        record_failure(history, &user_input, &command, &explanation, &e);
+                        if args.interactive {
+                            self.last_failure = Some(FailedAttempt {
+                                command: command.clone(),
+                                error: e.to_string(),
+                            });
+                        }
+                        result = Err(execution_error(&e));
+
+                        (true, Some(format!("Failed: {}", e)))
+                    }
+                }
+            }
+            UserAction::Copy => match copy_to_clipboard(&command) {
+                Ok(_) => {
+                    println!("\n{}", theme.success("📋 Command copied to clipboard!"));
+
+                    // Add to history when copied too
+                    let recorded = history.add_entry(
+                        user_input.clone(),
+                        command.clone(),
+                        Some(explanation.clone()),
+                    );
+
+                    // Prompt for feedback if not disabled
+                    if recorded && !args.no_feedback {
+                        handle_feedback(history, executor, &command).await?;
+                    }
+
+                    (false, Some("Copied to clipboard".to_string()))
+                }
+                Err(e) => {
+                    eprintln!(
+                        "{} {}",
+                        theme.error("❌ Error copying to clipboard:"),
+                        theme.error(&e.to_string())
+                    );
+                    (false, Some(format!("Copy failed: {}", e)))
+                }
+            },
+            UserAction::Abort => {
+                println!("\n{}", theme.warning("🛑 Command execution aborted."));
+                result = aborted(executor, &command);
+                (false, Some("Aborted".to_string()))
+            }
+        };
+
+        if let Some(audit) = audit {
+            audit.record(executor.safety_checker(), &user_input, &command, backend, outcome, theme);
+        }
+
+        result
+    }
+
+    // Run the newest history entry's command again, skipping generation
+    async fn repeat_last(&mut self) -> io::Result<()> {
+        let Some(entry) = self.history.entries().back().cloned() else {
+            println!("{}", self.theme.warning("No command history found. Nothing to repeat."));
+            return Ok(());
+        };
+
+        println!("\n{} {}", self.theme.info("🔁 Repeating:"), entry.input);
+        let explanation = entry.explanation.unwrap_or_default();
+        self.act_on_command(entry.input, entry.command, explanation, &[], "history").await
+    }
+
+    // Show the last command that failed, then offer to ask the LLM how to fix it with the
+    // failure as context
+    async fn last_error(&mut self) -> io::Result<()> {
+        let theme = &self.theme;
+        let Some(last) = LastError::load(&self.last_error_path) else {
+            println!("{}", theme.success("✅ No failed command recorded."));
+            return Ok(());
+        };
+
+        println!("{}: {}", theme.error("❌ Failed command"), theme.command(&last.command));
+        let exit_code = last.exit_code.map_or("none".to_string(), |code| code.to_string());
+        println!("{}: {}", theme.info("Exit code"), exit_code);
+        let when = chrono::DateTime::<chrono::Local>::from(
+            UNIX_EPOCH + std::time::Duration::from_secs(last.timestamp),
+        );
+        println!("{}: {}", theme.info("When"), when.format("%Y-%m-%d %H:%M:%S"));
+        println!("{}:\n{}", theme.info("Error output"), last.stderr.trim_end());
+
+        if !io::stdin().is_terminal() {
+            return Ok(());
+        }
+        let ask = run_blocking(|| {
+            Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Ask the LLM how to fix this error?")
+                .default(true)
+                .interact()
+                .unwrap_or(false)
+        })
+        .await?;
+        if !ask {
+            return Ok(());
+        }
+
+        self.last_failure = Some(failed_attempt(&last));
+        self.handle_request(format!("How do I fix the error from `{}`?", last.command)).await
+    }
+
+    // Handle a request and, with --auto-remediate, offer to ask the LLM for a fix whenever
+    // the command runs and fails, with the failure as context. The fix is confirmed and run
+    // like any other command, so this repeats until a command succeeds, the user declines,
+    // or MAX_REMEDIATIONS fixes have been tried.
+    async fn handle_remediating(&mut self, user_input: String) -> io::Result<()> {
+        self.remediation = None;
+        let mut result = self.handle_request(user_input).await;
+
+        for _ in 0..MAX_REMEDIATIONS {
+            let Some(failure) = self.remediation.take() else {
+                break;
+            };
+            if !self.args.auto_remediate || !self.offer_remediation(&failure).await? {
+                break;
+            }
+            self.last_failure = Some(failed_attempt(&failure));
+            let request = format!("How do I fix the error from `{}`?", failure.command);
+            result = self.handle_request(request).await;
+        }
+
+        self.remediation = None;
+        result
+    }
+
+    // Ask whether to generate a fix for a failed command. Without a terminal to ask at,
+    // --auto-remediate is taken as the answer.
+    async fn offer_remediation(&self, failure: &LastError) -> io::Result<bool> {
+        let exit_code = failure.exit_code.map_or("none".to_string(), |code| code.to_string());
+        println!(
+            "\n{} {} ({}: {})",
+            self.theme.warning("🩹 Command failed:"),
+            self.theme.command(&failure.command),
+            self.theme.info("exit code"),
+            exit_code
+        );
+
+        if !io::stdin().is_terminal() {
+            return Ok(true);
+        }
+        run_blocking(|| {
+            Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt("Ask the LLM for a command to fix it?")
+                .default(true)
+                .interact()
+                .unwrap_or(false)
+        })
+        .await
+    }
+}
+
+// Time the safety check of a command on its own. The executor checks it again before asking
+// what to do, but that time is mixed with waiting for the user.
+fn time_safety_check(timings: &mut Timings, executor: &ShellExecutor, command: &str) {
+    let started = Instant::now();
+    executor.safety_checker().check_command_detailed(command);
+    timings.record("Safety check", started);
+}
+
+// The exit reason of a command that didn't run: refused when executed (as blocked commands
+// are) or failed when run
+fn execution_error(error: &io::Error) -> io::Error {
+    let reason = match error.kind() {
+        io::ErrorKind::PermissionDenied => ExitReason::Blocked,
+        _ => ExitReason::Execution,
+    };
+    reason.error(error.to_string())
+}
+
+// Aborting only counts as a failure when the safety checker blocked the command
+fn aborted(executor: &ShellExecutor, command: &str) -> io::Result<()> {
+    match executor.safety_checker().check_command_detailed(command).level {
+        SafetyLevel::Blocked => Err(ExitReason::Blocked.error("Command blocked by policy")),
+        _ => Ok(()),
+    }
+}
+
+// Show a one-line status on stderr, replacing the previous one on a terminal
+fn show_status(theme: &Theme, message: &str) {
+    let status = format!("⏳ {}", message);
+    if io::stderr().is_terminal() {
+        let term = Term::stderr();
+        let _ = term.clear_line();
+        let _ = term.write_str(&theme.info(&status).to_string());
+    } else {
+        eprintln!("{}", theme.info(&status));
+    }
+}
+
+// Remove the status line once the work it describes is done
+fn clear_status() {
+    if io::stderr().is_terminal() {
+        let _ = Term::stderr().clear_line();
+    }
+}
+
+// A failure as prompt context, so the model can suggest a fix
+fn failed_attempt(last: &LastError) -> FailedAttempt {
+    FailedAttempt { command: last.command.clone(), error: last.stderr.clone() }
+}
+
+// Keep a failed command for --last-error, unless it's excluded from history. Failing to save
+// it only loses that record. The failure is returned when the command ran, rather than being
+// refused before it started.
+fn save_last_error(
+    path: &Path,
+    history: &CommandHistory,
+    input: &str,
+    command: &str,
+    error: &io::Error,
+) -> Option<LastError> {
+    if !history.should_record(input) || !history.should_record(command) {
+        return None;
+    }
+    let failed = CommandFailed::from_io_error(error);
+    let stderr = failed.map_or_else(|| error.to_string(), |failed| failed.stderr.clone());
+    let last = LastError::new(command, &stderr, failed.and_then(|f| f.exit_code));
+    let _ = last.save(path);
+    failed.map(|_| last)
+}
+
+// Commands that ran and failed go into history too, with their error output and exit code
+fn record_failure(
+    history: &mut CommandHistory,
+    input: &str,
+    command: &str,
+    explanation: &str,
+    error: &io::Error,
+) {
+    let Some(failed) = CommandFailed::from_io_error(error) else {
+        return;
+    };
+    if history.add_entry(input.to_string(), command.to_string(), Some(explanation.to_string())) {
+        history.update_last_entry_output(&failed.stderr);
+        history.update_last_entry_exit_code(failed.exit_code);
+    }
+}
+
+// Gather what the LLM should know besides the request: corrections the user made to
+// similar past requests, the previous failed attempt, attached files and preferences
+fn prompt_context(
+    history: &CommandHistory,
+    input: &str,
+    previous_failure: Option<FailedAttempt>,
+    attachments: &[AttachedFile],
+    prefer_no_sudo: bool,
+    target_os: Option<TargetOs>,
+    persona: Option<&str>,
+) -> PromptContext {
+    let corrections = history
+        .corrections_for(input, 3)
+        .into_iter()
+        .map(|entry| Correction { input: entry.input, command: entry.command })
+        .collect();
+
+    // The host's OS version only matters when generating for the host
+    let os = target_os.is_none_or(|target| target == TargetOs::host()).then(detect_os_version);
+
+    PromptContext {
+        corrections,
+        previous_failure,
+        files: attachments.to_vec(),
+        prefer_no_sudo,
+        target_os,
+        shell: None,
+        persona: persona.map(str::to_string),
+        os,
+        template: None,
+    }
+}
+
+// The configured template for the first backend's model. The fallback chain sends the same
+// prompt to every backend, so it's written for the one tried first.
+fn model_template(
+    templates: &HashMap<String, String>,
+    providers: &[Arc<LLMProvider>],
+) -> Option<String> {
+    let model = providers.first()?.model_name()?;
+    template_for_model(templates, model).map(str::to_string)
+}
+
+// Run plugin steps in order. At a terminal, the user chooses whether to go on after each
+// step; otherwise (or with --force) the steps stop at the first one that fails.
+async fn execute_steps(
+    executor: &ShellExecutor,
+    steps: &[CommandResult],
+    dry_run: bool,
+    force: bool,
+) -> io::Result<CommandOutput> {
+    let commands: Vec<String> = steps.iter().map(|step| step.command.clone()).collect();
+    let ask = !force && io::stdin().is_terminal();
+    executor
+        .execute_sequence(&commands, dry_run, move |step| async move {
+            if ask {
+                executor.prompt_for_next_step(step).await
+            } else {
+                Ok(step.default_action())
+            }
+        })
+        .await
+}
+
+// Read requests until EOF or `exit`, reloading declarative plugins when they change
+async fn run_interactive(app: &mut App) -> io::Result<()> {
+    let watcher = match (&app.plugin_dir, app.args.watch_plugins) {
+        (Some(dir), true) => match fs::create_dir_all(dir)
+            .and_then(|_| PluginWatcher::new(dir).map_err(|e| io::Error::other(e.to_string())))
+        {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                eprintln!(
+                    "{} {}",
+                    app.theme.warning("⚠️ Warning:"),
+                    app.theme.warning(&format!("Could not watch plugins: {}", e))
+                );
+                None
+            }
+        },
+        _ => None,
+    };
+
+    println!(
+        "{}",
+        app.theme.info(
+            "💬 Interactive mode - enter a request (type 'exit' or press Ctrl-D or Ctrl-C to quit)"
+        )
+    );
+
+    loop {
+        if let (Some(watcher), Some(dir)) = (&watcher, &app.plugin_dir) {
+            if watcher.has_changes() {
+                load_plugins(&mut app.plugin_manager, dir, &app.theme);
+                println!(
+                    "{} {}",
+                    app.theme.info("🔄 Reloaded plugins:"),
+                    app.plugin_manager.declarative_count()
+                );
+            }
+        }
+
+        print!("\n{} ", app.theme.info("Enter your request:"));
+        io::stdout().flush()?;
+        let read = run_blocking(|| {
+            let mut input = String::new();
+            io::stdin().read_line(&mut input).map(|read| (read > 0).then_some(input))
+        });
+
+        // Once Ctrl-C has stopped a command or cancelled a generation, its handler stays
+        // installed and the default of ending the process is gone, so the prompt quits on
+        // Ctrl-C itself. It exits right away: the read can't be stopped, and the runtime
+        // would wait for it on the way out.
+        let input = tokio::select! {
+            read = read => read??,
+            _ = tokio::signal::ctrl_c() => {
+                println!();
+                std::process::exit(ExitReason::Interrupted.code());
+            }
+        };
+        let Some(input) = input else {
+            println!();
+            break;
+        };
+
+        let input = input.trim();
+        if input.is_empty() {
+            continue;
+        }
+        if input == "exit" || input == "quit" {
+            break;
+        }
+
+        // Errors have already been reported, so keep the session going
+        let _ = app.handle_remediating(input.to_string()).await;
+    }
+
+    Ok(())
+}
+
+// Ask which plugin's command to use when several plugins handle the request. Without a
+// terminal to ask on, the first one (in registration order) is used.
+async fn choose_plugin(
+    matches: &[(String, Vec<CommandResult>)],
+    theme: &Theme,
+) -> io::Result<usize> {
+    if !io::stdin().is_terminal() {
+        return Ok(0);
+    }
+
+    println!("\n{}", theme.info("🔌 Several plugins can handle this request:"));
+    let items: Vec<String> = matches
+        .iter()
+        .map(|(name, steps)| {
+            let command = CommandResult::combine(steps.clone()).map(|r| r.command);
+            format!("{}: {}", name, command.unwrap_or_default())
+        })
+        .collect();
+    run_blocking(move || {
+        Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Choose a plugin")
+            .default(0)
+            .items(&items)
+            .interact()
+            .unwrap_or(0)
+    })
+    .await
+}
+
+// Load the declarative plugins in `dir`, reporting files that failed to load
+fn load_plugins(plugin_manager: &mut PluginManager, dir: &Path, theme: &Theme) {
+    for e in plugin_manager.reload_from_dir(dir) {
+        eprintln!("{} {}", theme.warning("⚠️ Skipping plugin:"), theme.warning(&e.to_string()));
+    }
+}
+
+// Time one request on each available backend and report whether its response parses.
+// The generated commands are never executed.
+async fn run_bench(
+    args: &CliArgs,
+    llm: &LLMConfig,
+    request: &str,
+    offline: bool,
+    theme: &Theme,
+) -> io::Result<()> {
+    let model_path = args.model_path.as_deref().unwrap_or(DEFAULT_MODEL_PATH);
+    let mut backends = vec![BackendKind::Ollama];
+    if Path::new(model_path).exists() {
+        backends.push(BackendKind::LlmRs);
+    } else {
+        println!("{} {}", theme.warning("⚠️ Skipping LLM-rs, model not found:"), model_path);
+    }
+    backends.push(BackendKind::OpenAI);
+
+    let providers = create_provider_chain(args, llm, &backends, offline, theme);
+    let prompt = construct_prompt(request, llm.shell.unwrap_or_else(Shell::detect));
 
-    // Try to process with plugins
-    let plugin_result = if let Some(plugin_name) = &args.plugin {
-        // If a specific plugin is requested, use only that plugin
-        let plugin_name = plugin_name.to_lowercase();
+    println!("\n{}", theme.info("⏱️ Benchmark:"));
+    let mut table = Table::new(&["Backend", "Latency", "Result"]).align_right(1);
+    for provider in &providers {
+        let start = Instant::now();
+        let response = provider.generate(&prompt).await;
+        let elapsed = start.elapsed();
 
-        // Find the requested plugin
-        if let Some(plugin) = plugin_manager.get_plugin(&plugin_name) {
-            // Process with the specified plugin
-            if plugin.can_handle(&user_input) {
-                if let Some(result) = plugin.handle(&user_input) {
-                    println!("{} {}", "🔌 Using plugin:".bright_green(), plugin_name);
+        let result = match response.map_err(|e| e.to_string()).and_then(|r| parse_response(&r)) {
+            Ok((command, _)) => theme.success(&format!("ok: {}", command)),
+            Err(e) => theme.error(&format!("failed: {}", e)),
+        };
+        table.add_row(vec![
+            provider.name().to_string(),
+            format!("{:.2?}", elapsed),
+            result.to_string(),
+        ]);
+    }
+    print_table(&table, theme);
 
-                    if args.debug {
-                        println!(
-                            "{} {}",
-                            "🔍 Debug - Plugin:".bright_blue(),
-                            format!("Plugin '{}' matched input", plugin_name).bright_blue()
-                        );
-                    }
+    Ok(())
+}
 
-                    Some(result)
-                } else {
-                    println!(
-                        "{} {} {}",
-                        "⚠️ Warning:".yellow(),
-                        format!("Plugin '{}' couldn't process the request", plugin_name).yellow(),
-                        "Falling back to LLM.".yellow()
-                    );
-                    None
-                }
-            } else {
-                println!(
-                    "{} {} {}",
-                    "⚠️ Warning:".yellow(),
-                    format!("Plugin '{}' can't handle this request", plugin_name).yellow(),
-                    "Falling back to LLM.".yellow()
-                );
-                None
-            }
-        } else {
-            println!(
-                "{} {}",
-                "⚠️ Warning:".yellow(),
-                format!("Plugin '{}' not found", plugin_name).yellow()
-            );
-            None
+// What batch prompts are built from besides the request itself
+struct BatchContext<'a> {
+    history: &'a CommandHistory,
+    attachments: &'a [AttachedFile],
+    config: &'a EnterpriseConfig,
+    preprocessors: &'a [Box<dyn InputPreprocessor>],
+}
+
+// Generate a command for each non-empty line of the file, without executing any of them
+async fn run_batch(
+    args: &CliArgs,
+    path: &str,
+    providers: &[Arc<LLMProvider>],
+    use_chain: bool,
+    context: &BatchContext<'_>,
+    theme: &Theme,
+) -> io::Result<()> {
+    let requests: Vec<String> = match fs::read_to_string(path) {
+        Ok(contents) => contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Err(e) => {
+            let message = format!("Could not read {}: {}", path, e);
+            eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&message));
+            return Err(ExitReason::Config.error(message));
         }
-    } else {
-        // Try all plugins
-        let mut result = None;
+    };
 
-        for (name, _) in &plugin_manager.list_plugins() {
-            if let Some(plugin) = plugin_manager.get_plugin(name) {
-                if plugin.can_handle(&user_input) {
-                    if let Some(cmd_result) = plugin.handle(&user_input) {
-                        println!("{} {}", "🔌 Using plugin:".bright_green(), name);
+    let prompts: Vec<String> = requests
+        .iter()
+        .map(|request| {
+            let mut prompt_context = prompt_context(
+                context.history,
+                request,
+                None,
+                context.attachments,
+                context.config.security.prefer_no_sudo,
+                context.config.llm.target_os,
+                context.config.llm.persona.as_deref(),
+            );
+            prompt_context.shell = context.config.llm.shell;
+            prompt_context.template =
+                model_template(&context.config.llm.prompt_templates, providers);
+            construct_prompt_with_context(
+                &preprocess_input(context.preprocessors, request),
+                &prompt_context,
+            )
+        })
+        .collect();
 
-                        if args.debug {
-                            println!(
-                                "{} {}",
-                                "🔍 Debug - Plugin:".bright_blue(),
-                                format!("Plugin '{}' automatically selected", name).bright_blue()
-                            );
-                        }
+    let parse_options = ParseOptions { strip_comments: !args.keep_comments, ..Default::default() };
+    let max_concurrent = context.config.llm.max_concurrent_requests;
+    let results =
+        generate_commands(providers, use_chain, &prompts, &parse_options, max_concurrent).await;
 
-                        result = Some(cmd_result);
-                        break;
-                    }
-                }
+    let mut failed = 0;
+    for (request, result) in requests.iter().zip(results) {
+        println!("\n{}: {}", theme.info("Request"), request);
+        match result {
+            Ok((command, explanation)) => {
+                println!("{}: {}", theme.success("Command"), theme.command(&command));
+                println!("{}: {}", theme.success("Explanation"), theme.explanation(&explanation));
+            }
+            Err(e) => {
+                println!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+                failed += 1;
             }
         }
+    }
 
-        result
-    };
+    if failed > 0 {
+        let message = format!("{} of {} requests failed", failed, requests.len());
+        return Err(ExitReason::Generation.error(message));
+    }
+    Ok(())
+}
 
-    // Process with plugin if we have a result
-    if let Some(plugin_result) = plugin_result {
-        println!("\n{}", "🤖 I'll help you with that!".bright_green());
-        println!("{}: {}", "Command".bright_green(), plugin_result.command);
-        println!("{}: {}", "Explanation".bright_green(), plugin_result.explanation);
-
-        // If the plugin has already executed the command, just display the output
-        if plugin_result.executed {
-            if let Some(output) = plugin_result.output {
-                println!("\n{}", "🚀 Command executed by plugin:".bright_green());
-                println!("{}", output);
-                // Add command to history
-                history.add_entry(
-                    user_input,
-                    plugin_result.command,
-                    Some(plugin_result.explanation.clone()),
-                );
-                return Ok(());
-            }
-        }
+// Read commands from stdin until EOF and print how the safety checker rates each one
+fn run_safety_tester(checker: &CommandSafetyChecker, theme: &Theme) -> io::Result<()> {
+    let interactive = io::stdin().is_terminal();
+    if interactive {
+        println!(
+            "{}",
+            theme.info("🛡️ Safety rule tester - enter commands to check (Ctrl-D to exit)")
+        );
+    }
 
-        // Otherwise, prompt user for action
-        let action = executor.prompt_for_action(
-            &plugin_result.command,
-            &plugin_result.explanation,
-            args.force,
-        )?;
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if interactive {
+            print!("{} ", theme.info("safety>"));
+            io::stdout().flush()?;
+        }
 
-        match action {
-            UserAction::Run => {
-                // Execute the command
-                match executor.execute_command(&plugin_result.command, args.dry_run).await {
-                    Ok(output) => {
-                        println!("\n{}", "✅ Command executed successfully:".bright_green());
-                        println!("{}", output);
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let command = line?;
+        let command = command.trim();
+        if command.is_empty() {
+            continue;
+        }
 
-                        // Add command to history
-                        history.add_entry(
-                            user_input.clone(),
-                            plugin_result.command.clone(),
-                            Some(plugin_result.explanation.clone()),
-                        );
+        let result = checker.check_command_detailed(command);
+        let level = result.level.to_string();
+        let level = match result.level {
+            SafetyLevel::Safe => theme.success(&level),
+            SafetyLevel::Warning => theme.warning(&level),
+            SafetyLevel::Dangerous | SafetyLevel::Blocked => theme.error(&level),
+        };
 
-                        // Prompt for feedback if not disabled
-                        if !args.no_feedback {
-                            handle_feedback(&mut history, &executor, &plugin_result.command)?;
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!(
-                            "\n{} {}",
-                            "❌ Error executing command:".bright_red(),
-                            e.to_string().bright_red()
-                        );
-                    }
-                }
+        match (result.reason, result.reason_code) {
+            (Some(reason), Some(code)) => {
+                println!("{} {} - {} ({:?})", command.bold(), level, reason, code)
             }
-            UserAction::Copy => {
-                match copy_to_clipboard(&plugin_result.command) {
-                    Ok(_) => {
-                        println!("\n{}", "📋 Command copied to clipboard!".bright_green());
+            (Some(reason), None) => println!("{} {} - {}", command.bold(), level, reason),
+            _ => println!("{} {}", command.bold(), level),
+        }
+    }
 
-                        // Add to history when copied too
-                        history.add_entry(
-                            user_input.clone(),
-                            plugin_result.command.clone(),
-                            Some(plugin_result.explanation.clone()),
-                        );
+    if interactive {
+        println!();
+    }
 
-                        // Prompt for feedback if not disabled
-                        if !args.no_feedback {
-                            handle_feedback(&mut history, &executor, &plugin_result.command)?;
-                        }
-                    }
-                    Err(e) => eprintln!(
-                        "{} {}",
-                        "❌ Error copying to clipboard:".bright_red(),
-                        e.to_string().bright_red()
-                    ),
-                }
-            }
-            UserAction::Abort => {
-                println!("\n{}", "🛑 Command execution aborted.".yellow());
-            }
+    Ok(())
+}
+
+// Print the audit log entries matching the --audit-* filters
+fn print_audit_report(args: &CliArgs, config: &EnterpriseConfig, theme: &Theme) -> io::Result<()> {
+    let mut query = AuditQuery::new()
+        .safety_levels(args.audit_level.iter().copied())
+        .executed_only(args.audit_executed_only);
+    if let Some(user) = &args.audit_user {
+        query = query.user(user);
+    }
+    if let Some(backend) = &args.audit_backend {
+        query = query.backend(backend);
+    }
+    if let Some(text) = &args.audit_contains {
+        query = query.command_contains(text);
+    }
+
+    let entries = match AuditLogger::new(config.get_audit_log_path()).query(&query) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+            return Err(ExitReason::Config.error(e.to_string()));
         }
+    };
 
+    if entries.is_empty() {
+        println!("{}", theme.warning("No matching audit entries found."));
         return Ok(());
     }
 
-    // If no plugin can handle it, use the LLM
-    println!("{} {}", "🧠 Using LLM backend:".bright_blue(), provider.name());
+    let page = listing_page(args, entries.len());
+    let shown = page.as_ref().map_or(0..entries.len(), |page| page.range.clone());
 
-    // Skip LLM if in offline mode and the LLM is online-only
-    if args.offline && provider.is_online() {
-        println!("{}", "❌ Cannot use online LLM in offline mode. Exiting.".bright_red());
-        return Ok(());
+    println!("\n{}", theme.info("🧾 Audit Report:"));
+    println!("{}", theme.info("---------------"));
+    let mut table = Table::new(&["Time", "User", "Level", "Command", "Backend", "Executed"]);
+    for entry in &entries[shown] {
+        let local_time = chrono::DateTime::<chrono::Local>::from(
+            UNIX_EPOCH + std::time::Duration::from_secs(entry.timestamp),
+        );
+        let level = entry.safety_level.to_string();
+        let level = match entry.safety_level {
+            SafetyLevel::Safe => theme.success(&level),
+            SafetyLevel::Warning => theme.warning(&level),
+            SafetyLevel::Dangerous | SafetyLevel::Blocked => theme.error(&level),
+        };
+
+        table.add_row(vec![
+            local_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+            entry.user.clone(),
+            level.to_string(),
+            theme.command(&entry.command).to_string(),
+            entry.backend.clone(),
+            if entry.executed { "yes" } else { "no" }.to_string(),
+        ]);
+    }
+    print_table(&table, theme);
+    println!("\n{} {}", theme.info("Entries:"), entries.len());
+    if let Some(page) = &page {
+        print_page_footer(page, entries.len(), theme);
     }
 
-    // Generate the shell command using the LLM
-    let prompt = construct_prompt(&user_input);
+    Ok(())
+}
 
-    if args.debug {
-        println!("{} {}", "🔍 Debug - Prompt:".bright_blue(), prompt.bright_blue());
+// The page of a listing to show: the one asked for with --page or, in a terminal, the last
+// page of a list longer than one page. Piped output shows everything unless --page is given.
+fn listing_page(args: &CliArgs, total: usize) -> Option<Page> {
+    let number = args.page.or_else(|| {
+        let long = total > args.page_size.max(1);
+        (long && io::stdout().is_terminal()).then_some(usize::MAX)
+    })?;
+    Some(paginate(total, number, args.page_size))
+}
+
+// Print a table fitted to the terminal, with its header in the info color
+fn print_table(table: &Table, theme: &Theme) {
+    let lines = table.render_for_output();
+    if let Some((header, rows)) = lines.split_first() {
+        println!("{}", theme.info(header));
+        for row in rows {
+            println!("{}", row);
+        }
+    }
+}
+
+fn print_page_footer(page: &Page, total: usize, theme: &Theme) {
+    if page.pages == 1 {
+        return;
     }
+    println!(
+        "{}",
+        theme.info(&format!(
+            "Page {} of {} (entries {}-{} of {}). Use --page <N> to see another page.",
+            page.number,
+            page.pages,
+            page.range.start + 1,
+            page.range.end,
+            total
+        ))
+    );
+}
 
-    let (command, explanation) = match generate_command(&provider, &prompt).await {
-        Ok((cmd, exp)) => (cmd, exp),
+// Summarize the entry checks of a history or audit file. Malformed or out-of-order entries
+// make it an invalid file, for scripts that check the exit code.
+fn print_integrity_report(
+    path: &str,
+    checks: Result<Vec<EntryCheck>, String>,
+    theme: &Theme,
+) -> io::Result<()> {
+    let report = match checks {
+        Ok(checks) => IntegrityReport::from_checks(checks),
         Err(e) => {
-            eprintln!(
-                "{} {}",
-                "❌ Error generating command:".bright_red(),
-                e.to_string().bright_red()
-            );
-            return Err(io::Error::new(io::ErrorKind::Other, e.to_string()));
+            let message = format!("Could not read {}: {}", path, e);
+            eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&message));
+            return Err(ExitReason::Config.error(message));
         }
     };
+    let format_time = |timestamp: u64| {
+        chrono::DateTime::<chrono::Local>::from(
+            UNIX_EPOCH + std::time::Duration::from_secs(timestamp),
+        )
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+    };
 
-    // Display command and explanation
-    println!("\n{}", "🤖 I'll help you with that!".bright_green());
-
-    // Prompt user for action
-    let action = executor.prompt_for_action(&command, &explanation, args.force)?;
-
-    match action {
-        UserAction::Run => {
-            // Execute the command directly without the helper function
-            match executor.execute_command(&command, args.dry_run).await {
-                Ok(output) => {
-                    println!("\n{}", "✅ Command executed successfully:".bright_green());
-                    println!("{}", output);
-
-                    // Add command to history
-                    history.add_entry(
-                        user_input.clone(),
-                        command.clone(),
-                        Some(explanation.clone()),
-                    );
+    println!("\n{} {}", theme.info("🩺 Checked:"), path);
+    println!("{} {}", theme.success("Valid entries:"), report.valid);
+    if let (Some(earliest), Some(latest)) = (report.earliest, report.latest) {
+        println!(
+            "{} {} to {}",
+            theme.info("Time span:"),
+            format_time(earliest),
+            format_time(latest)
+        );
+    }
 
-                    // Prompt for feedback if not disabled
-                    if !args.no_feedback {
-                        handle_feedback(&mut history, &executor, &command)?;
-                    }
-                }
-                Err(e) => {
-                    eprintln!(
-                        "\n{} {}",
-                        "❌ Error executing command:".bright_red(),
-                        e.to_string().bright_red()
-                    );
-                }
-            }
+    if !report.malformed.is_empty() {
+        println!("{} {}", theme.error("Malformed entries:"), report.malformed.len());
+        for (number, reason) in &report.malformed {
+            println!("  {} {}", theme.error(&format!("#{}:", number)), reason);
         }
-        UserAction::Copy => {
-            match copy_to_clipboard(&command) {
-                Ok(_) => {
-                    println!("\n{}", "📋 Command copied to clipboard!".bright_green());
+    }
+    if !report.out_of_order.is_empty() {
+        let numbers: Vec<String> =
+            report.out_of_order.iter().map(|number| format!("#{}", number)).collect();
+        println!("{} {}", theme.warning("Older than the entry before them:"), numbers.join(", "));
+    }
 
-                    // Add to history when copied too
-                    history.add_entry(
-                        user_input.clone(),
-                        command.clone(),
-                        Some(explanation.clone()),
-                    );
+    if report.is_clean() {
+        println!("{}", theme.success("✅ No problems found."));
+        return Ok(());
+    }
+    Err(ExitReason::Config.error(format!("{} has problems", path)))
+}
 
-                    // Prompt for feedback if not disabled
-                    if !args.no_feedback {
-                        handle_feedback(&mut history, &executor, &command)?;
-                    }
-                }
-                Err(e) => eprintln!(
-                    "{} {}",
-                    "❌ Error copying to clipboard:".bright_red(),
-                    e.to_string().bright_red()
-                ),
-            }
+// Print the JSON Schema of the configuration file
+#[cfg(feature = "config-schema")]
+fn print_config_schema(theme: &Theme) -> io::Result<()> {
+    match EnterpriseConfig::json_schema() {
+        Ok(schema) => {
+            println!("{}", schema);
+            Ok(())
         }
-        UserAction::Abort => {
-            println!("\n{}", "🛑 Command execution aborted.".yellow());
+        Err(e) => {
+            eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+            Err(ExitReason::Config.error(e.to_string()))
         }
     }
+}
 
-    Ok(())
+#[cfg(not(feature = "config-schema"))]
+fn print_config_schema(theme: &Theme) -> io::Result<()> {
+    let message = "--config-schema is not available. To enable it, build with --features \"cli/config-schema\"";
+    eprintln!("{} {}", theme.error("❌ Error:"), theme.error(message));
+    Err(ExitReason::Config.error(message))
+}
+
+// Explain a command with the LLM, reusing the cached explanation if there is one
+async fn enrich_explanation(
+    providers: &[Arc<LLMProvider>],
+    use_chain: bool,
+    cache: &mut ExplanationCache,
+    command: &str,
+) -> Result<String, LLMError> {
+    if let Some(explanation) = cache.get(command) {
+        return Ok(explanation.to_string());
+    }
+
+    let prompt = construct_explanation_prompt(command);
+    let options = ParseOptions::default();
+    let (_, explanation) = if use_chain {
+        generate_command_with_chain(providers, &prompt, &options).await?
+    } else {
+        generate_command(&providers[0], &prompt, &options).await?
+    };
+
+    if explanation.trim().is_empty() {
+        return Err(LLMError::ParsingError("The LLM returned an empty explanation".into()));
+    }
+
+    // Failing to save the cache only means the command is explained again next time
+    let _ = cache.insert(command.to_string(), explanation.clone());
+    Ok(explanation)
 }
 
 // Helper function to handle feedback
-fn handle_feedback(
+async fn handle_feedback(
     history: &mut CommandHistory,
     executor: &ShellExecutor,
     command: &str,
 ) -> io::Result<()> {
-    let (feedback, edited_cmd) = executor.prompt_for_feedback(command)?;
+    let (feedback, edited_cmd) = executor.prompt_for_feedback(command).await?;
+    let theme = executor.theme();
 
     // Process feedback
     match feedback {
         FeedbackAction::Helpful => {
             history.update_last_entry_feedback(FeedbackType::Helpful, None);
-            println!("{}", "👍 Thanks for your feedback!".bright_green());
+            println!("{}", theme.success("👍 Thanks for your feedback!"));
         }
         FeedbackAction::NotHelpful => {
             history.update_last_entry_feedback(FeedbackType::NotHelpful, None);
             println!(
                 "{}",
-                "👎 Sorry to hear that. We'll try to do better next time!".bright_yellow()
+                theme.warning("👎 Sorry to hear that. We'll try to do better next time!")
             );
         }
         FeedbackAction::Edit => {
@@ -396,12 +2034,12 @@ fn handle_feedback(
                 history.update_last_entry_feedback(FeedbackType::Edited, Some(cmd));
                 println!(
                     "{}",
-                    "✏️ Thanks for your correction! We'll learn from this.".bright_green()
+                    theme.success("✏️ Thanks for your correction! We'll learn from this.")
                 );
             }
         }
         FeedbackAction::Skip => {
-            println!("{}", "⏭️ Feedback skipped.".bright_blue());
+            println!("{}", theme.info("⏭️ Feedback skipped."));
         }
     }
 
@@ -409,74 +2047,86 @@ fn handle_feedback(
 }
 
 // Create the appropriate LLM provider based on CLI arguments
-fn create_llm_provider(args: &CliArgs) -> Result<LLMProvider, LLMError> {
+fn create_llm_provider(
+    args: &CliArgs,
+    llm: &LLMConfig,
+    offline: bool,
+    theme: &Theme,
+) -> Result<LLMProvider, LLMError> {
     // If offline mode is enabled, ensure we don't use online providers
-    if args.offline {
+    if offline {
         match args.backend.to_lowercase().as_str() {
             "openai" => {
                 println!(
                     "{}",
-                    "⚠️ OpenAI backend requires internet. Using local LLM instead.".yellow()
+                    theme.warning("⚠️ OpenAI backend requires internet. Using local LLM instead.")
                 );
                 return Ok(LLMProvider::LlmRs(LlmRsProvider::new(
-                    &args.model_path.clone().unwrap_or_else(|| "models/tinyllama.gguf".to_string()),
+                    &args.model_path.clone().unwrap_or_else(|| DEFAULT_MODEL_PATH.to_string()),
                 )));
             }
             "ollama" if args.online => {
                 println!(
                     "{}",
-                    "⚠️ Online Ollama mode requires internet. Using local model instead.".yellow()
+                    theme.warning(
+                        "⚠️ Online Ollama mode requires internet. Using local model instead."
+                    )
                 );
-                return Ok(LLMProvider::Ollama(OllamaProvider::new("codellama")));
+                return Ok(LLMProvider::Ollama(ollama_provider("codellama", llm)));
             }
             _ => {}
         }
     }
 
     match args.backend.to_lowercase().as_str() {
-        "ollama" => {
-            // Choose codellama or wizardcoder model
-            let model = if args.online { "wizardcoder" } else { "codellama" };
-            Ok(LLMProvider::Ollama(OllamaProvider::new(model)))
-        }
+        "ollama" => Ok(LLMProvider::Ollama(ollama_provider(ollama_model(args, offline), llm))),
         "llm-rs" => {
             let model_path = args.model_path.clone().unwrap_or_else(|| {
-                println!("{}", "ℹ️ No model path specified, using default model path".blue());
-                "models/tinyllama.gguf".to_string()
+                println!("{}", theme.info("ℹ️ No model path specified, using default model path"));
+                DEFAULT_MODEL_PATH.to_string()
             });
             Ok(LLMProvider::LlmRs(LlmRsProvider::new(&model_path)))
         }
         "openai" => {
-            if args.offline {
+            if offline {
                 return Err(LLMError::ApiKeyError(
                     "OpenAI backend cannot be used in offline mode".into(),
                 ));
             }
 
-            let model = args.openai_model.as_deref().unwrap_or("gpt-3.5-turbo");
-            match OpenAIProvider::new_with_model(model) {
+            let model = openai_model(args);
+            match openai_provider(model, llm) {
                 Ok(provider) => {
                     println!(
                         "{} {}",
-                        "✅ OpenAI backend initialized successfully with model:".green(),
-                        model.green()
+                        theme.success("✅ OpenAI backend initialized successfully with model:"),
+                        theme.success(model)
                     );
                     Ok(LLMProvider::OpenAI(provider))
                 }
                 Err(LLMError::ApiKeyError(msg)) => {
-                    eprintln!("{} {}", "❌ OpenAI Configuration Error:".red(), msg.red());
-                    eprintln!("{}", "💡 To use OpenAI backend:".yellow());
+                    eprintln!(
+                        "{} {}",
+                        theme.error("❌ OpenAI Configuration Error:"),
+                        theme.error(&msg)
+                    );
+                    eprintln!("{}", theme.warning("💡 To use OpenAI backend:"));
                     eprintln!(
                         "   {}",
-                        "1. Set your API key: export OPENAI_API_KEY=sk-your-key-here".yellow()
+                        theme
+                            .warning("1. Set your API key: export OPENAI_API_KEY=sk-your-key-here")
                     );
                     eprintln!(
                         "   {}",
-                        "2. Or create a .env file with: OPENAI_API_KEY=sk-your-key-here".yellow()
+                        theme.warning(
+                            "2. Or create a .env file with: OPENAI_API_KEY=sk-your-key-here"
+                        )
                     );
                     eprintln!(
                         "   {}",
-                        "3. Get your API key from: https://platform.openai.com/api-keys".yellow()
+                        theme.warning(
+                            "3. Get your API key from: https://platform.openai.com/api-keys"
+                        )
                     );
                     Err(LLMError::ApiKeyError(msg))
                 }
@@ -486,31 +2136,256 @@ fn create_llm_provider(args: &CliArgs) -> Result<LLMProvider, LLMError> {
         _ => {
             println!(
                 "{} {}",
-                "⚠️ Unknown backend:".yellow(),
-                format!("{}. Using default (Ollama)", args.backend).yellow()
+                theme.warning("⚠️ Unknown backend:"),
+                theme.warning(&format!("{}. Using default (Ollama)", args.backend))
             );
             Ok(LLMProvider::default())
         }
     }
 }
 
+// The Ollama model: wizardcoder with --online, unless offline mode rules it out
+fn ollama_model(args: &CliArgs, offline: bool) -> &'static str {
+    if args.online && !offline {
+        "wizardcoder"
+    } else {
+        "codellama"
+    }
+}
+
+fn openai_model(args: &CliArgs) -> &str {
+    args.openai_model.as_deref().unwrap_or("gpt-3.5-turbo")
+}
+
+// The model a backend asks, or None for LLM-rs, whose model file has no name to match
+fn backend_model(args: &CliArgs, backend: BackendKind, offline: bool) -> Option<&str> {
+    match backend {
+        BackendKind::Ollama => Some(ollama_model(args, offline)),
+        BackendKind::OpenAI => Some(openai_model(args)),
+        BackendKind::LlmRs => None,
+    }
+}
+
+// The backend that would be asked first for `input`, making the same choices as the
+// provider chain, the router and the selected backend without creating any provider.
+// None when no backend is usable, or --replay answers instead.
+fn first_backend(
+    args: &CliArgs,
+    llm: &LLMConfig,
+    input: &str,
+    offline: bool,
+) -> Option<BackendKind> {
+    let usable = |backend: &BackendKind| !(offline && backend.is_online());
+    if args.replay.is_some() {
+        return None;
+    }
+    if !llm.fallback_chain.is_empty() {
+        return llm.fallback_chain.iter().copied().find(usable);
+    }
+    if let Some(policy) = &llm.routing {
+        let preferred = policy.backend_for(input);
+        return [preferred, policy.short, policy.long].into_iter().find(usable);
+    }
+    match args.backend.to_lowercase().as_str() {
+        "llm-rs" => Some(BackendKind::LlmRs),
+        "openai" if offline => Some(BackendKind::LlmRs),
+        "openai" => Some(BackendKind::OpenAI),
+        _ => Some(BackendKind::Ollama),
+    }
+}
+
+// Create an Ollama provider with the extra request fields and timeout from `llm`
+fn ollama_provider(model: &str, llm: &LLMConfig) -> OllamaProvider {
+    OllamaProvider::new(model)
+        .with_extra_options(llm.ollama.extra_options.clone())
+        .with_timeout(llm.timeout())
+}
+
+// Create an OpenAI provider with the sampling settings, extra request fields and timeout
+// from `llm`
+fn openai_provider(model: &str, llm: &LLMConfig) -> Result<OpenAIProvider, LLMError> {
+    Ok(OpenAIProvider::new_with_model(model)?
+        .with_temperature(llm.temperature)
+        .with_top_p(llm.top_p)
+        .with_max_tokens(llm.max_tokens)
+        .with_extra_options(llm.openai.extra_options.clone())
+        .with_timeout(llm.timeout()))
+}
+
+// The providers of `llm.routing`. When one of them can't be created, such as an online
+// backend in offline mode, the other takes its requests too.
+struct Router {
+    policy: RoutingPolicy,
+    short: Arc<LLMProvider>,
+    long: Arc<LLMProvider>,
+}
+
+// Create the providers of a routing policy, or None if neither can be created
+fn create_router(
+    args: &CliArgs,
+    llm: &LLMConfig,
+    policy: &RoutingPolicy,
+    offline: bool,
+    theme: &Theme,
+) -> Option<Router> {
+    let create = |backend| create_provider_chain(args, llm, &[backend], offline, theme).pop();
+    let short = create(policy.short);
+    let long = if policy.long == policy.short { short.clone() } else { create(policy.long) };
+
+    let (short, long) = match (short, long) {
+        (Some(short), Some(long)) => (short, long),
+        (Some(provider), None) | (None, Some(provider)) => (provider.clone(), provider),
+        (None, None) => return None,
+    };
+    Some(Router { policy: policy.clone(), short, long })
+}
+
+// Pick the provider for a request by its length
+fn select_provider(input: &str, router: &Router) -> Arc<LLMProvider> {
+    if router.policy.backend_for(input) == router.policy.short {
+        router.short.clone()
+    } else {
+        router.long.clone()
+    }
+}
+
+// Create the providers of the configured fallback chain, in order. A backend listed twice
+// shares one provider. Online providers are skipped in offline mode, as are providers that
+// fail to initialize.
+fn create_provider_chain(
+    args: &CliArgs,
+    llm: &LLMConfig,
+    chain: &[BackendKind],
+    offline: bool,
+    theme: &Theme,
+) -> Vec<Arc<LLMProvider>> {
+    build_chain(chain, |backend| {
+        if offline && backend.is_online() {
+            println!(
+                "{} {:?}",
+                theme.warning("⚠️ Offline mode - skipping online backend:"),
+                backend
+            );
+            return None;
+        }
+
+        let provider = match backend {
+            BackendKind::Ollama => {
+                LLMProvider::Ollama(ollama_provider(ollama_model(args, offline), llm))
+            }
+            BackendKind::LlmRs => LLMProvider::LlmRs(LlmRsProvider::new(
+                args.model_path.as_deref().unwrap_or(DEFAULT_MODEL_PATH),
+            )),
+            BackendKind::OpenAI => match openai_provider(openai_model(args), llm) {
+                Ok(provider) => LLMProvider::OpenAI(provider),
+                Err(e) => {
+                    println!(
+                        "{} {}",
+                        theme.warning("⚠️ Skipping OpenAI backend:"),
+                        theme.warning(&e.to_string())
+                    );
+                    return None;
+                }
+            },
+        };
+        Some(provider)
+    })
+}
+
+// Show the entries that differ between this history and the one at `other_path`
+fn display_history_diff(diff: &HistoryDiff, other_path: &str, theme: &Theme) {
+    if diff.is_empty() {
+        println!("{}", theme.success("✅ The histories match."));
+        return;
+    }
+
+    let sections = [
+        ("Only in this history", &diff.only_here),
+        (&format!("Only in {}", other_path) as &str, &diff.only_there),
+    ];
+    for (title, entries) in sections {
+        if entries.is_empty() {
+            continue;
+        }
+        println!("\n{}", theme.info(&format!("{} ({}):", title, entries.len())));
+        for entry in entries {
+            println!(
+                "  [{}] \"{}\" => \"{}\"",
+                theme.info(&entry.timestamp),
+                theme.success(&entry.input),
+                theme.command(&entry.command)
+            );
+        }
+    }
+
+    if !diff.feedback_differs.is_empty() {
+        println!(
+            "\n{}",
+            theme.info(&format!("Feedback differs ({}):", diff.feedback_differs.len()))
+        );
+        for difference in &diff.feedback_differs {
+            println!(
+                "  \"{}\" => \"{}\": {} here, {} there",
+                theme.success(&difference.input),
+                theme.command(&difference.command),
+                difference.here,
+                difference.there
+            );
+        }
+    }
+}
+
+fn display_favorites(favorites: &Favorites, theme: &Theme) {
+    let favorites = favorites.list_favorites();
+    if favorites.is_empty() {
+        println!(
+            "{}",
+            theme.warning("No favorites yet. Save the last command with --favorite-add <NAME>.")
+        );
+        return;
+    }
+
+    println!("\n{}", theme.info("⭐ Favorites:"));
+    println!("{}", theme.info("-----------"));
+    for (name, favorite) in favorites {
+        println!("{}  {}", theme.success(name), theme.command(&favorite.command));
+        if let Some(explanation) = &favorite.explanation {
+            println!("   {}: {}", theme.info("Explanation"), explanation);
+        }
+    }
+}
+
 // Display the command history
-fn display_history(history: &CommandHistory) {
+fn display_history(
+    history: &CommandHistory,
+    theme: &Theme,
+    with_output: bool,
+    page: Option<&Page>,
+) {
     let entries = history.get_history();
 
     if entries.is_empty() {
-        println!("{}", "No command history found.".yellow());
+        println!("{}", theme.warning("No command history found."));
         return;
     }
 
-    println!("\n{}", "📜 Command History:".bright_cyan());
-    println!("{}", "---------------".bright_cyan());
+    println!("\n{}", theme.info("📜 Command History:"));
+    println!("{}", theme.info("---------------"));
 
-    for (i, entry) in entries.iter().enumerate() {
+    // Entries keep their numbers across pages
+    let shown = page.map_or(0..entries.len(), |page| page.range.clone());
+    let mut table = Table::new(&["#", "Time", "", "", "Request", "Command"]).align_right(0);
+    for (i, entry) in entries.iter().enumerate().skip(shown.start).take(shown.len()) {
         let local_time = chrono::DateTime::<chrono::Local>::from(
             std::time::UNIX_EPOCH + std::time::Duration::from_secs(entry.timestamp),
         );
-        let formatted_time = local_time.format("%Y-%m-%d %H:%M:%S");
+
+        // Whether the command succeeded, when it was run
+        let status_indicator = match entry.exit_code {
+            Some(0) => "✅",
+            Some(_) => "❌",
+            None => "  ",
+        };
 
         // Get feedback indicator
         let feedback_indicator = match entry.feedback {
@@ -520,25 +2395,57 @@ fn display_history(history: &CommandHistory) {
             FeedbackType::None => "  ",
         };
 
-        println!(
-            "{}. [{}] {} \"{}\" => \"{}\"",
-            (i + 1).to_string().bright_blue(),
-            formatted_time.to_string().cyan(),
-            feedback_indicator,
-            entry.input.bright_green(),
-            entry.command.yellow()
-        );
+        // Multi-line commands such as heredocs are shown in full below their row
+        let command = match entry.command.split_once('\n') {
+            Some((first_line, _)) => format!("{} …", first_line),
+            None => entry.command.clone(),
+        };
+        table.add_row(vec![
+            theme.info(&(i + 1).to_string()).to_string(),
+            theme.info(&local_time.format("%Y-%m-%d %H:%M:%S").to_string()).to_string(),
+            status_indicator.to_string(),
+            feedback_indicator.to_string(),
+            theme.success(&entry.input).to_string(),
+            theme.command(&command).to_string(),
+        ]);
+    }
+
+    let lines = table.render_for_output();
+    println!("{}", theme.info(&lines[0]));
+    for (entry, row) in entries.iter().skip(shown.start).zip(&lines[1..]) {
+        println!("{}", row);
+
+        if entry.command.contains('\n') {
+            println!("   {}", theme.info("┌────"));
+            for line in entry.command.lines() {
+                println!("   {} {}", theme.info("│"), theme.command(line));
+            }
+            println!("   {}", theme.info("└────"));
+        }
 
         // Show explanation if available
         if let Some(explanation) = &entry.explanation {
-            println!("   {}: {}", "Explanation".bright_cyan(), explanation);
+            println!("   {}: {}", theme.info("Explanation"), explanation);
         }
 
         // Show original command if edited
         if let Some(original) = &entry.original_command {
-            println!("   {}: {}", "Original command".bright_red(), original);
+            println!("   {}: {}", theme.error("Original command"), original);
+        }
+
+        if with_output {
+            if let Some(output) = &entry.output {
+                println!("   {}:", theme.info("Output"));
+                for line in output.lines() {
+                    println!("     {}", line);
+                }
+            }
         }
 
-        println!("");
+        println!();
+    }
+
+    if let Some(page) = page {
+        print_page_footer(page, entries.len(), theme);
     }
 }
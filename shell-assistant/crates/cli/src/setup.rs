@@ -0,0 +1,272 @@
+use cli::ExitReason;
+use core::config::BackendKind;
+use core::llm::{validate_api_key, OllamaProvider, DEFAULT_MODEL_PATH, LLM_RS_ENABLED};
+use core::EnterpriseConfig;
+use dialoguer::{theme::ColorfulTheme, Confirm, Password, Select};
+use executor::shell::run_blocking;
+use executor::Theme;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Model the wizard checks for (and offers to pull) when Ollama is selected
+const OLLAMA_MODEL: &str = "codellama";
+
+/// The outcome of a prerequisite check
+#[derive(Debug, Clone, PartialEq)]
+pub enum Check {
+    Ok(String),
+    /// The Ollama server is up but doesn't have the model
+    MissingModel(String),
+    Problem(String),
+}
+
+/// Check that the Ollama server is reachable and has `model` installed
+pub async fn check_ollama(model: &str) -> Check {
+    match OllamaProvider::new(model).has_model().await {
+        Ok(true) => Check::Ok(format!("Ollama is running and has the {} model", model)),
+        Ok(false) => Check::MissingModel(model.to_string()),
+        Err(e) => Check::Problem(e.to_string()),
+    }
+}
+
+/// Check that an OpenAI API key is set and looks valid
+pub fn check_openai_key() -> Check {
+    match std::env::var("OPENAI_API_KEY") {
+        Ok(key) if !key.trim().is_empty() => match validate_api_key(&key) {
+            Ok(_) => Check::Ok("OPENAI_API_KEY is set".to_string()),
+            Err(e) => Check::Problem(e.to_string()),
+        },
+        _ => Check::Problem("OPENAI_API_KEY is not set".to_string()),
+    }
+}
+
+/// Check that the LLM-rs backend is built in and its model file exists
+pub fn check_local_model(model_path: &Path) -> Check {
+    if !LLM_RS_ENABLED {
+        return Check::Problem(
+            "This build doesn't include LLM-rs (rebuild with --features \"core/llm-rs\")"
+                .to_string(),
+        );
+    }
+    if !model_path.is_file() {
+        return Check::Problem(format!("Model file not found: {}", model_path.display()));
+    }
+    Check::Ok(format!("Found model {}", model_path.display()))
+}
+
+/// Run the setup wizard. An existing config is used as the starting point and is only
+/// overwritten after confirmation, so the wizard can safely be run again. The config is
+/// written to `config_path` (from --config) and an OpenAI key to `env_file` (from
+/// --env-file) when given, otherwise to their default locations.
+pub async fn run_setup(
+    theme: &Theme,
+    config_path: Option<&Path>,
+    env_file: Option<&Path>,
+) -> io::Result<()> {
+    let config_path = match config_path
+        .map_or_else(EnterpriseConfig::default_path, |path| Ok(path.to_path_buf()))
+    {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+            return Err(ExitReason::Config.error(e.to_string()));
+        }
+    };
+
+    println!("{}", theme.info("👋 Welcome to Shell Assistant! Let's get you set up."));
+
+    let mut config = if config_path.exists() {
+        println!("{} {}", theme.info("Existing config:"), config_path.display());
+        match EnterpriseConfig::load(&config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+                return Err(ExitReason::Config.error(e.to_string()));
+            }
+        }
+    } else {
+        EnterpriseConfig::default()
+    };
+
+    let backends = [BackendKind::Ollama, BackendKind::LlmRs, BackendKind::OpenAI];
+    let current = config.llm.fallback_chain.first().copied().unwrap_or(BackendKind::Ollama);
+    let default = backends.iter().position(|&backend| backend == current).unwrap_or(0);
+    let selection = run_blocking(move || {
+        Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Which LLM backend do you want to use?")
+            .items(&[
+                "Ollama (local server)",
+                "LLM-rs (local GGUF model)",
+                "OpenAI (online, needs an API key)",
+            ])
+            .default(default)
+            .interact()
+    })
+    .await??;
+    let backend = backends[selection];
+
+    match backend {
+        BackendKind::Ollama => setup_ollama(theme).await?,
+        BackendKind::LlmRs => report(theme, &check_local_model(Path::new(DEFAULT_MODEL_PATH))),
+        BackendKind::OpenAI => setup_openai_key(theme, env_file).await?,
+    }
+
+    // Offline mode would rule out the backend that was just chosen
+    config.privacy.offline_only = if backend.is_online() {
+        false
+    } else {
+        let current = config.privacy.offline_only;
+        confirm("Never use online LLM providers (offline mode)?", current).await?
+    };
+    config.llm.fallback_chain = vec![backend];
+
+    if config_path.exists()
+        && !confirm(&format!("Overwrite {}?", config_path.display()), false).await?
+    {
+        println!("{}", theme.warning("Config left unchanged."));
+        return Ok(());
+    }
+
+    if let Err(e) = config.save(&config_path) {
+        eprintln!("{} {}", theme.error("❌ Error:"), theme.error(&e.to_string()));
+        return Err(ExitReason::Config.error(e.to_string()));
+    }
+    println!("{} {}", theme.success("✅ Config written to"), config_path.display());
+
+    Ok(())
+}
+
+// Check Ollama, offering to pull the model if the server is up but doesn't have it
+async fn setup_ollama(theme: &Theme) -> io::Result<()> {
+    let check = check_ollama(OLLAMA_MODEL).await;
+    report(theme, &check);
+
+    let missing_model = matches!(check, Check::MissingModel(_));
+    if missing_model && confirm(&format!("Pull the {} model now?", OLLAMA_MODEL), true).await? {
+        println!("{}", theme.info("⏳ Pulling model, this can take several minutes..."));
+        match OllamaProvider::new(OLLAMA_MODEL).pull_model().await {
+            Ok(()) => report(theme, &check_ollama(OLLAMA_MODEL).await),
+            Err(e) => report(theme, &Check::Problem(e.to_string())),
+        }
+    }
+
+    Ok(())
+}
+
+// Check for an OpenAI key, offering to save one to `env_file` or ~/.env if it's missing
+async fn setup_openai_key(theme: &Theme, env_file: Option<&Path>) -> io::Result<()> {
+    let check = check_openai_key();
+    report(theme, &check);
+    if let Check::Ok(_) = check {
+        return Ok(());
+    }
+
+    let key = run_blocking(|| {
+        Password::with_theme(&ColorfulTheme::default())
+            .with_prompt("OpenAI API key (leave empty to skip)")
+            .allow_empty_password(true)
+            .interact()
+    })
+    .await??;
+    if key.trim().is_empty() {
+        println!("{}", theme.warning("Skipped. Set OPENAI_API_KEY before using OpenAI."));
+        return Ok(());
+    }
+    let key = match validate_api_key(&key) {
+        Ok(key) => key,
+        Err(e) => {
+            report(theme, &Check::Problem(e.to_string()));
+            return Ok(());
+        }
+    };
+
+    let Some(env_path) = env_file.map(Path::to_path_buf).or_else(home_env_file) else {
+        println!("{}", theme.warning("Could not determine the home directory to save the key."));
+        return Ok(());
+    };
+    save_env_var(&env_path, "OPENAI_API_KEY", key)?;
+    println!("{} {}", theme.success("✅ Saved the key to"), env_path.display());
+
+    Ok(())
+}
+
+// The .env file in the home directory, which is found from any directory below it
+fn home_env_file() -> Option<PathBuf> {
+    Some(core::home_dir()?.join(".env"))
+}
+
+// Set `name` in a .env file, replacing an existing line for it and keeping the others. A new
+// file is only readable by the user, as it holds a secret.
+fn save_env_var(path: &Path, name: &str, value: &str) -> io::Result<()> {
+    let existing = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let assigns_name = |line: &str| {
+        let line = line.trim_start();
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        line.strip_prefix(name).is_some_and(|rest| rest.trim_start().starts_with('='))
+    };
+
+    let mut contents = String::new();
+    for line in existing.lines().filter(|line| !assigns_name(line)) {
+        contents.push_str(line);
+        contents.push('\n');
+    }
+    contents.push_str(&format!("{}={}\n", name, value));
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+fn report(theme: &Theme, check: &Check) {
+    match check {
+        Check::Ok(message) => println!("{} {}", theme.success("✅"), message),
+        Check::MissingModel(model) => {
+            println!("{} Ollama is running but the {} model is missing", theme.warning("⚠️"), model)
+        }
+        Check::Problem(message) => println!("{} {}", theme.warning("⚠️"), message),
+    }
+}
+
+async fn confirm(prompt: &str, default: bool) -> io::Result<bool> {
+    let prompt = prompt.to_string();
+    run_blocking(move || {
+        Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .default(default)
+            .interact()
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_env_var() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".env");
+
+        save_env_var(&path, "OPENAI_API_KEY", "sk-first").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "OPENAI_API_KEY=sk-first\n");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::write(&path, "# keys\nexport OPENAI_API_KEY = sk-old\nOPENAI_API_KEY_2=x\n").unwrap();
+        save_env_var(&path, "OPENAI_API_KEY", "sk-new").unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# keys\nOPENAI_API_KEY_2=x\nOPENAI_API_KEY=sk-new\n"
+        );
+    }
+}
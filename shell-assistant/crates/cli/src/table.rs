@@ -0,0 +1,168 @@
+use console::{measure_text_width, pad_str, truncate_str, Alignment};
+use executor::output_width;
+use std::borrow::Cow;
+
+// Columns are never truncated below this width, or their header's if it's narrower
+const MIN_COLUMN_WIDTH: usize = 8;
+
+const SEPARATOR: &str = "  ";
+
+/// Rows of text laid out in columns that fit the terminal. When the table is too wide, the
+/// widest columns are truncated with an ellipsis. Cells may contain colors.
+pub struct Table {
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+    right_aligned: Vec<usize>,
+}
+
+impl Table {
+    pub fn new(headers: &[&str]) -> Self {
+        Table {
+            headers: headers.iter().map(|header| header.to_string()).collect(),
+            rows: Vec::new(),
+            right_aligned: Vec::new(),
+        }
+    }
+
+    /// Align the cells of a column to the right, as for numbers
+    pub fn align_right(mut self, column: usize) -> Self {
+        self.right_aligned.push(column);
+        self
+    }
+
+    /// Add a row. Newlines in a cell are shown as `⏎`, so each row is one line.
+    pub fn add_row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells.into_iter().map(|cell| cell.replace('\n', " ⏎ ")).collect());
+    }
+
+    /// The header line followed by a line for each row, in the order they were added
+    pub fn render(&self, width: usize) -> Vec<String> {
+        let widths = self.column_widths(width);
+        std::iter::once(&self.headers)
+            .chain(&self.rows)
+            .map(|cells| self.render_row(cells, &widths))
+            .collect()
+    }
+
+    /// Render the table for standard output: as wide as the terminal, or
+    /// `executor::wrap::FIXED_WIDTH`
+    pub fn render_for_output(&self) -> Vec<String> {
+        self.render(output_width())
+    }
+
+    fn render_row(&self, cells: &[String], widths: &[usize]) -> String {
+        let last = widths.len().saturating_sub(1);
+        let line: Vec<String> = widths
+            .iter()
+            .enumerate()
+            .map(|(i, &width)| {
+                let cell = truncate(cells.get(i).map_or("", String::as_str), width);
+                let align = if self.right_aligned.contains(&i) {
+                    Alignment::Right
+                } else {
+                    Alignment::Left
+                };
+                // The last column isn't padded, so lines don't end in spaces
+                if i == last && align == Alignment::Left {
+                    cell.into_owned()
+                } else {
+                    pad_str(&cell, width, align, None).into_owned()
+                }
+            })
+            .collect();
+        line.join(SEPARATOR)
+    }
+
+    // Each column's width: as wide as its widest cell, then narrowed one character at a time
+    // from the widest column until the table fits
+    fn column_widths(&self, width: usize) -> Vec<usize> {
+        let mut widths: Vec<usize> = self.headers.iter().map(|h| measure_text_width(h)).collect();
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate().take(widths.len()) {
+                widths[i] = widths[i].max(measure_text_width(cell));
+            }
+        }
+
+        let minimums: Vec<usize> = self
+            .headers
+            .iter()
+            .zip(&widths)
+            .map(|(header, &natural)| natural.min(MIN_COLUMN_WIDTH.max(measure_text_width(header))))
+            .collect();
+        let separators = SEPARATOR.len() * widths.len().saturating_sub(1);
+        while widths.iter().sum::<usize>() + separators > width {
+            let widest =
+                (0..widths.len()).filter(|&i| widths[i] > minimums[i]).max_by_key(|&i| widths[i]);
+            match widest {
+                Some(i) => widths[i] -= 1,
+                None => break,
+            }
+        }
+        widths
+    }
+}
+
+// Shorten text to `width` columns, ending it with an ellipsis when anything was cut
+fn truncate(text: &str, width: usize) -> Cow<'_, str> {
+    if measure_text_width(text) <= width {
+        return Cow::Borrowed(text);
+    }
+    match width {
+        0 => Cow::Borrowed(""),
+        _ => truncate_str(text, width, "…"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        let mut table = Table::new(&["Name", "Description"]);
+        table.add_row(vec!["git".to_string(), "Git shortcuts".to_string()]);
+        table.add_row(vec![
+            "docker".to_string(),
+            "Builds, runs and inspects containers and images".to_string(),
+        ]);
+        table
+    }
+
+    #[test]
+    fn test_columns_line_up() {
+        assert_eq!(
+            table().render(80),
+            vec![
+                "Name    Description",
+                "git     Git shortcuts",
+                "docker  Builds, runs and inspects containers and images",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wide_columns_are_truncated() {
+        let lines = table().render(30);
+        assert_eq!(lines[2], "docker  Builds, runs and insp…");
+        assert!(lines.iter().all(|line| measure_text_width(line) <= 30));
+
+        // Columns stay as wide as their header, even if the line ends up too long
+        let lines = table().render(10);
+        assert_eq!(lines[1], "git     Git shortc…");
+    }
+
+    #[test]
+    fn test_right_aligned_and_multi_line_cells() {
+        let mut table = Table::new(&["Backend", "Latency"]).align_right(1);
+        table.add_row(vec!["Ollama".to_string(), "1.25s".to_string()]);
+        table.add_row(vec!["cat <<EOF\nhi\nEOF".to_string(), "90ms".to_string()]);
+
+        assert_eq!(
+            table.render(80),
+            vec![
+                "Backend               Latency",
+                "Ollama                  1.25s",
+                "cat <<EOF ⏎ hi ⏎ EOF     90ms",
+            ]
+        );
+    }
+}
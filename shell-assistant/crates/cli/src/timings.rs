@@ -0,0 +1,92 @@
+use executor::Theme;
+use std::time::{Duration, Instant};
+
+/// How long each phase of handling a request took, for `--timings`. Phases are kept in the
+/// order they first ran.
+#[derive(Debug, Default)]
+pub struct Timings {
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    /// Add the time since `started` to `phase`
+    pub fn record(&mut self, phase: &'static str, started: Instant) {
+        self.add(phase, started.elapsed());
+    }
+
+    // A phase that runs more than once, such as a remediation's second generation, adds up
+    fn add(&mut self, phase: &'static str, duration: Duration) {
+        match self.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += duration,
+            None => self.phases.push((phase, duration)),
+        }
+    }
+
+    /// The breakdown, one line per phase with its share of the total, then the total
+    pub fn lines(&self) -> Vec<String> {
+        let total: Duration = self.phases.iter().map(|(_, duration)| *duration).sum();
+        let width = self.phases.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max(5);
+
+        let mut lines: Vec<String> = self
+            .phases
+            .iter()
+            .map(|(name, duration)| {
+                let share = if total.is_zero() {
+                    0.0
+                } else {
+                    duration.as_secs_f64() / total.as_secs_f64() * 100.0
+                };
+                format!("{:width$}  {:>9}  {:>5.1}%", name, format_duration(*duration), share)
+            })
+            .collect();
+        lines.push(format!("{:width$}  {:>9}", "Total", format_duration(total)));
+        lines
+    }
+
+    /// Print the breakdown on stderr, so it never mixes with command output
+    pub fn print(&self, theme: &Theme) {
+        if self.phases.is_empty() {
+            return;
+        }
+        eprintln!("\n{}", theme.info("⏱️ Timings:"));
+        for line in self.lines() {
+            eprintln!("  {}", line);
+        }
+    }
+}
+
+// Short durations in microseconds or milliseconds, long ones in seconds
+fn format_duration(duration: Duration) -> String {
+    if duration < Duration::from_millis(1) {
+        format!("{}µs", duration.as_micros())
+    } else if duration < Duration::from_secs(1) {
+        format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+    } else {
+        format!("{:.2}s", duration.as_secs_f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings_breakdown() {
+        let mut timings = Timings::default();
+        timings.add("Plugin matching", Duration::from_millis(250));
+        timings.add("LLM generation", Duration::from_millis(1000));
+        timings.add("Parsing", Duration::from_millis(250));
+        timings.add("LLM generation", Duration::from_millis(500));
+
+        assert_eq!(
+            timings.lines(),
+            [
+                "Plugin matching    250.0ms   12.5%",
+                "LLM generation       1.50s   75.0%",
+                "Parsing            250.0ms   12.5%",
+                "Total                2.00s",
+            ]
+        );
+        assert_eq!(Timings::default().lines(), ["Total        0µs"]);
+    }
+}
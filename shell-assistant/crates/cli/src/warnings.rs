@@ -0,0 +1,127 @@
+use executor::Theme;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+use tracing_subscriber::Registry;
+
+/// Prints the warnings the library crates log through `tracing`, such as LLM fallbacks.
+/// They go to stderr so they never mix with command or JSON output.
+struct WarningLayer {
+    theme: Theme,
+    max_level: Level,
+    // Identical messages and how often they were logged, when repeats are collapsed
+    repeats: Option<Arc<Mutex<HashMap<String, usize>>>>,
+}
+
+impl<S: Subscriber> Layer<S> for WarningLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let level = *event.metadata().level();
+        if level > self.max_level {
+            return;
+        }
+
+        let mut visitor = MessageVisitor(String::new());
+        event.record(&mut visitor);
+        let message = visitor.0;
+
+        if let Some(repeats) = &self.repeats {
+            let mut repeats = repeats.lock().unwrap_or_else(|e| e.into_inner());
+            let count = repeats.entry(message.clone()).or_insert(0);
+            *count += 1;
+            if *count > 1 {
+                return;
+            }
+        }
+
+        if level <= Level::WARN {
+            eprintln!("{} {}", self.theme.warning("⚠️"), self.theme.warning(&message));
+        } else {
+            eprintln!("{} {}", self.theme.info("ℹ️"), self.theme.info(&message));
+        }
+    }
+}
+
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = format!("{:?}", value);
+        }
+    }
+}
+
+/// Repeated warnings that were held back, reported once the session ends
+pub struct WarningLog {
+    repeats: Option<Arc<Mutex<HashMap<String, usize>>>>,
+    theme: Theme,
+}
+
+impl WarningLog {
+    /// Print each collapsed warning once more, with how often it was logged
+    pub fn summarize(&self) {
+        let Some(repeats) = &self.repeats else {
+            return;
+        };
+        let repeats = repeats.lock().unwrap_or_else(|e| e.into_inner());
+        let mut repeated: Vec<(&String, &usize)> =
+            repeats.iter().filter(|(_, count)| **count > 1).collect();
+        repeated.sort();
+        for (message, count) in repeated {
+            eprintln!(
+                "{} {} ({} times this session)",
+                self.theme.warning("⚠️"),
+                self.theme.warning(message),
+                count
+            );
+        }
+    }
+}
+
+/// Start printing library warnings (and info messages with `debug`). With `collapse`,
+/// an identical message is only printed the first time; `WarningLog::summarize` then
+/// reports the repeats.
+pub fn init(theme: &Theme, debug: bool, collapse: bool) -> WarningLog {
+    let repeats = collapse.then(|| Arc::new(Mutex::new(HashMap::new())));
+    let layer = WarningLayer {
+        theme: theme.clone(),
+        max_level: if debug { Level::INFO } else { Level::WARN },
+        repeats: repeats.clone(),
+    };
+    // Only fails if a subscriber was already set, in which case that one is used
+    let _ = tracing::subscriber::set_global_default(Registry::default().with(layer));
+
+    WarningLog { repeats, theme: theme.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_warnings_are_collapsed() {
+        let repeats = Arc::new(Mutex::new(HashMap::new()));
+        let layer = WarningLayer {
+            theme: Theme::plain(),
+            max_level: Level::WARN,
+            repeats: Some(repeats.clone()),
+        };
+
+        tracing::subscriber::with_default(Registry::default().with(layer), || {
+            for _ in 0..3 {
+                tracing::warn!("Ollama failed: connection refused. Falling back to LLM-rs...");
+            }
+            tracing::warn!("LLM-rs failed: model not found. No more fallbacks.");
+            // Below the level, so not counted
+            tracing::info!("LLM-rs succeeded after fallback.");
+        });
+
+        let repeats = repeats.lock().unwrap();
+        assert_eq!(repeats["Ollama failed: connection refused. Falling back to LLM-rs..."], 3);
+        assert_eq!(repeats["LLM-rs failed: model not found. No more fallbacks."], 1);
+        assert_eq!(repeats.len(), 2);
+    }
+}
@@ -0,0 +1,435 @@
+use crate::integrity::EntryCheck;
+use crate::safety::{SafetyCheckResult, SafetyLevel, SafetyReason};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Errors that can occur while reading or writing the audit log
+#[derive(Error, Debug)]
+pub enum AuditError {
+    #[error("Could not access audit log: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid audit entry: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("{user} ran {count} commands in the last hour (limit {limit}); try again later")]
+    RateLimited { user: String, count: usize, limit: u32 },
+}
+
+/// The window `AuditLogger::check_rate_limit` counts executions in
+pub const RATE_LIMIT_WINDOW_SECS: u64 = 60 * 60;
+
+/// A record of a command that was suggested to a user and what happened to it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Groups the entries of one working session, which may span several invocations
+    pub session_id: String,
+    pub user: String,
+    /// The natural language request
+    pub input: String,
+    pub command: String,
+    /// The plugin or LLM backend that produced the command
+    pub backend: String,
+    pub safety_level: SafetyLevel,
+    /// The safety rule that flagged the command, if any
+    #[serde(default)]
+    pub reason_code: Option<SafetyReason>,
+    /// Whether the command was actually run
+    pub executed: bool,
+    /// What happened to the command and why it was flagged, see `audit_notes`
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+/// The notes of an audit entry: the outcome (e.g. "Aborted"), then the safety decision
+/// with its reason, then whether the user overrode a warning by running the command anyway.
+/// For example `Aborted; Blocked: ... [EnterpriseBlocked]`.
+pub fn audit_notes(
+    safety: &SafetyCheckResult,
+    executed: bool,
+    outcome: Option<&str>,
+) -> Option<String> {
+    let mut notes: Vec<String> = outcome.map(str::to_string).into_iter().collect();
+
+    if let Some(reason) = &safety.reason {
+        match safety.reason_code {
+            Some(code) => notes.push(format!("{}: {} [{:?}]", safety.level, reason, code)),
+            None => notes.push(format!("{}: {}", safety.level, reason)),
+        }
+    }
+    if executed && safety.is_unsafe() {
+        notes.push(format!("Override: run after confirming the {} rating", safety.level));
+    }
+
+    (!notes.is_empty()).then(|| notes.join("; "))
+}
+
+/// The `(executed, outcome)` of a command handled in a dry run: it's never executed, and the
+/// outcome says it was a dry run, e.g. `Dry run; Copied to clipboard`
+pub fn dry_run_outcome(outcome: Option<&str>) -> (bool, Option<String>) {
+    let outcome = match outcome {
+        Some(outcome) => format!("Dry run; {}", outcome),
+        None => "Dry run".to_string(),
+    };
+    (false, Some(outcome))
+}
+
+/// A combined filter over audit entries. Every filter that is set must match;
+/// an empty query matches all entries.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuditQuery {
+    user: Option<String>,
+    safety_levels: Vec<SafetyLevel>,
+    time_range: Option<(u64, u64)>,
+    backend: Option<String>,
+    executed_only: bool,
+    command_contains: Option<String>,
+}
+
+impl AuditQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match entries of the given user
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Only match entries with one of the given safety levels
+    pub fn safety_levels(mut self, levels: impl IntoIterator<Item = SafetyLevel>) -> Self {
+        self.safety_levels = levels.into_iter().collect();
+        self
+    }
+
+    /// Only match entries with timestamps in `start..=end`
+    pub fn time_range(mut self, start: u64, end: u64) -> Self {
+        self.time_range = Some((start, end));
+        self
+    }
+
+    /// Only match entries produced by the given plugin or backend (case-insensitive)
+    pub fn backend(mut self, backend: impl Into<String>) -> Self {
+        self.backend = Some(backend.into());
+        self
+    }
+
+    /// Only match commands that were actually run
+    pub fn executed_only(mut self, executed_only: bool) -> Self {
+        self.executed_only = executed_only;
+        self
+    }
+
+    /// Only match commands containing the given text
+    pub fn command_contains(mut self, text: impl Into<String>) -> Self {
+        self.command_contains = Some(text.into());
+        self
+    }
+
+    /// Check whether an entry passes every filter of the query
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.user.as_ref().is_none_or(|user| &entry.user == user)
+            && (self.safety_levels.is_empty() || self.safety_levels.contains(&entry.safety_level))
+            && self.time_range.is_none_or(|(start, end)| (start..=end).contains(&entry.timestamp))
+            && self
+                .backend
+                .as_ref()
+                .is_none_or(|backend| entry.backend.eq_ignore_ascii_case(backend))
+            && (!self.executed_only || entry.executed)
+            && self
+                .command_contains
+                .as_ref()
+                .is_none_or(|text| entry.command.contains(text.as_str()))
+    }
+}
+
+/// Appends audit entries to a JSON Lines file and reads them back
+pub struct AuditLogger {
+    path: PathBuf,
+}
+
+impl AuditLogger {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        AuditLogger { path: path.into() }
+    }
+
+    /// Get the path of the audit log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append an entry, creating the log file (and its directory) if needed
+    pub fn log(&self, entry: &AuditEntry) -> Result<(), AuditError> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let line = serde_json::to_string(entry)?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        Ok(())
+    }
+
+    /// Read all entries, oldest first. A missing log file has no entries.
+    pub fn read_entries(&self) -> Result<Vec<AuditEntry>, AuditError> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let contents = fs::read_to_string(&self.path)?;
+        contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    /// Parse the log line by line without stopping at bad lines, for `IntegrityReport`. Each
+    /// non-empty line's number comes with its entry's timestamp, or why it couldn't be read.
+    /// Unlike `read_entries`, a missing log file is an error.
+    pub fn check_entries(&self) -> Result<Vec<EntryCheck>, AuditError> {
+        let contents = fs::read_to_string(&self.path)?;
+        Ok(contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                let entry = serde_json::from_str::<AuditEntry>(line);
+                (i + 1, entry.map(|entry| entry.timestamp).map_err(|e| e.to_string()))
+            })
+            .collect())
+    }
+
+    /// Get the entries matching every filter of `query`, oldest first
+    pub fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, AuditError> {
+        Ok(self.read_entries()?.into_iter().filter(|e| query.matches(e)).collect())
+    }
+
+    /// Get the entries of the given user
+    pub fn get_user_entries(&self, user: &str) -> Result<Vec<AuditEntry>, AuditError> {
+        Ok(self.read_entries()?.into_iter().filter(|e| e.user == user).collect())
+    }
+
+    /// Get the entries with the given safety level
+    pub fn get_entries_by_safety(&self, level: SafetyLevel) -> Result<Vec<AuditEntry>, AuditError> {
+        Ok(self.read_entries()?.into_iter().filter(|e| e.safety_level == level).collect())
+    }
+
+    /// Fails with `AuditError::RateLimited` once `user` has executed `limit` commands in the
+    /// hour before `now`. Only entries marked as executed count.
+    pub fn check_rate_limit(&self, user: &str, limit: u32, now: u64) -> Result<(), AuditError> {
+        let query = AuditQuery::new()
+            .user(user)
+            .executed_only(true)
+            .time_range(now.saturating_sub(RATE_LIMIT_WINDOW_SECS), now);
+        let count = self.query(&query)?.len();
+        if count >= limit as usize {
+            return Err(AuditError::RateLimited { user: user.to_string(), count, limit });
+        }
+        Ok(())
+    }
+
+    /// Get the entries with timestamps in `start..=end`
+    pub fn get_entries_in_range(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<AuditEntry>, AuditError> {
+        Ok(self
+            .read_entries()?
+            .into_iter()
+            .filter(|e| (start..=end).contains(&e.timestamp))
+            .collect())
+    }
+}
+
+/// Get the name of the current OS user, or `unknown`
+pub fn current_user() -> String {
+    std::env::var(if cfg!(windows) { "USERNAME" } else { "USER" })
+        .ok()
+        .filter(|user| !user.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(user: &str, timestamp: u64, safety_level: SafetyLevel) -> AuditEntry {
+        AuditEntry {
+            timestamp,
+            session_id: "abc".to_string(),
+            user: user.to_string(),
+            input: "list files".to_string(),
+            command: "ls".to_string(),
+            backend: "Ollama".to_string(),
+            safety_level,
+            reason_code: None,
+            executed: true,
+            notes: None,
+        }
+    }
+
+    #[test]
+    fn test_log_and_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let logger = AuditLogger::new(&path);
+        assert!(logger.read_entries().unwrap().is_empty());
+
+        logger.log(&entry("alice", 100, SafetyLevel::Safe)).unwrap();
+        logger.log(&entry("bob", 200, SafetyLevel::Warning)).unwrap();
+        logger.log(&entry("alice", 300, SafetyLevel::Warning)).unwrap();
+
+        assert_eq!(logger.read_entries().unwrap().len(), 3);
+        assert_eq!(logger.get_user_entries("alice").unwrap().len(), 2);
+        assert_eq!(logger.get_entries_by_safety(SafetyLevel::Warning).unwrap().len(), 2);
+        assert_eq!(logger.get_entries_in_range(150, 300).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_check_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let logger = AuditLogger::new(&path);
+        assert!(logger.check_entries().is_err());
+
+        logger.log(&entry("alice", 200, SafetyLevel::Safe)).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(file, "{{\"timestamp\": 300, \"user\": \"bob\"}}\n").unwrap();
+        logger.log(&entry("bob", 100, SafetyLevel::Warning)).unwrap();
+
+        let checks = logger.check_entries().unwrap();
+        let numbers: Vec<usize> = checks.iter().map(|(number, _)| *number).collect();
+        assert_eq!(numbers, [1, 2, 4]);
+        assert_eq!(checks[0].1, Ok(200));
+        assert!(checks[1].1.as_ref().unwrap_err().contains("missing field"));
+        assert_eq!(checks[2].1, Ok(100));
+    }
+
+    #[test]
+    fn test_combined_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let logger = AuditLogger::new(&path);
+
+        logger.log(&entry("alice", 100, SafetyLevel::Safe)).unwrap();
+        logger.log(&entry("bob", 200, SafetyLevel::Warning)).unwrap();
+        logger.log(&entry("alice", 300, SafetyLevel::Warning)).unwrap();
+        logger
+            .log(&AuditEntry { executed: false, ..entry("alice", 400, SafetyLevel::Dangerous) })
+            .unwrap();
+
+        let query = AuditQuery::new()
+            .user("alice")
+            .safety_levels([SafetyLevel::Warning, SafetyLevel::Dangerous]);
+        let timestamps: Vec<u64> =
+            logger.query(&query).unwrap().iter().map(|e| e.timestamp).collect();
+        assert_eq!(timestamps, vec![300, 400]);
+
+        let query = query.executed_only(true).backend("ollama");
+        assert_eq!(logger.query(&query).unwrap().len(), 1);
+
+        let query = AuditQuery::new().time_range(150, 350).command_contains("rm");
+        assert!(logger.query(&query).unwrap().is_empty());
+        assert_eq!(logger.query(&AuditQuery::new()).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let logger = AuditLogger::new(&path);
+        let now = 10_000;
+
+        // An old run, another user's run and a command that wasn't run don't count
+        logger.log(&entry("alice", now - RATE_LIMIT_WINDOW_SECS - 1, SafetyLevel::Safe)).unwrap();
+        logger.log(&entry("bob", now - 10, SafetyLevel::Safe)).unwrap();
+        logger
+            .log(&AuditEntry { executed: false, ..entry("alice", now - 5, SafetyLevel::Safe) })
+            .unwrap();
+        logger.log(&entry("alice", now - 60, SafetyLevel::Safe)).unwrap();
+        assert!(logger.check_rate_limit("alice", 2, now).is_ok());
+
+        logger.log(&entry("alice", now - 30, SafetyLevel::Safe)).unwrap();
+        assert!(matches!(
+            logger.check_rate_limit("alice", 2, now),
+            Err(AuditError::RateLimited { count: 2, limit: 2, .. })
+        ));
+        assert!(logger.check_rate_limit("bob", 2, now).is_ok());
+    }
+
+    #[test]
+    fn test_blocked_command_notes() {
+        use crate::safety::CommandSafetyChecker;
+
+        let checker =
+            CommandSafetyChecker::new().with_enterprise_lists(vec![], vec!["curl".into()]);
+        let safety = checker.check_command_detailed("curl example.com");
+        let reason = safety.reason.clone().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let logger = AuditLogger::new(&path);
+        logger
+            .log(&AuditEntry {
+                command: "curl example.com".to_string(),
+                safety_level: safety.level,
+                reason_code: safety.reason_code,
+                executed: false,
+                notes: audit_notes(&safety, false, Some("Aborted")),
+                ..entry("alice", 100, SafetyLevel::Safe)
+            })
+            .unwrap();
+
+        let notes = logger.read_entries().unwrap()[0].notes.clone().unwrap();
+        assert!(notes.starts_with("Aborted; Blocked: "));
+        assert!(notes.contains(&reason));
+        assert!(notes.contains("[EnterpriseBlocked]"));
+
+        // Running a flagged command records the override
+        let safety = checker.check_command_detailed("mv a.txt b.txt");
+        let notes = audit_notes(&safety, true, None).unwrap();
+        assert!(notes.ends_with("Override: run after confirming the Warning rating"), "{}", notes);
+        assert_eq!(audit_notes(&checker.check_command_detailed("ls"), true, None), None);
+    }
+
+    #[test]
+    fn test_dry_run_entry() {
+        use crate::safety::CommandSafetyChecker;
+
+        let safety = CommandSafetyChecker::new().check_command_detailed("mv a.txt b.txt");
+        let (executed, outcome) = dry_run_outcome(None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let logger = AuditLogger::new(&path);
+        logger
+            .log(&AuditEntry {
+                command: "mv a.txt b.txt".to_string(),
+                safety_level: safety.level,
+                reason_code: safety.reason_code,
+                executed,
+                notes: audit_notes(&safety, executed, outcome.as_deref()),
+                ..entry("alice", 100, SafetyLevel::Safe)
+            })
+            .unwrap();
+
+        let logged = &logger.read_entries().unwrap()[0];
+        assert!(!logged.executed);
+        assert_eq!(logged.safety_level, SafetyLevel::Warning);
+        let notes = logged.notes.clone().unwrap();
+        assert!(notes.starts_with("Dry run; Warning: "), "{}", notes);
+        // Nothing ran, so nothing was overridden
+        assert!(!notes.contains("Override"));
+
+        let (_, outcome) = dry_run_outcome(Some("Copied to clipboard"));
+        assert_eq!(outcome.as_deref(), Some("Dry run; Copied to clipboard"));
+    }
+}
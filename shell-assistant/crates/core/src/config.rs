@@ -0,0 +1,761 @@
+use crate::llm::{DEFAULT_TIMEOUT, OLLAMA_REQUEST_FIELDS, OPENAI_REQUEST_FIELDS};
+use crate::safety::{CommandSafetyChecker, SafetyLevel};
+use crate::shell::{Shell, TargetOs};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use thiserror::Error;
+
+/// The system-wide baseline config, overridden by the user and explicit configs
+#[cfg(not(windows))]
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/shell-assistant/config.yaml";
+/// The system-wide baseline config, overridden by the user and explicit configs
+#[cfg(windows)]
+pub const SYSTEM_CONFIG_PATH: &str = "C:\\ProgramData\\shell-assistant\\config.yaml";
+
+/// The longest `llm.persona` accepted, in characters. It's sent with every request, so a
+/// long one slows down generation and crowds out the request itself.
+pub const MAX_PERSONA_CHARS: usize = 1000;
+
+/// Errors that can occur while loading the configuration
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Could not read config file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid config file: {0}")]
+    Parse(#[from] serde_yaml::Error),
+
+    #[error("Could not serialize config: {0}")]
+    Serialize(serde_yaml::Error),
+
+    #[error("Could not determine the home directory (HOME/USERPROFILE is not set)")]
+    NoHomeDir,
+
+    #[error("Profile not found: {0}")]
+    ProfileNotFound(String),
+
+    #[error("Invalid config: {0}")]
+    Invalid(String),
+}
+
+/// LLM backends that can be named in the configuration
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub enum BackendKind {
+    #[serde(rename = "ollama")]
+    Ollama,
+    #[serde(rename = "llm-rs")]
+    LlmRs,
+    #[serde(rename = "openai")]
+    OpenAI,
+}
+
+impl BackendKind {
+    /// Returns true if this backend requires internet access
+    pub fn is_online(&self) -> bool {
+        matches!(self, BackendKind::OpenAI)
+    }
+}
+
+/// The user's home directory (`HOME`, or `USERPROFILE` on Windows), if it is set
+pub fn home_dir() -> Option<PathBuf> {
+    std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// LLM related settings
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct LLMConfig {
+    /// Backends to try in order until one succeeds, e.g. `["ollama", "llm-rs", "openai"]`.
+    /// When empty, the built-in fallback for the selected backend is used.
+    pub fallback_chain: Vec<BackendKind>,
+    /// How many requests batch mode sends at once. Raise it for hosted backends, but keep
+    /// it low for a local Ollama server, which serves requests one at a time.
+    pub max_concurrent_requests: usize,
+    /// Generate commands for `windows` or `unix` instead of this machine's OS. Commands
+    /// for another OS can be copied but not run.
+    pub target_os: Option<TargetOs>,
+    /// The shell commands are written for: `bash`, `zsh`, `fish`, `powershell` or `cmd`.
+    /// Detected from `SHELL` (`PSModulePath` on Windows) when unset.
+    pub shell: Option<Shell>,
+    /// Instructions placed before the built-in prompt, for house style or constraints such as
+    /// "prefer POSIX-portable commands" or "always use long flag names"
+    pub persona: Option<String>,
+    /// Shown while the fallback chain moves on to the next backend, with `{attempt}`, `{total}`
+    /// and `{provider}` filled in. Defaults to "Retrying ({attempt}/{total}) via {provider}…".
+    pub retry_message: Option<String>,
+    /// Prompts to use instead of the built-in one, keyed by model name prefix (e.g.
+    /// `wizardcoder` or `llama`), with `{user_input}`, `{os_type}` and `{context}` filled in
+    pub prompt_templates: HashMap<String, String>,
+    /// Sampling temperature sent to OpenAI, from 0 to 2. Lower values give more predictable
+    /// commands. When unset, the API's default is used.
+    pub temperature: Option<f32>,
+    /// Nucleus sampling sent to OpenAI, from 0 to 1. When unset, the API's default is used.
+    pub top_p: Option<f32>,
+    /// The most tokens OpenAI may generate for a reply. When unset, the API's default is used.
+    pub max_tokens: Option<u32>,
+    /// How many seconds to wait for Ollama or OpenAI to respond before giving up (30 by default)
+    pub timeout_secs: Option<u64>,
+    /// Send short requests to one backend and longer ones to another. Ignored when
+    /// `fallback_chain` is set.
+    pub routing: Option<RoutingPolicy>,
+    /// Settings for the Ollama backend
+    pub ollama: ProviderConfig,
+    /// Settings for the OpenAI backend
+    pub openai: ProviderConfig,
+}
+
+impl Default for LLMConfig {
+    fn default() -> Self {
+        Self {
+            fallback_chain: Vec::new(),
+            max_concurrent_requests: 1,
+            target_os: None,
+            shell: None,
+            persona: None,
+            retry_message: None,
+            prompt_templates: HashMap::new(),
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            timeout_secs: None,
+            routing: None,
+            ollama: ProviderConfig::default(),
+            openai: ProviderConfig::default(),
+        }
+    }
+}
+
+impl LLMConfig {
+    /// How long to wait for an HTTP backend to respond
+    pub fn timeout(&self) -> Duration {
+        self.timeout_secs.map_or(DEFAULT_TIMEOUT, Duration::from_secs)
+    }
+}
+
+/// Which backend handles a request, by how long it is. Short requests are usually simple
+/// enough for a fast local model, while longer ones benefit from a stronger model.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct RoutingPolicy {
+    /// Requests of at most this many words are short
+    pub max_short_words: usize,
+    /// The backend for short requests
+    pub short: BackendKind,
+    /// The backend for longer requests
+    pub long: BackendKind,
+}
+
+impl Default for RoutingPolicy {
+    fn default() -> Self {
+        Self { max_short_words: 12, short: BackendKind::Ollama, long: BackendKind::OpenAI }
+    }
+}
+
+impl RoutingPolicy {
+    /// The backend that should handle `input`
+    pub fn backend_for(&self, input: &str) -> BackendKind {
+        if input.split_whitespace().count() <= self.max_short_words {
+            self.short
+        } else {
+            self.long
+        }
+    }
+}
+
+/// Settings for one LLM backend
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ProviderConfig {
+    /// Fields added to every request body as they are, for parameters the assistant doesn't
+    /// model, e.g. Ollama's `options: {seed: 42}` or OpenAI's `presence_penalty: 0.5`.
+    /// Fields the assistant sets itself, such as `model` or `stream`, are rejected.
+    pub extra_options: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Privacy related settings
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct PrivacyConfig {
+    /// Never use online LLM providers
+    pub offline_only: bool,
+    /// Requests and commands matching any of these are never added to history, e.g.
+    /// `~/secrets`. A pattern matches anywhere in the text, with `*` for any characters.
+    pub history_exclude_patterns: Vec<String>,
+}
+
+impl PrivacyConfig {
+    /// `history_exclude_patterns` as matched against requests and commands. A pattern that
+    /// starts a path with `~/`, `$HOME/` or `${HOME}/` matches any of these spellings, and the
+    /// path under `home` itself
+    pub fn exclude_patterns_for_home(&self, home: Option<&Path>) -> Vec<String> {
+        const HOME_PREFIXES: [&str; 3] = ["${HOME}/", "$HOME/", "~/"];
+        let home = home.map(|home| format!("{}{}", home.display(), std::path::MAIN_SEPARATOR));
+
+        let mut patterns = Vec::new();
+        for pattern in &self.history_exclude_patterns {
+            let pattern = HOME_PREFIXES
+                .iter()
+                .fold(pattern.clone(), |pattern, prefix| pattern.replace(prefix, "~/"));
+            if !pattern.contains("~/") {
+                patterns.push(pattern);
+                continue;
+            }
+            for prefix in HOME_PREFIXES.iter().copied().chain(home.as_deref()) {
+                patterns.push(pattern.replace("~/", prefix));
+            }
+        }
+        patterns
+    }
+}
+
+/// Command execution policy
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct SecurityConfig {
+    /// When non-empty, only commands starting with one of these may run
+    pub allowed_commands: Vec<String>,
+    /// Commands that are always refused
+    pub blocked_commands: Vec<String>,
+    /// Ask the model to avoid `sudo` and other privilege escalation unless it's required
+    pub prefer_no_sudo: bool,
+    /// Refuse commands rated at or above this level outright, even with `--force`
+    pub block_at_level: Option<SafetyLevel>,
+    /// How many commands each user may run per hour, counted from the audit log. Setting it
+    /// turns on audit logging.
+    pub max_commands_per_hour: Option<u32>,
+    /// Only show and explain commands, never run or copy them (for demos and training)
+    pub read_only: bool,
+}
+
+/// Audit log settings
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Record every suggested command and what happened to it
+    pub enabled: bool,
+    /// Where to write the audit log (default: `~/.shell-assistant/audit.log`)
+    pub log_path: Option<String>,
+}
+
+/// How requests are rewritten before plugins and the LLM see them
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct InputConfig {
+    /// Words to expand, e.g. `k8s: kubernetes`. Matched as whole words, ignoring case.
+    pub abbreviations: HashMap<String, String>,
+}
+
+/// How suggested commands are shown
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct DisplayConfig {
+    /// How many lines of a long command, wrapped to the terminal width, are shown before
+    /// confirming (default: 10). The rest can be viewed from the action menu.
+    pub max_command_lines: Option<usize>,
+}
+
+/// Output colors: a preset (`dark` or `light`) plus optional per-role overrides
+/// using color names such as `green` or `bright blue`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub preset: Option<String>,
+    pub success: Option<String>,
+    pub warning: Option<String>,
+    pub error: Option<String>,
+    pub command: Option<String>,
+    pub explanation: Option<String>,
+    pub info: Option<String>,
+}
+
+/// Configuration loaded from `config.yaml`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct EnterpriseConfig {
+    pub llm: LLMConfig,
+    pub security: SecurityConfig,
+    pub privacy: PrivacyConfig,
+    pub theme: ThemeConfig,
+    pub audit: AuditConfig,
+    pub input: InputConfig,
+    pub display: DisplayConfig,
+}
+
+impl EnterpriseConfig {
+    /// Load the configuration from a YAML file
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path)?;
+        Self::from_yaml(&contents)
+    }
+
+    /// Parse the configuration from a YAML string
+    pub fn from_yaml(contents: &str) -> Result<Self, ConfigError> {
+        serde_yaml::from_str::<Self>(contents)?.validate()
+    }
+
+    // Reject values that parse but can't be used
+    fn validate(self) -> Result<Self, ConfigError> {
+        if let Some(persona) = &self.llm.persona {
+            let chars = persona.chars().count();
+            if chars > MAX_PERSONA_CHARS {
+                return Err(ConfigError::Invalid(format!(
+                    "llm.persona is {} characters long; the limit is {}",
+                    chars, MAX_PERSONA_CHARS
+                )));
+            }
+        }
+        if self.llm.timeout_secs == Some(0) {
+            return Err(ConfigError::Invalid("llm.timeout_secs must be at least 1".to_string()));
+        }
+        let providers = [
+            ("ollama", &self.llm.ollama, OLLAMA_REQUEST_FIELDS),
+            ("openai", &self.llm.openai, OPENAI_REQUEST_FIELDS),
+        ];
+        for (name, provider, reserved) in providers {
+            if let Some(key) =
+                provider.extra_options.keys().find(|key| reserved.contains(&key.as_str()))
+            {
+                return Err(ConfigError::Invalid(format!(
+                    "llm.{}.extra_options can't set '{}', which the assistant sets itself",
+                    name, key
+                )));
+            }
+        }
+        let matches_everything =
+            |pattern: &String| pattern.chars().all(|c| c == '*' || c.is_whitespace());
+        if self.privacy.history_exclude_patterns.iter().any(matches_everything) {
+            return Err(ConfigError::Invalid(
+                "privacy.history_exclude_patterns can't have an empty or `*`-only pattern, \
+                 which would exclude everything"
+                    .to_string(),
+            ));
+        }
+        let ranges = [("temperature", self.llm.temperature, 2.0), ("top_p", self.llm.top_p, 1.0)];
+        for (name, value, max) in ranges {
+            if let Some(value) = value.filter(|value| !(0.0..=max).contains(value)) {
+                return Err(ConfigError::Invalid(format!(
+                    "llm.{} is {}; it must be between 0 and {}",
+                    name, value, max
+                )));
+            }
+        }
+        Ok(self)
+    }
+
+    /// Write the configuration to a YAML file, creating its directory if needed
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let contents = serde_yaml::to_string(self).map_err(ConfigError::Serialize)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Get the JSON Schema of the configuration file, for editors and config linters
+    #[cfg(feature = "config-schema")]
+    pub fn json_schema() -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(&schemars::schema_for!(EnterpriseConfig))
+    }
+
+    /// Create a safety checker that applies this config's command lists and block level
+    pub fn safety_checker(&self) -> CommandSafetyChecker {
+        CommandSafetyChecker::new()
+            .with_enterprise_lists(
+                self.security.allowed_commands.clone(),
+                self.security.blocked_commands.clone(),
+            )
+            .with_block_at_level(self.security.block_at_level)
+    }
+
+    /// Get the application directory (`~/.shell-assistant`)
+    pub fn app_dir() -> Result<PathBuf, ConfigError> {
+        Self::app_dir_in(home_dir().as_deref())
+    }
+
+    /// Get the application directory, falling back to a directory under the
+    /// system temp dir when the home directory can't be determined
+    pub fn app_dir_or_fallback() -> PathBuf {
+        Self::app_dir_or_fallback_in(home_dir().as_deref())
+    }
+
+    // The application directory under `home`
+    fn app_dir_in(home: Option<&Path>) -> Result<PathBuf, ConfigError> {
+        Ok(home.ok_or(ConfigError::NoHomeDir)?.join(".shell-assistant"))
+    }
+
+    fn app_dir_or_fallback_in(home: Option<&Path>) -> PathBuf {
+        Self::app_dir_in(home).unwrap_or_else(|_| std::env::temp_dir().join("shell-assistant"))
+    }
+
+    /// Get the default config file path (`~/.shell-assistant/config.yaml`)
+    pub fn default_path() -> Result<PathBuf, ConfigError> {
+        Ok(Self::app_dir()?.join("config.yaml"))
+    }
+
+    /// Get the history file path, degrading to the fallback app dir without a home directory
+    pub fn get_history_path(&self) -> PathBuf {
+        Self::app_dir_or_fallback().join("history.json")
+    }
+
+    /// Get the audit log path, degrading to the fallback app dir without a home directory
+    pub fn get_audit_log_path(&self) -> PathBuf {
+        match &self.audit.log_path {
+            Some(path) => PathBuf::from(path),
+            None => Self::app_dir_or_fallback().join("audit.log"),
+        }
+    }
+
+    /// Get the path of the cache of LLM-generated plugin command explanations
+    pub fn get_explanation_cache_path(&self) -> PathBuf {
+        Self::app_dir_or_fallback().join("explanations.json")
+    }
+
+    /// Get the path of the file holding the user's favorite commands
+    pub fn get_favorites_path(&self) -> PathBuf {
+        Self::app_dir_or_fallback().join("favorites.json")
+    }
+
+    /// Get the path of the file holding the last command that failed, for `--last-error`
+    pub fn get_last_error_path(&self) -> PathBuf {
+        Self::app_dir_or_fallback().join("last_error.json")
+    }
+
+    /// Get the path of the file that keeps the current session between invocations
+    pub fn get_session_path(&self) -> PathBuf {
+        Self::app_dir_or_fallback().join("session")
+    }
+
+    /// Get the directory holding named profiles (`~/.shell-assistant/profiles`)
+    pub fn profiles_dir() -> Result<PathBuf, ConfigError> {
+        Ok(Self::app_dir()?.join("profiles"))
+    }
+
+    /// Get the directory holding declarative plugins (`~/.shell-assistant/plugins`)
+    pub fn plugins_dir() -> Result<PathBuf, ConfigError> {
+        Ok(Self::app_dir()?.join("plugins"))
+    }
+
+    /// Load the named profile from `~/.shell-assistant/profiles/<name>.yaml`
+    pub fn load_profile(name: &str) -> Result<Self, ConfigError> {
+        Self::load_profile_from(&Self::profiles_dir()?, name)
+    }
+
+    /// Load the named profile from the given profiles directory
+    pub fn load_profile_from(dir: &Path, name: &str) -> Result<Self, ConfigError> {
+        Self::load(&Self::profile_path_in(dir, name)?)
+    }
+
+    /// Get the path of the named profile, which must exist
+    pub fn profile_path(name: &str) -> Result<PathBuf, ConfigError> {
+        Self::profile_path_in(&Self::profiles_dir()?, name)
+    }
+
+    /// Get the path of the named profile in the given profiles directory, which must exist
+    pub fn profile_path_in(dir: &Path, name: &str) -> Result<PathBuf, ConfigError> {
+        // Profile names must not escape the profiles directory
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(ConfigError::ProfileNotFound(name.to_string()));
+        }
+
+        let path = dir.join(format!("{}.yaml", name));
+        if !path.is_file() {
+            return Err(ConfigError::ProfileNotFound(name.to_string()));
+        }
+
+        Ok(path)
+    }
+
+    /// List the names of the available profiles, sorted
+    pub fn list_profiles() -> Result<Vec<String>, ConfigError> {
+        Self::list_profiles_in(&Self::profiles_dir()?)
+    }
+
+    /// List the names of the profiles in the given directory, sorted
+    pub fn list_profiles_in(dir: &Path) -> Result<Vec<String>, ConfigError> {
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut names = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "yaml") {
+                if let Some(stem) = path.file_stem() {
+                    names.push(stem.to_string_lossy().into_owned());
+                }
+            }
+        }
+
+        names.sort();
+        Ok(names)
+    }
+
+    /// Load the system config, then the user config, then `explicit` (if given),
+    /// with later layers overriding individual fields of earlier ones. Missing
+    /// system and user configs are skipped; a missing explicit config is an error.
+    pub fn load_layered(explicit: Option<PathBuf>) -> Result<Self, ConfigError> {
+        let mut layers = vec![PathBuf::from(SYSTEM_CONFIG_PATH)];
+        if let Ok(path) = Self::default_path() {
+            layers.push(path);
+        }
+        layers.retain(|path| path.is_file());
+
+        if let Some(path) = explicit {
+            layers.push(path);
+        }
+
+        Self::load_layers(&layers)
+    }
+
+    /// Load and deep-merge the given config files in order
+    pub fn load_layers(paths: &[PathBuf]) -> Result<Self, ConfigError> {
+        let mut merged = serde_yaml::Value::Null;
+        for path in paths {
+            let layer: serde_yaml::Value = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+            merge_yaml(&mut merged, layer);
+        }
+
+        if merged.is_null() {
+            return Ok(Self::default());
+        }
+        serde_yaml::from_value::<Self>(merged)?.validate()
+    }
+
+    /// Load the config from `path` if given, otherwise from the default path.
+    /// A missing default config file (or home directory) is not an error and
+    /// yields the defaults.
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self, ConfigError> {
+        if let Some(path) = path {
+            return Self::load(path);
+        }
+
+        match Self::default_path() {
+            Ok(path) if path.exists() => Self::load(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+}
+
+// Merge `layer` into `base`: mappings are merged key by key, while any other value
+// (including lists) replaces the base value. Null values leave the base untouched.
+fn merge_yaml(base: &mut serde_yaml::Value, layer: serde_yaml::Value) {
+    use serde_yaml::Value;
+
+    match (base, layer) {
+        (_, Value::Null) => {}
+        (Value::Mapping(base), Value::Mapping(layer)) => {
+            for (key, value) in layer {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_yaml(existing, value),
+                    None => {
+                        if !value.is_null() {
+                            base.insert(key, value);
+                        }
+                    }
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_routing_thresholds() {
+        let config = EnterpriseConfig::from_yaml(
+            "llm:\n  routing:\n    max_short_words: 3\n    short: llm-rs\n",
+        )
+        .unwrap();
+        let routing = config.llm.routing.unwrap();
+        assert_eq!(routing.long, BackendKind::OpenAI);
+
+        assert_eq!(routing.backend_for("list files"), BackendKind::LlmRs);
+        assert_eq!(routing.backend_for("  show   disk usage "), BackendKind::LlmRs);
+        assert_eq!(routing.backend_for("find large log files"), BackendKind::OpenAI);
+        assert_eq!(routing.backend_for(""), BackendKind::LlmRs);
+
+        let routing = RoutingPolicy::default();
+        let twelve = "a ".repeat(12);
+        assert_eq!(routing.backend_for(&twelve), BackendKind::Ollama);
+        assert_eq!(routing.backend_for(&format!("{} b", twelve)), BackendKind::OpenAI);
+        assert_eq!(EnterpriseConfig::default().llm.routing, None);
+    }
+
+    #[test]
+    fn test_parse_fallback_chain() {
+        let config = EnterpriseConfig::from_yaml(
+            "llm:\n  fallback_chain: [\"ollama\", \"llm-rs\", \"openai\"]\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.llm.fallback_chain,
+            vec![BackendKind::Ollama, BackendKind::LlmRs, BackendKind::OpenAI]
+        );
+        assert_eq!(config.llm.max_concurrent_requests, 1);
+        assert!(!config.privacy.offline_only);
+    }
+
+    #[test]
+    fn test_no_home_dir_degrades() {
+        assert!(matches!(EnterpriseConfig::app_dir_in(None), Err(ConfigError::NoHomeDir)));
+        assert!(EnterpriseConfig::app_dir_or_fallback_in(None).starts_with(std::env::temp_dir()));
+
+        let home = Path::new("/home/alice");
+        assert_eq!(
+            EnterpriseConfig::app_dir_or_fallback_in(Some(home)),
+            home.join(".shell-assistant")
+        );
+    }
+
+    #[test]
+    fn test_profiles() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("work.yaml"), "privacy:\n  offline_only: true\n").unwrap();
+        fs::write(dir.join("personal.yaml"), "llm:\n  fallback_chain: [\"openai\"]\n").unwrap();
+        fs::write(dir.join("notes.txt"), "not a profile").unwrap();
+
+        assert_eq!(EnterpriseConfig::list_profiles_in(dir).unwrap(), vec!["personal", "work"]);
+
+        let work = EnterpriseConfig::load_profile_from(dir, "work").unwrap();
+        assert!(work.privacy.offline_only);
+        let personal = EnterpriseConfig::load_profile_from(dir, "personal").unwrap();
+        assert_eq!(personal.llm.fallback_chain, vec![BackendKind::OpenAI]);
+
+        for name in ["missing", "../work", ""] {
+            assert!(matches!(
+                EnterpriseConfig::load_profile_from(dir, name),
+                Err(ConfigError::ProfileNotFound(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_save_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yaml");
+        let mut config = EnterpriseConfig::default();
+        config.llm.fallback_chain = vec![BackendKind::Ollama];
+        config.privacy.offline_only = true;
+        config.save(&path).unwrap();
+
+        let loaded = EnterpriseConfig::load(&path).unwrap();
+        assert_eq!(loaded.llm.fallback_chain, vec![BackendKind::Ollama]);
+        assert!(loaded.privacy.offline_only);
+    }
+
+    #[test]
+    fn test_unknown_backend_is_rejected() {
+        assert!(EnterpriseConfig::from_yaml("llm:\n  fallback_chain: [\"bard\"]\n").is_err());
+    }
+
+    #[test]
+    fn test_persona_length_is_limited() {
+        let config =
+            EnterpriseConfig::from_yaml("llm:\n  persona: Prefer POSIX-portable commands\n");
+        assert_eq!(config.unwrap().llm.persona.as_deref(), Some("Prefer POSIX-portable commands"));
+
+        let yaml = format!("llm:\n  persona: {}\n", "x".repeat(MAX_PERSONA_CHARS + 1));
+        assert!(matches!(EnterpriseConfig::from_yaml(&yaml), Err(ConfigError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_extra_options_cant_set_request_fields() {
+        let yaml = "llm:\n  ollama:\n    extra_options:\n      seed: 42\n";
+        assert!(EnterpriseConfig::from_yaml(yaml).is_ok());
+
+        for yaml in [
+            "llm:\n  ollama:\n    extra_options:\n      prompt: hi\n",
+            "llm:\n  openai:\n    extra_options:\n      temperature: 0.1\n",
+        ] {
+            assert!(matches!(EnterpriseConfig::from_yaml(yaml), Err(ConfigError::Invalid(_))));
+        }
+    }
+
+    #[test]
+    fn test_history_exclude_patterns() {
+        for pattern in ["\"\"", "\"*\"", "\" ** \""] {
+            let yaml = format!("privacy:\n  history_exclude_patterns: [{}]\n", pattern);
+            assert!(matches!(EnterpriseConfig::from_yaml(&yaml), Err(ConfigError::Invalid(_))));
+        }
+
+        let privacy = PrivacyConfig {
+            history_exclude_patterns: vec!["$HOME/secrets".to_string(), "vault * read".to_string()],
+            ..PrivacyConfig::default()
+        };
+        let home = Path::new("/home/alice");
+        let patterns = privacy.exclude_patterns_for_home(Some(home));
+        for pattern in ["~/secrets", "$HOME/secrets", "${HOME}/secrets", "vault * read"] {
+            assert!(patterns.iter().any(|p| p == pattern), "{} missing", pattern);
+        }
+        let expanded = format!("{}{}secrets", home.display(), std::path::MAIN_SEPARATOR);
+        assert!(patterns.contains(&expanded));
+
+        assert_eq!(privacy.exclude_patterns_for_home(None).len(), 4);
+    }
+
+    #[test]
+    fn test_layered_override() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let system = dir.join("system.yaml");
+        let user = dir.join("user.yaml");
+        let explicit = dir.join("explicit.yaml");
+        fs::write(
+            &system,
+            "security:\n  allowed_commands: [\"ls\"]\n  blocked_commands: [\"curl\"]\nprivacy:\n  offline_only: true\n",
+        )
+        .unwrap();
+        fs::write(
+            &user,
+            "security:\n  allowed_commands: [\"ls\", \"git\"]\ntheme:\n  preset: light\n",
+        )
+        .unwrap();
+        fs::write(&explicit, "theme:\n  error: magenta\nprivacy: ~\n").unwrap();
+
+        let config = EnterpriseConfig::load_layers(&[system, user, explicit.clone()]).unwrap();
+        // Lists from a later layer replace earlier ones, untouched fields are kept
+        assert_eq!(config.security.allowed_commands, vec!["ls", "git"]);
+        assert_eq!(config.security.blocked_commands, vec!["curl"]);
+        assert!(config.privacy.offline_only);
+        // Nested fields are merged individually
+        assert_eq!(config.theme.preset.as_deref(), Some("light"));
+        assert_eq!(config.theme.error.as_deref(), Some("magenta"));
+
+        assert!(EnterpriseConfig::load_layers(&[dir.join("missing.yaml")]).is_err());
+        assert!(!EnterpriseConfig::load_layers(&[]).unwrap().privacy.offline_only);
+        assert!(EnterpriseConfig::load_layered(Some(dir.join("missing.yaml"))).is_err());
+    }
+
+    #[cfg(feature = "config-schema")]
+    #[test]
+    fn test_json_schema() {
+        let schema: serde_json::Value =
+            serde_json::from_str(&EnterpriseConfig::json_schema().unwrap()).unwrap();
+        let properties = &schema["properties"];
+        for section in ["llm", "security", "privacy", "theme", "audit"] {
+            assert!(properties.get(section).is_some(), "{}", section);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+/// A file entry's number with its timestamp, or why it couldn't be read
+pub type EntryCheck = (usize, Result<u64, String>);
+
+/// What checking a history or audit file entry by entry found. Entries are numbered from 1
+/// by their position in the file, or by line in JSON Lines files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IntegrityReport {
+    /// How many entries could be read
+    pub valid: usize,
+    /// The entries that couldn't be read, with why
+    pub malformed: Vec<(usize, String)>,
+    /// The oldest timestamp of the valid entries
+    pub earliest: Option<u64>,
+    /// The newest timestamp of the valid entries
+    pub latest: Option<u64>,
+    /// Entries older than the valid entry before them. Entries are appended as they happen,
+    /// so this points at edits or files stitched together.
+    pub out_of_order: Vec<usize>,
+}
+
+impl IntegrityReport {
+    /// Summarize the checks of a file's entries, in file order: each entry's number with its
+    /// timestamp, or why it couldn't be read
+    pub fn from_checks(checks: impl IntoIterator<Item = EntryCheck>) -> Self {
+        let mut report = IntegrityReport::default();
+        let mut previous = None;
+
+        for (number, check) in checks {
+            let timestamp = match check {
+                Ok(timestamp) => timestamp,
+                Err(reason) => {
+                    report.malformed.push((number, reason));
+                    continue;
+                }
+            };
+
+            report.valid += 1;
+            report.earliest = Some(report.earliest.map_or(timestamp, |t| t.min(timestamp)));
+            report.latest = Some(report.latest.map_or(timestamp, |t| t.max(timestamp)));
+            if previous.is_some_and(|previous| timestamp < previous) {
+                report.out_of_order.push(number);
+            }
+            previous = Some(timestamp);
+        }
+        report
+    }
+
+    /// Whether every entry could be read and they are in order
+    pub fn is_clean(&self) -> bool {
+        self.malformed.is_empty() && self.out_of_order.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_from_checks() {
+        let report = IntegrityReport::from_checks([
+            (1, Ok(200)),
+            (2, Err("missing field `user`".to_string())),
+            (3, Ok(100)),
+            (4, Ok(300)),
+            (6, Ok(250)),
+        ]);
+
+        assert_eq!(report.valid, 4);
+        assert_eq!(report.malformed, [(2, "missing field `user`".to_string())]);
+        assert_eq!((report.earliest, report.latest), (Some(100), Some(300)));
+        assert_eq!(report.out_of_order, [3, 6]);
+        assert!(!report.is_clean());
+
+        let report = IntegrityReport::from_checks([(1, Ok(100)), (2, Ok(100))]);
+        assert!(report.is_clean());
+        assert_eq!(IntegrityReport::from_checks([]), IntegrityReport::default());
+    }
+}
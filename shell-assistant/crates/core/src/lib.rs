@@ -1,9 +1,41 @@
+pub mod audit;
+pub mod config;
+pub mod integrity;
 pub mod llm;
+pub mod os;
 pub mod parser;
+pub mod preprocess;
 pub mod prompt;
 pub mod safety;
+pub mod session;
+pub mod shell;
 
-pub use llm::{LLMEngine, LLMError, LLMProvider};
-pub use parser::{generate_command, mock_llm_call, parse_response, LLMResponse};
-pub use prompt::construct_prompt;
-pub use safety::CommandSafetyChecker;
+pub use audit::{audit_notes, dry_run_outcome, AuditEntry, AuditError, AuditLogger, AuditQuery};
+pub use config::{home_dir, EnterpriseConfig};
+pub use integrity::{EntryCheck, IntegrityReport};
+pub use llm::{
+    ChainAttempt, InterceptingProvider, LLMEngine, LLMError, LLMProvider, RequestInterceptor,
+    DEFAULT_RETRY_MESSAGE,
+};
+pub use os::{detect_os_version, OsFamily, OsInfo};
+pub use parser::{
+    generate_command, generate_command_with_chain, generate_commands, mock_llm_call,
+    parse_response, parse_response_with_options, strip_trailing_comment, LLMResponse, ParseOptions,
+};
+pub use preprocess::{preprocess_input, AbbreviationExpander, InputPreprocessor};
+pub use prompt::{
+    construct_explanation_prompt, construct_prompt, construct_prompt_for_model,
+    construct_prompt_with_context, template_for_model, AttachedFile, AttachmentError, Correction,
+    FailedAttempt, PromptContext,
+};
+pub use safety::{
+    explain_risk, CommandSafetyChecker, SafetyCheckResult, SafetyLevel, SafetyReason,
+};
+pub use session::Session;
+pub use shell::{apparent_shell, escape_control_chars, shell_quote, Shell, ShellKind, TargetOs};
+
+// This crate shadows the built-in `core` for its dependents (and for its own
+// test harness), so re-export std for derive/attribute macros that expand to
+// `::core::...` paths (clap, tokio, thiserror).
+#[doc(hidden)]
+pub use std::*;
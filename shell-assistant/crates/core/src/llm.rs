@@ -2,9 +2,11 @@ use async_trait::async_trait;
 #[cfg(feature = "llm-rs")]
 use llama_cpp;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::env;
-#[cfg(feature = "llm-rs")]
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 use thiserror::Error;
 
 // Define error types for LLM operations
@@ -13,6 +15,9 @@ pub enum LLMError {
     #[error("Network error: {0}")]
     NetworkError(#[from] reqwest::Error),
 
+    #[error("Ollama doesn't appear to be running at {0}. Start it with `ollama serve` or install from https://ollama.com.")]
+    OllamaNotRunning(String),
+
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
 
@@ -28,16 +33,73 @@ pub enum LLMError {
     #[error("Rate limit exceeded")]
     RateLimitExceeded,
 
+    #[error("No response within {} seconds", .0.as_secs())]
+    Timeout(Duration),
+
+    #[error("Recording error: {0}")]
+    RecordingError(String),
+
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
 
+/// Receives the pieces of a streamed response as they arrive
+pub type OnFragment<'a> = dyn FnMut(&str) + Send + 'a;
+
+/// How long HTTP backends wait for a response when no `llm.timeout_secs` is configured.
+/// A streamed response may take longer in total: the timeout applies to connecting, to the
+/// response starting and to each wait for its next piece.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The default `llm.retry_message`
+pub const DEFAULT_RETRY_MESSAGE: &str = "Retrying ({attempt}/{total}) via {provider}…";
+
+/// One provider of a chain being tried, reported before its request is sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainAttempt {
+    /// Starts at 1
+    pub number: usize,
+    pub total: usize,
+    pub provider: String,
+}
+
+impl ChainAttempt {
+    /// Whether an earlier provider has already failed
+    pub fn is_retry(&self) -> bool {
+        self.number > 1
+    }
+
+    /// Fill in the `{attempt}`, `{total}` and `{provider}` placeholders of a message such as
+    /// `DEFAULT_RETRY_MESSAGE`
+    pub fn describe(&self, template: &str) -> String {
+        template
+            .replace("{attempt}", &self.number.to_string())
+            .replace("{total}", &self.total.to_string())
+            .replace("{provider}", &self.provider)
+    }
+}
+
+/// Receives each attempt of a provider chain as it starts
+pub type OnAttempt<'a> = dyn FnMut(&ChainAttempt) + Send + 'a;
+
 // Define a generic trait for LLM engines
 #[async_trait]
 pub trait LLMEngine: Send + Sync {
     async fn generate(&self, prompt: &str) -> Result<String, LLMError>;
     fn name(&self) -> &str;
 
+    /// Generate a response, passing each piece of text to `on_fragment` as it arrives, and
+    /// return the whole response. Engines that can't stream pass it all at once.
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        on_fragment: &mut OnFragment<'_>,
+    ) -> Result<String, LLMError> {
+        let response = self.generate(prompt).await?;
+        on_fragment(&response);
+        Ok(response)
+    }
+
     /// Returns true if this LLM requires internet access
     fn is_online(&self) -> bool {
         false // Default implementation assumes local model
@@ -48,6 +110,9 @@ pub trait LLMEngine: Send + Sync {
 pub struct OllamaProvider {
     api_url: String,
     model: String,
+    last_usage: Mutex<Option<OllamaUsage>>,
+    extra_options: Map<String, Value>,
+    timeout: Duration,
 }
 
 impl OllamaProvider {
@@ -55,37 +120,400 @@ impl OllamaProvider {
         Self {
             api_url: "http://localhost:11434/api/generate".to_string(),
             model: model.to_string(),
+            last_usage: Mutex::new(None),
+            extra_options: Map::new(),
+            timeout: DEFAULT_TIMEOUT,
         }
     }
+
+    /// Give up on a request to the server after this long, instead of `DEFAULT_TIMEOUT`.
+    /// Model downloads aren't limited.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Add these fields to every generate request, for parameters this crate doesn't model
+    /// (e.g. `options: {seed: 42}`)
+    pub fn with_extra_options(mut self, extra_options: Map<String, Value>) -> Self {
+        self.extra_options = extra_options;
+        self
+    }
+
+    /// Timing and token counts reported by Ollama for the last completed generation
+    pub fn last_usage(&self) -> Option<OllamaUsage> {
+        self.last_usage.lock().ok().and_then(|usage| *usage)
+    }
+
+    /// The model this provider generates with
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// List the models installed on the Ollama server, e.g. `codellama:latest`
+    pub async fn list_models(&self) -> Result<Vec<String>, LLMError> {
+        let response = http_client(self.timeout, false)?
+            .get(self.endpoint("tags"))
+            .send()
+            .await
+            .map_err(|e| ollama_generate_error(e, self.timeout))?
+            .error_for_status()?;
+        let tags: OllamaTags = response.json().await?;
+        Ok(tags.models.into_iter().map(|model| model.name).collect())
+    }
+
+    /// Check whether this provider's model is installed on the Ollama server
+    pub async fn has_model(&self) -> Result<bool, LLMError> {
+        Ok(self.list_models().await?.iter().any(|name| is_same_model(name, &self.model)))
+    }
+
+    /// Download this provider's model from the Ollama library. This can take several minutes.
+    pub async fn pull_model(&self) -> Result<(), LLMError> {
+        let request = serde_json::json!({ "name": self.model, "stream": false });
+        reqwest::Client::new()
+            .post(self.endpoint("pull"))
+            .json(&request)
+            .send()
+            .await
+            .map_err(ollama_request_error)?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    // The URL of another Ollama API endpoint on the same server
+    fn endpoint(&self, name: &str) -> String {
+        let base = self.api_url.strip_suffix("generate").unwrap_or(&self.api_url);
+        format!("{}{}", base, name)
+    }
+
+    // The generate request for a prompt, with the configured extra fields
+    fn request<'a>(&'a self, prompt: &'a str, stream: bool) -> OllamaRequest<'a> {
+        OllamaRequest { model: &self.model, prompt, stream, extra_options: &self.extra_options }
+    }
+}
+
+// Ollama reports installed models with a tag (`codellama:latest`), while requests
+// may leave it out, meaning `latest`
+fn is_same_model(installed: &str, requested: &str) -> bool {
+    let with_tag = |name: &str| {
+        if name.contains(':') {
+            name.to_string()
+        } else {
+            format!("{}:latest", name)
+        }
+    };
+    with_tag(installed) == with_tag(requested)
 }
 
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct OllamaTags {
+    models: Vec<OllamaModel>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct OllamaModel {
+    name: String,
+}
+
+/// Timing and token counts Ollama reports in its final (`done`) frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OllamaUsage {
+    /// Total time spent generating the response
+    pub total_duration: Option<std::time::Duration>,
+    /// Number of tokens in the prompt
+    pub prompt_eval_count: Option<u64>,
+    /// Number of tokens in the response
+    pub eval_count: Option<u64>,
+}
+
+/// Request body fields the Ollama provider sets itself, which `extra_options` can't set
+pub const OLLAMA_REQUEST_FIELDS: &[&str] = &["model", "prompt", "stream"];
+
 #[derive(Serialize)]
 struct OllamaRequest<'a> {
     model: &'a str,
     prompt: &'a str,
     stream: bool,
+    #[serde(flatten)]
+    extra_options: &'a Map<String, Value>,
 }
 
-#[derive(Deserialize)]
+// A single response frame. Non-streaming responses are one frame with `done: true`;
+// streaming responses end with a frame that has an empty `response` and `done: true`.
+// Failures, such as a model that isn't installed, are a frame with only `error`.
+// Unknown fields (e.g. `context`) are ignored.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
 struct OllamaResponse {
     response: String,
+    done: bool,
+    error: Option<String>,
+    /// Nanoseconds
+    total_duration: Option<u64>,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+impl OllamaResponse {
+    fn usage(&self) -> OllamaUsage {
+        OllamaUsage {
+            total_duration: self.total_duration.map(std::time::Duration::from_nanos),
+            prompt_eval_count: self.prompt_eval_count,
+            eval_count: self.eval_count,
+        }
+    }
+}
+
+// Combine the newline-delimited frames of an Ollama response body into the full
+// text, along with the usage from the final frame (if one was received)
+fn aggregate_ollama_frames(body: &str) -> Result<(String, Option<OllamaUsage>), LLMError> {
+    let mut decoder = LineDecoder::new(decode_ollama_line);
+    let mut text = decoder.push(body.as_bytes())?.concat();
+    text.extend(decoder.finish()?);
+    Ok((text, decoder.state))
+}
+
+/// Splits a response body that arrives in network chunks into lines and decodes each one
+/// into a text fragment with `decode_line`. Chunks can end mid-line or even mid-character,
+/// so incomplete lines are buffered until the rest arrives. `state` keeps whatever else the
+/// lines report, such as usage.
+struct LineDecoder<S> {
+    buffer: Vec<u8>,
+    state: S,
+    decode_line: fn(&mut S, &str) -> Result<Option<String>, LLMError>,
+}
+
+impl<S: Default> LineDecoder<S> {
+    fn new(decode_line: fn(&mut S, &str) -> Result<Option<String>, LLMError>) -> Self {
+        LineDecoder { buffer: Vec::new(), state: S::default(), decode_line }
+    }
+
+    /// Feed the next chunk of the body, returning the fragments of the lines it completed
+    fn push(&mut self, chunk: &[u8]) -> Result<Vec<String>, LLMError> {
+        self.buffer.extend_from_slice(chunk);
+        let mut fragments = Vec::new();
+
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            if let Some(fragment) =
+                (self.decode_line)(&mut self.state, &String::from_utf8_lossy(&line))?
+            {
+                fragments.push(fragment);
+            }
+        }
+        Ok(fragments)
+    }
+
+    /// Decode whatever is left once the body has ended without a final line break
+    fn finish(&mut self) -> Result<Option<String>, LLMError> {
+        let line = std::mem::take(&mut self.buffer);
+        (self.decode_line)(&mut self.state, &String::from_utf8_lossy(&line))
+    }
+}
+
+// Decode a newline-delimited JSON frame of an Ollama response, keeping the usage
+// reported by the final frame
+fn decode_ollama_line(
+    usage: &mut Option<OllamaUsage>,
+    line: &str,
+) -> Result<Option<String>, LLMError> {
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let frame: OllamaResponse = serde_json::from_str(line)?;
+    if let Some(error) = frame.error {
+        return Err(LLMError::LocalModelError(format!("Ollama error: {}", error)));
+    }
+    if frame.done {
+        *usage = Some(frame.usage());
+    }
+    Ok(Some(frame.response).filter(|response| !response.is_empty()))
+}
+
+// Whether an error was caused by the connection being refused, i.e. nothing listening
+fn is_connection_refused(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<std::io::Error>() {
+            if io_err.kind() == std::io::ErrorKind::ConnectionRefused {
+                return true;
+            }
+        }
+        source = err.source();
+    }
+    false
+}
+
+// Map a failed request to Ollama, explaining a refused connection as Ollama not running.
+// Other network errors (DNS, timeouts, ...) are kept as they are.
+fn ollama_request_error(err: reqwest::Error) -> LLMError {
+    if is_connection_refused(&err) {
+        let url = err.url().map(|url| url.origin().ascii_serialization()).unwrap_or_default();
+        LLMError::OllamaNotRunning(url)
+    } else {
+        LLMError::NetworkError(err)
+    }
+}
+
+// Turn an error status from Ollama into an error with the message from its body
+async fn check_ollama_status(
+    response: reqwest::Response,
+    timeout: Duration,
+) -> Result<reqwest::Response, LLMError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.map_err(|e| ollama_generate_error(e, timeout))?;
+    let message = serde_json::from_str::<OllamaResponse>(&body)
+        .ok()
+        .and_then(|frame| frame.error)
+        .unwrap_or(body);
+    Err(LLMError::LocalModelError(format!("Ollama error ({}): {}", status, message.trim())))
+}
+
+// A client whose requests give up after `timeout`, including reading the response. A
+// streamed response keeps arriving for as long as the model writes, so for streaming only
+// connecting is limited, and `within_timeout` limits each step instead.
+fn http_client(timeout: Duration, stream: bool) -> Result<reqwest::Client, LLMError> {
+    let builder = reqwest::Client::builder().connect_timeout(timeout);
+    let builder = if stream { builder } else { builder.timeout(timeout) };
+    Ok(builder.build()?)
+}
+
+// Wait for one step of a request, such as the next chunk of a streamed response, giving
+// up after `timeout`
+async fn within_timeout<T>(
+    timeout: Duration,
+    step: impl std::future::Future<Output = Result<T, reqwest::Error>>,
+    failed: impl Fn(reqwest::Error) -> LLMError,
+) -> Result<T, LLMError> {
+    tokio::time::timeout(timeout, step)
+        .await
+        .map_err(|_| LLMError::Timeout(timeout))?
+        .map_err(failed)
+}
+
+// Report a request that ran out of time as such, rather than as a network error
+fn request_error(err: reqwest::Error, timeout: Duration) -> LLMError {
+    if err.is_timeout() {
+        LLMError::Timeout(timeout)
+    } else {
+        LLMError::NetworkError(err)
+    }
+}
+
+// Report a generate request that ran out of time, or found no server, as such
+fn ollama_generate_error(err: reqwest::Error, timeout: Duration) -> LLMError {
+    if err.is_timeout() {
+        LLMError::Timeout(timeout)
+    } else {
+        ollama_request_error(err)
+    }
+}
+
+// How long to wait before asking again when Ollama sends back an empty response, which
+// happens while a model is still being loaded
+const EMPTY_RESPONSE_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+impl OllamaProvider {
+    // Send one generate request and return the whole response
+    async fn generate_once(&self, prompt: &str) -> Result<String, LLMError> {
+        let client = http_client(self.timeout, false)?;
+        let request = self.request(prompt, false);
+        let failed = |e| ollama_generate_error(e, self.timeout);
+
+        let response = client.post(&self.api_url).json(&request).send().await.map_err(failed)?;
+        let response = check_ollama_status(response, self.timeout).await?;
+        let body = response.text().await.map_err(failed)?;
+        let (text, usage) = aggregate_ollama_frames(&body)?;
+
+        if let Ok(mut last_usage) = self.last_usage.lock() {
+            *last_usage = usage;
+        }
+
+        Ok(text)
+    }
+
+    // Send one streaming generate request, passing each piece of the response to
+    // `on_fragment` as it arrives
+    async fn stream_once(
+        &self,
+        prompt: &str,
+        on_fragment: &mut OnFragment<'_>,
+    ) -> Result<String, LLMError> {
+        let client = http_client(self.timeout, true)?;
+        let request = self.request(prompt, true);
+        let failed = |e| ollama_generate_error(e, self.timeout);
+
+        let send = client.post(&self.api_url).json(&request).send();
+        let response = within_timeout(self.timeout, send, failed).await?;
+        let mut response = check_ollama_status(response, self.timeout).await?;
+        let mut decoder = LineDecoder::new(decode_ollama_line);
+        let mut text = String::new();
+
+        while let Some(chunk) = within_timeout(self.timeout, response.chunk(), failed).await? {
+            for fragment in decoder.push(&chunk)? {
+                on_fragment(&fragment);
+                text.push_str(&fragment);
+            }
+        }
+        if let Some(fragment) = decoder.finish()? {
+            on_fragment(&fragment);
+            text.push_str(&fragment);
+        }
+
+        if let Ok(mut last_usage) = self.last_usage.lock() {
+            *last_usage = decoder.state;
+        }
+
+        Ok(text)
+    }
+}
+
+// A response that is still empty after retrying, with what probably caused it
+fn require_response(text: String) -> Result<String, LLMError> {
+    if text.trim().is_empty() {
+        return Err(LLMError::LocalModelError(
+            "Ollama returned an empty response; the model may still be loading".into(),
+        ));
+    }
+    Ok(text)
 }
 
 #[async_trait]
 impl LLMEngine for OllamaProvider {
     async fn generate(&self, prompt: &str) -> Result<String, LLMError> {
-        let client = reqwest::Client::new();
-        let request = OllamaRequest { model: &self.model, prompt, stream: false };
+        let text = self.generate_once(prompt).await?;
+        if !text.trim().is_empty() {
+            return Ok(text);
+        }
 
-        let response = client
-            .post(&self.api_url)
-            .json(&request)
-            .send()
-            .await?
-            .json::<OllamaResponse>()
-            .await?;
+        tracing::info!(
+            "Ollama returned an empty response. Retrying in case the model is loading..."
+        );
+        tokio::time::sleep(EMPTY_RESPONSE_RETRY_DELAY).await;
+        require_response(self.generate_once(prompt).await?)
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        on_fragment: &mut OnFragment<'_>,
+    ) -> Result<String, LLMError> {
+        let text = self.stream_once(prompt, on_fragment).await?;
+        if !text.trim().is_empty() {
+            return Ok(text);
+        }
 
-        Ok(response.response)
+        tracing::info!(
+            "Ollama returned an empty response. Retrying in case the model is loading..."
+        );
+        tokio::time::sleep(EMPTY_RESPONSE_RETRY_DELAY).await;
+        require_response(self.stream_once(prompt, on_fragment).await?)
     }
 
     fn name(&self) -> &str {
@@ -93,8 +521,84 @@ impl LLMEngine for OllamaProvider {
     }
 
     fn is_online(&self) -> bool {
-        // WizardCoder model usually needs to be downloaded
-        self.model == "wizardcoder"
+        // WizardCoder model usually needs to be downloaded. Otherwise Ollama is only
+        // online when it's served from somewhere other than this machine or the LAN.
+        self.model == "wizardcoder" || !is_loopback_or_private(&self.api_url)
+    }
+}
+
+/// Returns true if `url` points at this machine or a private network (loopback,
+/// RFC 1918, link-local or unique local addresses), so talking to it keeps data off
+/// the internet. Unparseable URLs are treated as public.
+pub fn is_loopback_or_private(url: &str) -> bool {
+    use std::net::IpAddr;
+
+    let Some(host) =
+        reqwest::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_lowercase))
+    else {
+        return false;
+    };
+
+    // IPv6 hosts keep their brackets in URLs
+    let host: IpAddr = match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => ip,
+        Err(_) => {
+            let domain = host.trim_end_matches('.');
+            return domain == "localhost" || domain.ends_with(".localhost");
+        }
+    };
+
+    match host {
+        IpAddr::V4(ip) => ip.is_loopback() || ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            // fc00::/7 is unique local, fe80::/10 is link-local
+            ip.is_loopback() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// Find the nearest `.env` file, starting at `start` and walking up parent
+/// directories (like git does for `.git`). The search stops after checking
+/// `stop` (typically the home directory) or at the filesystem root.
+pub fn find_env_file(start: &Path, stop: Option<&Path>) -> Option<PathBuf> {
+    for dir in start.ancestors() {
+        let candidate = dir.join(".env");
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+
+        if stop == Some(dir) {
+            break;
+        }
+    }
+
+    None
+}
+
+/// Load environment variables from `explicit` if given, otherwise from the
+/// nearest `.env` discovered from the current directory up to the home directory.
+/// Variables that are already set are never overridden.
+/// Returns the path of the file that was loaded, if any.
+pub fn load_env_file(explicit: Option<&Path>) -> Result<Option<PathBuf>, LLMError> {
+    if let Some(path) = explicit {
+        dotenv::from_path(path).map_err(|e| {
+            LLMError::Unknown(format!("Failed to load env file {}: {}", path.display(), e))
+        })?;
+        return Ok(Some(path.to_path_buf()));
+    }
+
+    let cwd = env::current_dir()
+        .map_err(|e| LLMError::Unknown(format!("Could not determine current directory: {}", e)))?;
+    let home = crate::config::home_dir();
+
+    match find_env_file(&cwd, home.as_deref()) {
+        Some(path) => {
+            // Ignore parse errors in discovered files, matching dotenv::dotenv()
+            let _ = dotenv::from_path(&path);
+            Ok(Some(path))
+        }
+        None => Ok(None),
     }
 }
 
@@ -104,6 +608,11 @@ pub struct OpenAIProvider {
     model: String,
     call_count: std::sync::atomic::AtomicUsize,
     max_calls: usize,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    max_tokens: Option<u32>,
+    extra_options: Map<String, Value>,
+    timeout: Duration,
 }
 
 impl OpenAIProvider {
@@ -112,8 +621,8 @@ impl OpenAIProvider {
     }
 
     pub fn new_with_model(model: &str) -> Result<Self, LLMError> {
-        // Load from .env file if it exists
-        let _ = dotenv::dotenv();
+        // Load from the nearest .env file if one exists
+        let _ = load_env_file(None);
 
         // Get API key from environment
         let api_key = env::var("OPENAI_API_KEY").map_err(|_| {
@@ -123,21 +632,52 @@ impl OpenAIProvider {
             )
         })?;
 
-        // Validate API key format (should start with sk-)
-        if !api_key.starts_with("sk-") {
-            return Err(LLMError::ApiKeyError(
-                "Invalid OpenAI API key format. API keys should start with 'sk-'".into(),
-            ));
-        }
+        let api_key = validate_api_key(&api_key)?.to_string();
 
         Ok(Self {
             api_key,
             model: model.to_string(),
             call_count: std::sync::atomic::AtomicUsize::new(0),
             max_calls: 50, // Limit to 50 calls per session
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            extra_options: Map::new(),
+            timeout: DEFAULT_TIMEOUT,
         })
     }
 
+    /// Sample with this temperature instead of the API's default
+    pub fn with_temperature(mut self, temperature: Option<f32>) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// Sample from the tokens making up this probability mass instead of the API's default
+    pub fn with_top_p(mut self, top_p: Option<f32>) -> Self {
+        self.top_p = top_p;
+        self
+    }
+
+    /// Stop generating after this many tokens instead of the API's default
+    pub fn with_max_tokens(mut self, max_tokens: Option<u32>) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Add these fields to every chat completion request, for parameters this crate doesn't
+    /// model (e.g. `presence_penalty`)
+    pub fn with_extra_options(mut self, extra_options: Map<String, Value>) -> Self {
+        self.extra_options = extra_options;
+        self
+    }
+
+    /// Give up on a request after this long, instead of `DEFAULT_TIMEOUT`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
     pub fn get_model(&self) -> &str {
         &self.model
     }
@@ -147,10 +687,84 @@ impl OpenAIProvider {
     }
 }
 
+// Never print the API key, even in debug output
+impl std::fmt::Debug for OpenAIProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OpenAIProvider")
+            .field("api_key", &mask_api_key(&self.api_key))
+            .field("model", &self.model)
+            .field("call_count", &self.call_count)
+            .field("max_calls", &self.max_calls)
+            .field("temperature", &self.temperature)
+            .field("top_p", &self.top_p)
+            .field("max_tokens", &self.max_tokens)
+            .field("extra_options", &self.extra_options)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// OpenAI API keys are longer than this; a shorter one was cut off when it was copied
+const MIN_API_KEY_LEN: usize = 40;
+
+/// Check the format of an OpenAI API key before any request is made, so a key mangled by
+/// copy-paste fails with a specific error rather than a 401. Returns the key without
+/// surrounding whitespace, such as the newline of a key read from a file.
+pub fn validate_api_key(key: &str) -> Result<&str, LLMError> {
+    let key = key.trim();
+    if key.contains(char::is_whitespace) {
+        return Err(LLMError::ApiKeyError(
+            "OpenAI API key contains whitespace. Check OPENAI_API_KEY for pasted line breaks."
+                .into(),
+        ));
+    }
+    if !key.starts_with("sk-") {
+        return Err(LLMError::ApiKeyError(
+            "Invalid OpenAI API key format. API keys should start with 'sk-'".into(),
+        ));
+    }
+    if key.len() < MIN_API_KEY_LEN {
+        return Err(LLMError::ApiKeyError(format!(
+            "OpenAI API key appears truncated ({} characters). Copy the whole key again.",
+            key.len()
+        )));
+    }
+    if !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(LLMError::ApiKeyError(
+            "OpenAI API key contains unexpected characters, such as quotes around it.".into(),
+        ));
+    }
+    Ok(key)
+}
+
+/// Mask an API key for display, keeping only the `sk-` prefix that identifies its kind
+pub fn mask_api_key(key: &str) -> String {
+    if key.starts_with("sk-") {
+        "sk-***".to_string()
+    } else {
+        "***".to_string()
+    }
+}
+
+/// Request body fields the OpenAI provider sets itself, which `extra_options` can't set
+pub const OPENAI_REQUEST_FIELDS: &[&str] =
+    &["model", "messages", "stream", "temperature", "top_p", "max_tokens"];
+
 #[derive(Serialize)]
 struct OpenAIRequest<'a> {
     model: &'a str,
     messages: Vec<OpenAIMessage<'a>>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    // Left out when unset, so the API's defaults apply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(flatten)]
+    extra_options: &'a Map<String, Value>,
 }
 
 #[derive(Serialize)]
@@ -174,38 +788,87 @@ struct OpenAIResponseMessage {
     content: String,
 }
 
-#[async_trait]
-impl LLMEngine for OpenAIProvider {
-    async fn generate(&self, prompt: &str) -> Result<String, LLMError> {
+// One event of a streamed response; the text is spread over the deltas of many events
+#[derive(Deserialize)]
+struct OpenAIStreamChunk {
+    #[serde(default)]
+    choices: Vec<OpenAIStreamChoice>,
+}
+
+#[derive(Deserialize)]
+struct OpenAIStreamChoice {
+    delta: OpenAIDelta,
+}
+
+#[derive(Deserialize)]
+struct OpenAIDelta {
+    content: Option<String>,
+}
+
+// Decode a server-sent event line of a streamed OpenAI response (`data: {...}` lines
+// ending with `data: [DONE]`), noting in `done` when the end has been reached
+fn decode_openai_line(done: &mut bool, line: &str) -> Result<Option<String>, LLMError> {
+    let line = line.trim_end_matches(['\r', '\n']);
+
+    // Blank lines separate events; comments and other fields carry no text
+    let Some(data) = line.strip_prefix("data:") else {
+        return Ok(None);
+    };
+    let data = data.trim_start();
+    if *done || data.is_empty() {
+        return Ok(None);
+    }
+    if data == "[DONE]" {
+        *done = true;
+        return Ok(None);
+    }
+
+    let chunk: OpenAIStreamChunk = serde_json::from_str(data).map_err(|e| {
+        LLMError::ParsingError(format!("Failed to parse OpenAI stream event: {}", e))
+    })?;
+    Ok(chunk.choices.into_iter().next().and_then(|choice| choice.delta.content))
+}
+
+impl OpenAIProvider {
+    // The chat completion request for a prompt, with the configured sampling settings
+    fn request<'a>(&'a self, prompt: &'a str, stream: bool) -> OpenAIRequest<'a> {
+        OpenAIRequest {
+            model: &self.model,
+            messages: vec![OpenAIMessage { role: "user", content: prompt }],
+            stream,
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: self.max_tokens,
+            extra_options: &self.extra_options,
+        }
+    }
+
+    // Send a chat completion request, turning HTTP errors into LLM errors
+    async fn send(&self, prompt: &str, stream: bool) -> Result<reqwest::Response, LLMError> {
         // Check if we've exceeded the call limit
         let current_count = self.call_count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
         if current_count >= self.max_calls {
             return Err(LLMError::RateLimitExceeded);
         }
 
-        let client = reqwest::Client::new();
-        let request = OpenAIRequest {
-            model: &self.model,
-            messages: vec![OpenAIMessage { role: "user", content: prompt }],
-        };
+        let client = http_client(self.timeout, stream)?;
+        let request = self.request(prompt, stream);
 
-        let response = client
+        let send = client
             .post("https://api.openai.com/v1/chat/completions")
             .header("Authorization", format!("Bearer {}", self.api_key))
             .header("Content-Type", "application/json")
             .json(&request)
-            .send()
-            .await;
-
-        let response = match response {
-            Ok(resp) => resp,
-            Err(e) => return Err(LLMError::NetworkError(e)),
-        };
+            .send();
+        let response =
+            within_timeout(self.timeout, send, |e| request_error(e, self.timeout)).await?;
 
         // Check for HTTP errors
         if !response.status().is_success() {
             let status = response.status();
             let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
+            // Error bodies can echo the key back
+            let error_text = error_text.replace(&self.api_key, &mask_api_key(&self.api_key));
 
             return match status.as_u16() {
                 401 => Err(LLMError::ApiKeyError("Invalid OpenAI API key. Please check your OPENAI_API_KEY environment variable.".into())),
@@ -214,7 +877,18 @@ impl LLMEngine for OpenAIProvider {
             };
         }
 
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl LLMEngine for OpenAIProvider {
+    async fn generate(&self, prompt: &str) -> Result<String, LLMError> {
+        let response = self.send(prompt, false).await?;
         let openai_response: OpenAIResponse = response.json().await.map_err(|e| {
+            if e.is_timeout() {
+                return LLMError::Timeout(self.timeout);
+            }
             LLMError::ParsingError(format!("Failed to parse OpenAI response: {}", e))
         })?;
 
@@ -225,6 +899,30 @@ impl LLMEngine for OpenAIProvider {
         Ok(openai_response.choices[0].message.content.clone())
     }
 
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        on_fragment: &mut OnFragment<'_>,
+    ) -> Result<String, LLMError> {
+        let mut response = self.send(prompt, true).await?;
+        let mut decoder = LineDecoder::new(decode_openai_line);
+        let mut text = String::new();
+
+        let failed = |e| request_error(e, self.timeout);
+        while let Some(chunk) = within_timeout(self.timeout, response.chunk(), failed).await? {
+            for fragment in decoder.push(&chunk)? {
+                on_fragment(&fragment);
+                text.push_str(&fragment);
+            }
+        }
+        if let Some(fragment) = decoder.finish()? {
+            on_fragment(&fragment);
+            text.push_str(&fragment);
+        }
+
+        Ok(text)
+    }
+
     fn name(&self) -> &str {
         "OpenAI"
     }
@@ -235,6 +933,12 @@ impl LLMEngine for OpenAIProvider {
     }
 }
 
+/// Default path of the local GGUF model used by the LLM-rs backend
+pub const DEFAULT_MODEL_PATH: &str = "models/tinyllama.gguf";
+
+/// Whether this build includes the LLM-rs backend (the `llm-rs` feature)
+pub const LLM_RS_ENABLED: bool = cfg!(feature = "llm-rs");
+
 // LLM-rs (local) implementation
 #[cfg(feature = "llm-rs")]
 pub struct LlmRsProvider {
@@ -323,88 +1027,353 @@ impl LLMEngine for LlmRsProvider {
     }
 }
 
+/// A prompt and the response a provider gave to it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedResponse {
+    pub prompt: String,
+    pub response: String,
+}
+
+fn read_recordings(path: &Path) -> Result<Vec<RecordedResponse>, LLMError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| {
+        LLMError::RecordingError(format!("Could not read {}: {}", path.display(), e))
+    })?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+/// Wraps providers and appends every prompt/response pair they produce to a JSON file,
+/// for replaying later with `ReplayProvider`
+pub struct RecordingProvider {
+    inner: Vec<Arc<LLMProvider>>,
+    chain: bool,
+    name: String,
+    path: PathBuf,
+    recordings: Mutex<Vec<RecordedResponse>>,
+}
+
+impl RecordingProvider {
+    /// Record the responses of `inner`, which are tried in order like `generate_with_chain`
+    /// when `chain` is set, and otherwise a single provider with its built-in fallback.
+    /// Recordings already in `path` are kept.
+    pub fn new(inner: Vec<Arc<LLMProvider>>, chain: bool, path: PathBuf) -> Result<Self, LLMError> {
+        let recordings = if path.exists() { read_recordings(&path)? } else { Vec::new() };
+        let name = inner.iter().map(|p| p.name()).collect::<Vec<&str>>().join(" → ");
+
+        Ok(Self {
+            inner,
+            chain,
+            name: format!("{} (recording)", name),
+            path,
+            recordings: Mutex::new(recordings),
+        })
+    }
+
+    fn record(&self, prompt: &str, response: &str) -> Result<(), LLMError> {
+        let mut recordings = self
+            .recordings
+            .lock()
+            .map_err(|_| LLMError::RecordingError("Recording lock poisoned".into()))?;
+        recordings
+            .push(RecordedResponse { prompt: prompt.to_string(), response: response.to_string() });
+
+        let json = serde_json::to_string_pretty(&*recordings)?;
+        std::fs::write(&self.path, json).map_err(|e| {
+            LLMError::RecordingError(format!("Could not write {}: {}", self.path.display(), e))
+        })
+    }
+}
+
+#[async_trait]
+impl LLMEngine for RecordingProvider {
+    async fn generate(&self, prompt: &str) -> Result<String, LLMError> {
+        let response = match self.inner.as_slice() {
+            [provider] if !self.chain => provider.generate_with_fallback(prompt).await?,
+            providers => generate_with_chain(providers, prompt).await?,
+        };
+
+        self.record(prompt, &response)?;
+        Ok(response)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Serves responses recorded by `RecordingProvider`, matched by prompt, so the whole
+/// pipeline can run deterministically without a model
+pub struct ReplayProvider {
+    responses: std::collections::HashMap<String, String>,
+}
+
+impl ReplayProvider {
+    /// Load recorded responses from a JSON file. When a prompt was recorded more than
+    /// once, the latest response is served.
+    pub fn load(path: &Path) -> Result<Self, LLMError> {
+        Ok(Self::from_recordings(read_recordings(path)?))
+    }
+
+    pub fn from_recordings(recordings: Vec<RecordedResponse>) -> Self {
+        Self { responses: recordings.into_iter().map(|r| (r.prompt, r.response)).collect() }
+    }
+}
+
+#[async_trait]
+impl LLMEngine for ReplayProvider {
+    async fn generate(&self, prompt: &str) -> Result<String, LLMError> {
+        self.responses
+            .get(prompt)
+            .cloned()
+            .ok_or_else(|| LLMError::RecordingError("No recorded response for this prompt".into()))
+    }
+
+    fn name(&self) -> &str {
+        "Replay"
+    }
+}
+
+/// Sees every prompt before it's sent and every response before it's parsed, e.g. for
+/// logging, guardrails or redaction. Both methods pass their input through unchanged by
+/// default, so an interceptor only implements what it needs.
+pub trait RequestInterceptor: Send + Sync {
+    /// Returns the prompt to send instead of `prompt`
+    fn on_prompt(&self, prompt: &str) -> String {
+        prompt.to_string()
+    }
+
+    /// Returns the response to parse instead of `response`
+    fn on_response(&self, response: &str) -> String {
+        response.to_string()
+    }
+}
+
+/// Runs a provider's requests through a `RequestInterceptor`. Streamed responses are
+/// delivered whole, once the interceptor has seen them.
+pub struct InterceptingProvider {
+    inner: Arc<LLMProvider>,
+    interceptor: Arc<dyn RequestInterceptor>,
+    name: String,
+}
+
+impl InterceptingProvider {
+    pub fn new(inner: Arc<LLMProvider>, interceptor: Arc<dyn RequestInterceptor>) -> Self {
+        let name = format!("{} (intercepted)", inner.name());
+        Self { inner, interceptor, name }
+    }
+
+    // Like `generate`, keeping the wrapped provider's built-in fallback
+    async fn generate_with_fallback(&self, prompt: &str) -> Result<String, LLMError> {
+        let prompt = self.interceptor.on_prompt(prompt);
+        let response = Box::pin(self.inner.generate_with_fallback(&prompt)).await?;
+        Ok(self.interceptor.on_response(&response))
+    }
+}
+
+#[async_trait]
+impl LLMEngine for InterceptingProvider {
+    async fn generate(&self, prompt: &str) -> Result<String, LLMError> {
+        let prompt = self.interceptor.on_prompt(prompt);
+        let response = self.inner.generate(&prompt).await?;
+        Ok(self.interceptor.on_response(&response))
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_online(&self) -> bool {
+        self.inner.is_online()
+    }
+}
+
 // LLM Provider enum
 pub enum LLMProvider {
     Ollama(OllamaProvider),
     LlmRs(LlmRsProvider),
     OpenAI(OpenAIProvider),
+    Recording(Box<RecordingProvider>),
+    Replay(ReplayProvider),
+    Intercepted(Box<InterceptingProvider>),
 }
 
-impl LLMProvider {
-    pub fn default() -> Self {
+impl Default for LLMProvider {
+    fn default() -> Self {
         Self::Ollama(OllamaProvider::new("codellama"))
     }
+}
 
+impl LLMProvider {
     pub fn is_online(&self) -> bool {
         match self {
-            Self::Ollama(provider) => provider.model == "wizardcoder", // Wizardcoder requires download
+            Self::Ollama(provider) => provider.is_online(),
             Self::OpenAI(_) => true,
-            Self::LlmRs(_) => false,
+            Self::Recording(recorder) => recorder.inner.iter().any(|p| p.is_online()),
+            Self::Intercepted(provider) => provider.is_online(),
+            Self::LlmRs(_) | Self::Replay(_) => false,
+        }
+    }
+
+    /// The name of the model the provider asks, for picking a model-specific prompt. A
+    /// recording provider reports its first backend's model.
+    pub fn model_name(&self) -> Option<&str> {
+        match self {
+            Self::Ollama(provider) => Some(provider.model()),
+            Self::OpenAI(provider) => Some(provider.get_model()),
+            Self::Recording(recorder) => recorder.inner.first()?.model_name(),
+            Self::Intercepted(provider) => provider.inner.model_name(),
+            Self::LlmRs(_) | Self::Replay(_) => None,
         }
     }
 
+    /// Generate a response, falling back to the built-in chain for this provider
+    /// (Ollama falls back to LLM-rs; other providers have no fallback)
     pub async fn generate_with_fallback(&self, prompt: &str) -> Result<String, LLMError> {
         match self {
+            LLMProvider::Ollama(_) => {
+                let fallback = Self::builtin_fallback();
+                try_in_order(&[self, &fallback], prompt, &mut |_| {}).await
+            }
+            LLMProvider::Intercepted(provider) => provider.generate_with_fallback(prompt).await,
+            _ => try_in_order(&[self], prompt, &mut |_| {}).await,
+        }
+    }
+
+    /// Like `generate_with_fallback`, passing the response to `on_fragment` as it streams in.
+    /// Only OpenAI and Ollama stream; other providers pass the whole response at once.
+    /// Ollama only falls back if it fails before streaming anything, so a fallback's
+    /// response never follows part of Ollama's.
+    pub async fn generate_stream_with_fallback(
+        &self,
+        prompt: &str,
+        on_fragment: &mut OnFragment<'_>,
+    ) -> Result<String, LLMError> {
+        match self {
+            LLMProvider::OpenAI(provider) => provider.generate_stream(prompt, on_fragment).await,
             LLMProvider::Ollama(provider) => {
-                match provider.generate(prompt).await {
-                    Ok(response) => Ok(response),
-                    Err(e) => {
-                        println!("Ollama failed: {}. Falling back to LLM-rs...", e);
-                        match &LLMProvider::LlmRs(LlmRsProvider::new("models/tinyllama.gguf")) {
-                            LLMProvider::LlmRs(provider) => {
-                                match provider.generate(prompt).await {
-                                    Ok(response) => Ok(response),
-                                    Err(e) => {
-                                        println!("LLM-rs failed: {}. OpenAI fallback disabled.", e);
-                                        // Return the error instead of falling back to OpenAI
-                                        Err(e)
-                                        // Commented out OpenAI fallback for now
-                                        /*
-                                        match OpenAIProvider::new() {
-                                            Ok(openai_provider) => {
-                                                let openai = LLMProvider::OpenAI(openai_provider);
-                                                match &openai {
-                                                    LLMProvider::OpenAI(provider) => provider.generate(prompt).await,
-                                                    _ => unreachable!(),
-                                                }
-                                            },
-                                            Err(e) => Err(e),
-                                        }
-                                        */
-                                    }
-                                }
-                            }
-                            _ => unreachable!(),
-                        }
+                let mut streamed = false;
+                let result = provider
+                    .generate_stream(prompt, &mut |fragment: &str| {
+                        streamed = true;
+                        on_fragment(fragment);
+                    })
+                    .await;
+
+                match result {
+                    Err(e) if !streamed => {
+                        let fallback = Self::builtin_fallback();
+                        tracing::warn!(
+                            "{} failed: {}. Falling back to {}...",
+                            provider.name(),
+                            e,
+                            fallback.name()
+                        );
+                        let response = fallback.generate(prompt).await?;
+                        on_fragment(&response);
+                        Ok(response)
                     }
+                    result => result,
                 }
             }
-            LLMProvider::LlmRs(provider) => {
-                match provider.generate(prompt).await {
-                    Ok(response) => Ok(response),
-                    Err(e) => {
-                        println!("LLM-rs failed: {}. OpenAI fallback disabled.", e);
-                        // Return the error instead of falling back to OpenAI
-                        Err(e)
-                        // Commented out OpenAI fallback for now
-                        /*
-                        match OpenAIProvider::new() {
-                            Ok(openai_provider) => {
-                                let openai = LLMProvider::OpenAI(openai_provider);
-                                match &openai {
-                                    LLMProvider::OpenAI(provider) => provider.generate(prompt).await,
-                                    _ => unreachable!(),
-                                }
-                            },
-                            Err(e) => Err(e),
-                        }
-                        */
-                    }
+            _ => {
+                let response = self.generate_with_fallback(prompt).await?;
+                on_fragment(&response);
+                Ok(response)
+            }
+        }
+    }
+
+    /// The LLM-rs provider Ollama falls back to. It's created once and shared, so the
+    /// model is loaded at most once per process rather than on every fallback.
+    pub fn builtin_fallback() -> Arc<LLMProvider> {
+        static FALLBACK: OnceLock<Arc<LLMProvider>> = OnceLock::new();
+        FALLBACK
+            .get_or_init(|| Arc::new(LLMProvider::LlmRs(LlmRsProvider::new(DEFAULT_MODEL_PATH))))
+            .clone()
+    }
+}
+
+/// Build a fallback chain from its tiers, creating each provider with `create`. Tiers that
+/// are equal share one provider, so its client (or loaded model) is reused rather than
+/// created again. Tiers that `create` returns None for are left out.
+pub fn build_chain<K: PartialEq>(
+    tiers: &[K],
+    mut create: impl FnMut(&K) -> Option<LLMProvider>,
+) -> Vec<Arc<LLMProvider>> {
+    let mut created: Vec<(&K, Arc<LLMProvider>)> = Vec::new();
+    let mut chain = Vec::new();
+
+    for tier in tiers {
+        let provider = match created.iter().find(|(kind, _)| *kind == tier) {
+            Some((_, provider)) => provider.clone(),
+            None => {
+                let Some(provider) = create(tier) else {
+                    continue;
+                };
+                let provider = Arc::new(provider);
+                created.push((tier, provider.clone()));
+                provider
+            }
+        };
+        chain.push(provider);
+    }
+    chain
+}
+
+/// Try each provider in order until one succeeds, returning the last error if all fail
+pub async fn generate_with_chain(
+    providers: &[Arc<LLMProvider>],
+    prompt: &str,
+) -> Result<String, LLMError> {
+    generate_with_chain_progress(providers, prompt, &mut |_| {}).await
+}
+
+/// Like `generate_with_chain`, calling `on_attempt` before each provider is tried so the
+/// caller can show progress through the chain
+pub async fn generate_with_chain_progress(
+    providers: &[Arc<LLMProvider>],
+    prompt: &str,
+    on_attempt: &mut OnAttempt<'_>,
+) -> Result<String, LLMError> {
+    let providers: Vec<&LLMProvider> = providers.iter().map(|p| p.as_ref()).collect();
+    try_in_order(&providers, prompt, on_attempt).await
+}
+
+async fn try_in_order(
+    providers: &[&LLMProvider],
+    prompt: &str,
+    on_attempt: &mut OnAttempt<'_>,
+) -> Result<String, LLMError> {
+    let mut last_error = None;
+
+    for (i, provider) in providers.iter().enumerate() {
+        on_attempt(&ChainAttempt {
+            number: i + 1,
+            total: providers.len(),
+            provider: provider.name().to_string(),
+        });
+        match provider.generate(prompt).await {
+            Ok(response) => {
+                if i > 0 {
+                    tracing::info!("{} succeeded after fallback.", provider.name());
                 }
+                return Ok(response);
+            }
+            Err(e) => {
+                match providers.get(i + 1) {
+                    Some(next) => tracing::warn!(
+                        "{} failed: {}. Falling back to {}...",
+                        provider.name(),
+                        e,
+                        next.name()
+                    ),
+                    None => tracing::warn!("{} failed: {}. No more fallbacks.", provider.name(), e),
+                }
+                last_error = Some(e);
             }
-            LLMProvider::OpenAI(provider) => provider.generate(prompt).await,
         }
     }
+
+    Err(last_error.unwrap_or_else(|| LLMError::Unknown("No LLM providers configured".into())))
 }
 
 #[async_trait]
@@ -414,6 +1383,26 @@ impl LLMEngine for LLMProvider {
             LLMProvider::Ollama(provider) => provider.generate(prompt).await,
             LLMProvider::LlmRs(provider) => provider.generate(prompt).await,
             LLMProvider::OpenAI(provider) => provider.generate(prompt).await,
+            LLMProvider::Recording(provider) => provider.generate(prompt).await,
+            LLMProvider::Replay(provider) => provider.generate(prompt).await,
+            LLMProvider::Intercepted(provider) => provider.generate(prompt).await,
+        }
+    }
+
+    async fn generate_stream(
+        &self,
+        prompt: &str,
+        on_fragment: &mut OnFragment<'_>,
+    ) -> Result<String, LLMError> {
+        match self {
+            LLMProvider::Ollama(provider) => provider.generate_stream(prompt, on_fragment).await,
+            LLMProvider::LlmRs(provider) => provider.generate_stream(prompt, on_fragment).await,
+            LLMProvider::OpenAI(provider) => provider.generate_stream(prompt, on_fragment).await,
+            LLMProvider::Recording(provider) => provider.generate_stream(prompt, on_fragment).await,
+            LLMProvider::Replay(provider) => provider.generate_stream(prompt, on_fragment).await,
+            LLMProvider::Intercepted(provider) => {
+                provider.generate_stream(prompt, on_fragment).await
+            }
         }
     }
 
@@ -422,6 +1411,547 @@ impl LLMEngine for LLMProvider {
             LLMProvider::Ollama(provider) => provider.name(),
             LLMProvider::LlmRs(provider) => provider.name(),
             LLMProvider::OpenAI(provider) => provider.name(),
+            LLMProvider::Recording(provider) => provider.name(),
+            LLMProvider::Replay(provider) => provider.name(),
+            LLMProvider::Intercepted(provider) => provider.name(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A provider that only answers `prompt`, with `ls`
+    fn replay(prompt: &str) -> Arc<LLMProvider> {
+        Arc::new(LLMProvider::Replay(ReplayProvider::from_recordings(vec![RecordedResponse {
+            prompt: prompt.to_string(),
+            response: "{\"command\": \"ls\"}".to_string(),
+        }])))
+    }
+
+    // An OpenAI provider with no sampling settings, built without reading the environment
+    fn test_openai_provider() -> OpenAIProvider {
+        OpenAIProvider {
+            api_key: "sk-test".to_string(),
+            model: "gpt-4o-mini".to_string(),
+            call_count: std::sync::atomic::AtomicUsize::new(0),
+            max_calls: 50,
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            extra_options: Map::new(),
+            timeout: DEFAULT_TIMEOUT,
         }
     }
+
+    #[test]
+    fn test_ollama_single_frame_with_extra_fields() {
+        let body = r#"{"model":"codellama","created_at":"2024-01-01T00:00:00Z","response":"ls -la","done":true,"context":[1,2,3],"total_duration":1500000000,"load_duration":1000,"prompt_eval_count":12,"eval_count":34,"some_new_field":{"nested":true}}"#;
+
+        let (text, usage) = aggregate_ollama_frames(body).unwrap();
+        assert_eq!(text, "ls -la");
+
+        let usage = usage.unwrap();
+        assert_eq!(usage.total_duration, Some(std::time::Duration::from_millis(1500)));
+        assert_eq!(usage.prompt_eval_count, Some(12));
+        assert_eq!(usage.eval_count, Some(34));
+    }
+
+    #[test]
+    fn test_ollama_streamed_frames_with_empty_final_frame() {
+        let body = concat!(
+            "{\"response\":\"ls\",\"done\":false}\n",
+            "{\"response\":\" -la\",\"done\":false}\n",
+            "{\"response\":\"\",\"done\":true,\"eval_count\":2}\n",
+        );
+
+        let (text, usage) = aggregate_ollama_frames(body).unwrap();
+        assert_eq!(text, "ls -la");
+        assert_eq!(usage.unwrap().eval_count, Some(2));
+
+        // A response cut off before the final frame has no usage
+        let (text, usage) = aggregate_ollama_frames("{\"response\":\"ls\"}").unwrap();
+        assert_eq!(text, "ls");
+        assert_eq!(usage, None);
+    }
+
+    #[test]
+    fn test_ollama_error_frame() {
+        let body = concat!(
+            "{\"response\":\"ls\",\"done\":false}\n",
+            "{\"error\":\"an error was encountered while running the model\"}\n",
+        );
+        match aggregate_ollama_frames(body) {
+            Err(LLMError::LocalModelError(message)) => {
+                assert!(message.contains("while running the model"), "{}", message)
+            }
+            other => panic!("expected LocalModelError, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ollama_error_status() {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let body = r#"{"error":"model \"nope\" not found, try pulling it first"}"#;
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            let response = format!(
+                "HTTP/1.1 404 Not Found\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        let provider = OllamaProvider {
+            api_url: format!("http://127.0.0.1:{}/api/generate", port),
+            ..OllamaProvider::new("nope")
+        };
+
+        match provider.generate("list files").await {
+            Err(LLMError::LocalModelError(message)) => {
+                assert!(message.contains("404"), "{}", message);
+                assert!(message.contains("try pulling it first"), "{}", message);
+            }
+            other => panic!("expected LocalModelError, got {:?}", other),
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_openai_request_sampling_settings() {
+        let provider = test_openai_provider();
+
+        // Without settings the body is what it always was
+        let body = serde_json::to_value(provider.request("list files", false)).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "model": "gpt-4o-mini",
+                "messages": [{"role": "user", "content": "list files"}],
+            })
+        );
+
+        let provider =
+            provider.with_temperature(Some(0.25)).with_top_p(Some(0.5)).with_max_tokens(Some(256));
+        let body = serde_json::to_value(provider.request("list files", true)).unwrap();
+        assert_eq!(body["temperature"], 0.25);
+        assert_eq!(body["top_p"], 0.5);
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(body["stream"], true);
+
+        // Compared as text, since parsing would hide a field that appears twice
+        let extra = serde_json::json!({"presence_penalty": 0.5});
+        let provider = provider.with_extra_options(extra.as_object().unwrap().clone());
+        let body = serde_json::to_string(&provider.request("list files", false)).unwrap();
+        assert_eq!(
+            body,
+            r#"{"model":"gpt-4o-mini","messages":[{"role":"user","content":"list files"}],"temperature":0.25,"top_p":0.5,"max_tokens":256,"presence_penalty":0.5}"#
+        );
+    }
+
+    #[test]
+    fn test_ollama_request_extra_options() {
+        let provider = OllamaProvider::new("codellama");
+        let body = serde_json::to_value(provider.request("list files", false)).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"model": "codellama", "prompt": "list files", "stream": false})
+        );
+
+        // Compared as text, since parsing would hide a field that appears twice
+        let extra = serde_json::json!({"seed": 42, "options": {"repeat_penalty": 1.1}});
+        let provider = provider.with_extra_options(extra.as_object().unwrap().clone());
+        let body = serde_json::to_string(&provider.request("list files", true)).unwrap();
+        assert_eq!(
+            body,
+            r#"{"model":"codellama","prompt":"list files","stream":true,"options":{"repeat_penalty":1.1},"seed":42}"#
+        );
+    }
+
+    #[test]
+    fn test_ollama_stream_decoding() {
+        let body = concat!(
+            "{\"response\":\"{\\\"command\\\": \",\"done\":false}\n",
+            "{\"response\":\"\\\"ls — all\\\"\",\"done\":false}\n\n",
+            "{\"response\":\", \\\"explanation\\\": \\\"\\\"}\",\"done\":false}\n",
+            "{\"response\":\"\",\"done\":true,\"eval_count\":3}\n",
+        );
+
+        // Split the body into chunks of every size, including mid-line and mid-character
+        for size in 1..body.len() {
+            let mut decoder = LineDecoder::new(decode_ollama_line);
+            let mut fragments = Vec::new();
+            for chunk in body.as_bytes().chunks(size) {
+                fragments.extend(decoder.push(chunk).unwrap());
+            }
+            fragments.extend(decoder.finish().unwrap());
+
+            let text = fragments.concat();
+            assert_eq!(fragments.len(), 3, "size {}", size);
+            assert_eq!(text, r#"{"command": "ls — all", "explanation": ""}"#, "size {}", size);
+            assert_eq!(decoder.state.unwrap().eval_count, Some(3));
+            assert!(crate::parser::parse_response(&text).is_ok());
+        }
+
+        let mut decoder = LineDecoder::new(decode_ollama_line);
+        assert!(decoder.push(b"{not json}\n").is_err());
+    }
+
+    #[test]
+    fn test_openai_stream_decoding() {
+        let body = concat!(
+            ": keep-alive\n\n",
+            "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"{\\\"command\\\": \"}}]}\r\n\r\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"\\\"ls — all\\\"\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":",
+            "{\"content\":\", \\\"explanation\\\": \\\"\\\"}\"}}]}\n\n",
+            "data: [DONE]\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"ignored\"}}]}\n\n",
+        );
+
+        // Split the body into chunks of every size, including mid-line and mid-character
+        for size in 1..body.len() {
+            let mut decoder = LineDecoder::new(decode_openai_line);
+            let mut text = String::new();
+            for chunk in body.as_bytes().chunks(size) {
+                text.extend(decoder.push(chunk).unwrap());
+            }
+            text.extend(decoder.finish().unwrap());
+
+            assert_eq!(text, r#"{"command": "ls — all", "explanation": ""}"#, "size {}", size);
+            assert!(crate::parser::parse_response(&text).is_ok());
+        }
+
+        // A final event without a trailing line break is still decoded
+        let mut decoder = LineDecoder::new(decode_openai_line);
+        assert!(decoder
+            .push(b"data: {\"choices\":[{\"delta\":{\"content\":\"ls\"}}]}")
+            .unwrap()
+            .is_empty());
+        assert_eq!(decoder.finish().unwrap().as_deref(), Some("ls"));
+
+        let mut decoder = LineDecoder::new(decode_openai_line);
+        assert!(decoder.push(b"data: {not json}\n").is_err());
+    }
+
+    #[test]
+    fn test_chain_tiers_share_providers() {
+        let mut created = 0;
+        let chain = build_chain(&["ollama", "openai", "ollama"], |tier| {
+            created += 1;
+            (*tier == "ollama").then(|| LLMProvider::Ollama(OllamaProvider::new("codellama")))
+        });
+
+        // OpenAI failed to initialize; both Ollama tiers share one client
+        assert_eq!(created, 2);
+        assert_eq!(chain.len(), 2);
+        assert!(Arc::ptr_eq(&chain[0], &chain[1]));
+
+        assert!(Arc::ptr_eq(&LLMProvider::builtin_fallback(), &LLMProvider::builtin_fallback()));
+    }
+
+    #[tokio::test]
+    async fn test_ollama_connection_refused_is_not_running() {
+        // Reserve a free port, then close it so nothing is listening there
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let provider = OllamaProvider {
+            api_url: format!("http://127.0.0.1:{}/api/generate", port),
+            ..OllamaProvider::new("codellama")
+        };
+
+        match provider.generate("list files").await {
+            Err(LLMError::OllamaNotRunning(url)) => {
+                assert_eq!(url, format!("http://127.0.0.1:{}", port))
+            }
+            other => panic!("expected OllamaNotRunning, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ollama_empty_response_is_retried() {
+        use std::io::{Read, Write};
+
+        // Answer every request with an empty response, as Ollama does while loading a model
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let body = r#"{"model":"codellama","response":" ","done":true}"#;
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request).unwrap();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        let provider = OllamaProvider {
+            api_url: format!("http://127.0.0.1:{}/api/generate", port),
+            ..OllamaProvider::new("codellama")
+        };
+
+        match provider.generate("list files").await {
+            Err(LLMError::LocalModelError(message)) => {
+                assert!(message.contains("empty response"), "{}", message)
+            }
+            other => panic!("expected LocalModelError, got {:?}", other),
+        }
+        // Both attempts reached the server
+        server.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ollama_slow_stream_isnt_cut_off() {
+        use std::io::{Read, Write};
+
+        // Each frame arrives well within the timeout, but the whole response takes longer
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            let headers = "HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\n\
+                           Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n";
+            stream.write_all(headers.as_bytes()).unwrap();
+            let frames = [
+                "{\"response\":\"ls\",\"done\":false}\n",
+                "{\"response\":\" -la\",\"done\":false}\n",
+                "{\"response\":\"\",\"done\":true}\n",
+            ];
+            for frame in frames {
+                std::thread::sleep(Duration::from_millis(150));
+                let chunk = format!("{:x}\r\n{}\r\n", frame.len(), frame);
+                stream.write_all(chunk.as_bytes()).unwrap();
+            }
+            stream.write_all(b"0\r\n\r\n").unwrap();
+        });
+        let provider = OllamaProvider {
+            api_url: format!("http://127.0.0.1:{}/api/generate", port),
+            ..OllamaProvider::new("codellama")
+        }
+        .with_timeout(Duration::from_millis(300));
+
+        let text = provider.generate_stream("list files", &mut |_: &str| {}).await.unwrap();
+        assert_eq!(text, "ls -la");
+        server.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ollama_timeout() {
+        // Connections are queued but never answered
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let provider = OllamaProvider {
+            api_url: format!("http://127.0.0.1:{}/api/generate", port),
+            ..OllamaProvider::new("codellama")
+        }
+        .with_timeout(Duration::from_millis(200));
+
+        match provider.generate("list files").await {
+            Err(LLMError::Timeout(timeout)) => assert_eq!(timeout, Duration::from_millis(200)),
+            other => panic!("expected Timeout, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_debug_masks_api_key() {
+        let key = "sk-proj-0123456789abcdef";
+        let provider = OpenAIProvider { api_key: key.to_string(), ..test_openai_provider() };
+
+        for debug in [format!("{:?}", provider), format!("{:#?}", provider)] {
+            assert!(!debug.contains(key));
+            assert!(!debug.contains("0123456789abcdef"));
+            assert!(debug.contains("sk-***"));
+        }
+        assert_eq!(mask_api_key("not-an-openai-key"), "***");
+    }
+
+    #[test]
+    fn test_validate_api_key() {
+        let key = format!("sk-proj-{}", "a1B2c3D4".repeat(6));
+
+        // Surrounding whitespace is trimmed
+        assert_eq!(validate_api_key(&format!("{}\n", key)).unwrap(), key);
+
+        let error = |key: &str| validate_api_key(key).unwrap_err().to_string();
+        assert!(error(&format!("{} {}", &key[..20], &key[20..])).contains("whitespace"));
+        assert!(error("sk-proj-0123456789").contains("truncated"));
+        assert!(error(&format!("\"{}\"", key)).contains("'sk-'"));
+        assert!(error(&format!("{}\"", key)).contains("unexpected characters"));
+    }
+
+    #[test]
+    fn test_same_model() {
+        assert!(is_same_model("codellama:latest", "codellama"));
+        assert!(is_same_model("codellama:7b", "codellama:7b"));
+        assert!(!is_same_model("codellama:7b", "codellama"));
+        assert!(!is_same_model("llama2:latest", "codellama"));
+
+        let provider = OllamaProvider::new("codellama");
+        assert_eq!(provider.endpoint("tags"), "http://localhost:11434/api/tags");
+    }
+
+    #[test]
+    fn test_loopback_or_private() {
+        assert!(is_loopback_or_private("http://localhost:11434"));
+        assert!(is_loopback_or_private("http://127.0.0.1:11434/api/generate"));
+        assert!(is_loopback_or_private("http://10.0.0.5"));
+        assert!(is_loopback_or_private("http://192.168.1.20:11434"));
+        assert!(is_loopback_or_private("http://[::1]:11434"));
+        assert!(!is_loopback_or_private("https://api.openai.com"));
+        assert!(!is_loopback_or_private("https://api.anthropic.com/v1"));
+        assert!(!is_loopback_or_private("http://8.8.8.8"));
+        assert!(!is_loopback_or_private("not a url"));
+
+        // A remote Ollama server counts as online
+        let remote = LLMProvider::Ollama(OllamaProvider {
+            api_url: "https://ollama.example.com/api/generate".to_string(),
+            ..OllamaProvider::new("codellama")
+        });
+        assert!(remote.is_online());
+        assert!(!LLMProvider::Ollama(OllamaProvider::new("codellama")).is_online());
+    }
+
+    #[tokio::test]
+    async fn test_chain_reports_each_attempt() {
+        let chain = [replay("other prompt"), replay("list files"), replay("list files")];
+
+        let mut attempts = Vec::new();
+        let response = generate_with_chain_progress(&chain, "list files", &mut |attempt| {
+            attempts.push(attempt.clone())
+        })
+        .await
+        .unwrap();
+        assert_eq!(response, "{\"command\": \"ls\"}");
+
+        // The first provider fails and the second succeeds, so the third is never tried
+        assert_eq!(attempts.len(), 2);
+        assert!(!attempts[0].is_retry());
+        assert_eq!(attempts[1].describe(DEFAULT_RETRY_MESSAGE), "Retrying (2/3) via Replay…");
+
+        let mut count = 0;
+        let result = generate_with_chain_progress(&chain, "unknown", &mut |_| count += 1).await;
+        assert!(result.is_err());
+        assert_eq!(count, 3);
+    }
+
+    #[tokio::test]
+    async fn test_chain_falls_back_in_order() {
+        // Nothing listens on the port once the listener is dropped, so Ollama is refused
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let ollama = Arc::new(LLMProvider::Ollama(OllamaProvider {
+            api_url: format!("http://127.0.0.1:{}/api/generate", port),
+            ..OllamaProvider::new("codellama")
+        }));
+        let unrecorded = replay("other prompt");
+
+        let mut tried = Vec::new();
+        let chain = [ollama.clone(), unrecorded.clone(), replay("list files"), ollama.clone()];
+        let response = generate_with_chain_progress(&chain, "list files", &mut |attempt| {
+            tried.push(attempt.provider.clone())
+        })
+        .await
+        .unwrap();
+        assert_eq!(response, "{\"command\": \"ls\"}");
+        assert_eq!(tried, ["Ollama", "Replay", "Replay"]);
+
+        // When every provider fails, the error of the last one is reported
+        let result = generate_with_chain(&[ollama.clone(), unrecorded.clone()], "list files").await;
+        assert!(matches!(result, Err(LLMError::RecordingError(_))), "{:?}", result);
+        let result = generate_with_chain(&[unrecorded, ollama], "list files").await;
+        assert!(matches!(result, Err(LLMError::OllamaNotRunning(_))), "{:?}", result);
+
+        let result = generate_with_chain(&[], "list files").await;
+        assert!(matches!(result, Err(LLMError::Unknown(_))), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn test_interceptor() {
+        struct Uppercase;
+
+        impl RequestInterceptor for Uppercase {
+            fn on_prompt(&self, prompt: &str) -> String {
+                prompt.to_uppercase()
+            }
+        }
+
+        struct PassThrough;
+
+        impl RequestInterceptor for PassThrough {}
+
+        let replay = Arc::new(LLMProvider::Replay(ReplayProvider::from_recordings(vec![
+            RecordedResponse { prompt: "LIST FILES".to_string(), response: "ls".to_string() },
+        ])));
+        let intercepted = LLMProvider::Intercepted(Box::new(InterceptingProvider::new(
+            replay.clone(),
+            Arc::new(Uppercase),
+        )));
+        assert_eq!(intercepted.generate("list files").await.unwrap(), "ls");
+        assert_eq!(intercepted.generate_with_fallback("list files").await.unwrap(), "ls");
+        assert_eq!(intercepted.name(), "Replay (intercepted)");
+
+        // The default interceptor changes nothing
+        let passed = InterceptingProvider::new(replay, Arc::new(PassThrough));
+        assert!(passed.generate("list files").await.is_err());
+        assert_eq!(passed.generate("LIST FILES").await.unwrap(), "ls");
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("recording.json");
+
+        let replayed = ReplayProvider::from_recordings(vec![RecordedResponse {
+            prompt: "list files".to_string(),
+            response: "{\"command\": \"ls\"}".to_string(),
+        }]);
+        let recorder = RecordingProvider::new(
+            vec![Arc::new(LLMProvider::Replay(replayed))],
+            false,
+            path.clone(),
+        )
+        .unwrap();
+        assert_eq!(recorder.generate("list files").await.unwrap(), "{\"command\": \"ls\"}");
+        assert!(recorder.generate("unknown prompt").await.is_err());
+
+        let replay = ReplayProvider::load(&path).unwrap();
+        assert_eq!(replay.generate("list files").await.unwrap(), "{\"command\": \"ls\"}");
+        assert!(matches!(
+            replay.generate("list all files").await,
+            Err(LLMError::RecordingError(_))
+        ));
+    }
+
+    #[test]
+    fn test_find_env_file_walks_up_to_stop() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path();
+        let nested = root.join("project").join("src");
+        std::fs::create_dir_all(&nested).unwrap();
+
+        // Nothing found below the stop directory
+        assert_eq!(find_env_file(&nested, Some(root)), None);
+
+        // The nearest .env wins
+        std::fs::write(root.join(".env"), "A=1").unwrap();
+        assert_eq!(find_env_file(&nested, Some(root)), Some(root.join(".env")));
+        std::fs::write(root.join("project").join(".env"), "A=2").unwrap();
+        assert_eq!(find_env_file(&nested, Some(root)), Some(root.join("project").join(".env")));
+
+        // Directories above the stop directory are not searched
+        assert_eq!(find_env_file(&nested, Some(&nested)), None);
+    }
 }
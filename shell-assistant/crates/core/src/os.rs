@@ -0,0 +1,162 @@
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// The family of operating systems a machine belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OsFamily {
+    Linux,
+    MacOs,
+    Windows,
+    Other,
+}
+
+// Linux distributions (by os-release ID) and the first major version that ships `ip`
+// rather than `ifconfig` and `systemctl` rather than `service`
+const MODERN_SINCE: &[(&str, u32)] =
+    &[("ubuntu", 16), ("debian", 9), ("centos", 7), ("rhel", 7), ("fedora", 15)];
+
+/// The operating system and its version, so commands can be picked for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OsInfo {
+    pub family: OsFamily,
+    /// A lowercase identifier such as `ubuntu`, from `/etc/os-release` on Linux
+    pub id: Option<String>,
+    /// A readable name such as `Ubuntu` or `macOS`
+    pub name: Option<String>,
+    /// The version, such as `22.04` or `14.2`
+    pub version: Option<String>,
+}
+
+impl OsInfo {
+    pub fn new(family: OsFamily) -> Self {
+        OsInfo { family, id: None, name: None, version: None }
+    }
+
+    pub fn with_release(mut self, id: &str, name: &str, version: &str) -> Self {
+        self.id = Some(id.to_string());
+        self.name = Some(name.to_string());
+        self.version = Some(version.to_string());
+        self
+    }
+
+    fn with_version(mut self, version: Option<String>) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Returns true on Linux releases old enough to lack modern tools such as `ip` and
+    /// `systemctl`. Unknown distributions and versions are assumed to be modern.
+    pub fn is_legacy_linux(&self) -> bool {
+        if self.family != OsFamily::Linux {
+            return false;
+        }
+        let major = self.version.as_deref().and_then(|v| v.split('.').next()?.parse::<u32>().ok());
+        match (self.id.as_deref(), major) {
+            (Some(id), Some(major)) => MODERN_SINCE
+                .iter()
+                .any(|(distribution, since)| *distribution == id && major < *since),
+            _ => false,
+        }
+    }
+
+    /// Returns true on Linux releases that have `ip` and `systemctl`
+    pub fn is_modern_linux(&self) -> bool {
+        self.family == OsFamily::Linux && !self.is_legacy_linux()
+    }
+
+    /// A short description for prompts, e.g. `Ubuntu 22.04`
+    pub fn describe(&self) -> String {
+        let name = self.name.clone().unwrap_or_else(|| {
+            match self.family {
+                OsFamily::Linux => "Linux",
+                OsFamily::MacOs => "macOS",
+                OsFamily::Windows => "Windows",
+                OsFamily::Other => "Unknown OS",
+            }
+            .to_string()
+        });
+        match &self.version {
+            Some(version) => format!("{} {}", name, version),
+            None => name,
+        }
+    }
+}
+
+/// Detect the operating system this machine runs and its version. The result is cached,
+/// so it's cheap to call for every request.
+pub fn detect_os_version() -> OsInfo {
+    static DETECTED: OnceLock<OsInfo> = OnceLock::new();
+    DETECTED.get_or_init(detect).clone()
+}
+
+fn detect() -> OsInfo {
+    if cfg!(target_os = "linux") {
+        std::fs::read_to_string("/etc/os-release")
+            .map(|contents| parse_os_release(&contents))
+            .unwrap_or_else(|_| OsInfo::new(OsFamily::Linux))
+    } else if cfg!(target_os = "macos") {
+        OsInfo { name: Some("macOS".to_string()), ..OsInfo::new(OsFamily::MacOs) }
+            .with_version(command_output("sw_vers", &["-productVersion"]))
+    } else if cfg!(windows) {
+        // `ver` prints e.g. "Microsoft Windows [Version 10.0.19045.3803]"
+        let version = command_output("cmd", &["/C", "ver"]).and_then(|ver| {
+            let (_, rest) = ver.split_once("Version ")?;
+            Some(rest.trim_end_matches(']').to_string())
+        });
+        OsInfo { name: Some("Windows".to_string()), ..OsInfo::new(OsFamily::Windows) }
+            .with_version(version)
+    } else {
+        OsInfo::new(OsFamily::Other)
+    }
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !text.is_empty()).then_some(text)
+}
+
+// Read the ID, NAME and VERSION_ID fields of `/etc/os-release`
+fn parse_os_release(contents: &str) -> OsInfo {
+    let mut info = OsInfo::new(OsFamily::Linux);
+    for line in contents.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let value = Some(value.trim().trim_matches('"').to_string());
+        match key.trim() {
+            "ID" => info.id = value.map(|id| id.to_lowercase()),
+            "NAME" => info.name = value,
+            "VERSION_ID" => info.version = value,
+            _ => {}
+        }
+    }
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_os_release() {
+        let info = parse_os_release(
+            "NAME=\"Ubuntu\"\nVERSION_ID=\"22.04\"\nID=ubuntu\nPRETTY_NAME=\"Ubuntu 22.04.3 LTS\"\n",
+        );
+        assert_eq!(info, OsInfo::new(OsFamily::Linux).with_release("ubuntu", "Ubuntu", "22.04"));
+        assert_eq!(info.describe(), "Ubuntu 22.04");
+    }
+
+    #[test]
+    fn test_legacy_linux() {
+        let linux = |id, version| OsInfo::new(OsFamily::Linux).with_release(id, id, version);
+
+        assert!(linux("centos", "6.10").is_legacy_linux());
+        assert!(linux("ubuntu", "14.04").is_legacy_linux());
+        assert!(linux("ubuntu", "22.04").is_modern_linux());
+        // Unknown distributions and versions are assumed to be modern
+        assert!(linux("arch", "").is_modern_linux());
+        assert!(OsInfo::new(OsFamily::Linux).is_modern_linux());
+        assert!(!OsInfo::new(OsFamily::MacOs).is_modern_linux());
+    }
+}
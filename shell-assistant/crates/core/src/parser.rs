@@ -1,5 +1,10 @@
-use crate::llm::{LLMError, LLMProvider};
+use crate::llm::{generate_with_chain, LLMError, LLMProvider};
+use crate::shell::ShellKind;
+use futures::future::join_all;
 use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct LLMResponse {
@@ -7,25 +12,142 @@ pub struct LLMResponse {
     pub explanation: String,
 }
 
+const NO_EXPLANATION: &str = "No explanation provided";
+
+/// Default limit on the length of a command guessed from a free-form response
+pub const DEFAULT_MAX_FALLBACK_COMMAND_LEN: usize = 300;
+
+// How much of an unparseable response is shown in the error
+const MAX_RAW_RESPONSE_CHARS: usize = 500;
+
+/// Options controlling how the command is extracted from an LLM response
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Strip trailing `# comments` from the extracted command
+    pub strip_comments: bool,
+    /// Shell whose comment syntax is used when stripping comments
+    pub shell: ShellKind,
+    /// When the response isn't structured and its first line is taken as the command,
+    /// a first line longer than this (in characters) is a parse failure instead
+    pub max_fallback_command_len: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            strip_comments: true,
+            shell: ShellKind::host(),
+            max_fallback_command_len: DEFAULT_MAX_FALLBACK_COMMAND_LEN,
+        }
+    }
+}
+
 // Parse the raw LLM response into a structured format
 pub fn parse_response(response: &str) -> Result<(String, String), String> {
+    parse_response_with_options(response, &ParseOptions::default())
+}
+
+/// Parse the raw LLM response using the given options
+pub fn parse_response_with_options(
+    response: &str,
+    options: &ParseOptions,
+) -> Result<(String, String), String> {
     // First try to parse as JSON
-    match serde_json::from_str::<LLMResponse>(response) {
-        Ok(parsed) => return Ok((parsed.command, parsed.explanation)),
-        Err(_) => {
-            // If not JSON, try to extract command and explanation from text
-            // This handles cases where the LLM returns a non-JSON response
-            if let Some((command, explanation)) = extract_command_from_text(response) {
-                return Ok((command, explanation));
+    let (command, explanation) = match serde_json::from_str::<LLMResponse>(response) {
+        Ok(parsed) => (parsed.command, parsed.explanation),
+        // If not JSON, try to extract command and explanation from text
+        // This handles cases where the LLM returns a non-JSON response
+        Err(_) => extract_command_from_text(response, options.max_fallback_command_len)
+            .ok_or_else(|| {
+                // Don't flood the terminal with a runaway response
+                let raw: String = response.chars().take(MAX_RAW_RESPONSE_CHARS).collect();
+                let more = if raw.len() < response.len() { "..." } else { "" };
+                format!("Failed to parse LLM response. Raw response: {}{}", raw, more)
+            })?,
+    };
+
+    // A null byte can't be passed to a shell, and hints at a corrupted or malicious response
+    if command.contains('\0') {
+        return Err("The generated command contains a null byte, so it was rejected".to_string());
+    }
+
+    if !options.strip_comments {
+        return Ok((command, explanation));
+    }
+
+    let (command, comment) = strip_trailing_comment(&command, options.shell);
+    let explanation = match comment {
+        // Keep the model's explanation; only use the comment when there is none
+        Some(comment) if explanation.trim().is_empty() || explanation == NO_EXPLANATION => comment,
+        _ => explanation,
+    };
+
+    Ok((command, explanation))
+}
+
+/// Remove trailing comments (`ls -la  # list files`) from each line of a command.
+/// A `#` only starts a comment at the beginning of a word and outside of quotes,
+/// following the rules of the given shell. Returns the stripped command and the
+/// removed comment text, if any. Lines that are entirely comments are kept.
+pub fn strip_trailing_comment(command: &str, shell: ShellKind) -> (String, Option<String>) {
+    let mut lines = Vec::new();
+    let mut comments = Vec::new();
+
+    for line in command.lines() {
+        match find_comment_start(line, shell) {
+            Some(idx) if !line[..idx].trim().is_empty() => {
+                lines.push(line[..idx].trim_end());
+                let comment = line[idx..].trim_start_matches('#').trim();
+                if !comment.is_empty() {
+                    comments.push(comment);
+                }
+            }
+            _ => lines.push(line),
+        }
+    }
+
+    let comment = if comments.is_empty() { None } else { Some(comments.join(" ")) };
+    (lines.join("\n"), comment)
+}
+
+// Find the byte index where an unquoted comment starts in a single line
+fn find_comment_start(line: &str, shell: ShellKind) -> Option<usize> {
+    let escape = match shell {
+        ShellKind::Sh => '\\',
+        ShellKind::PowerShell => '`',
+    };
+
+    let mut in_single = false;
+    let mut in_double = false;
+    let mut escaped = false;
+    let mut prev: Option<char> = None;
+
+    for (idx, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == escape && !in_single {
+            escaped = true;
+        } else if c == '\'' && !in_double {
+            in_single = !in_single;
+        } else if c == '"' && !in_single {
+            in_double = !in_double;
+        } else if c == '#' && !in_single && !in_double {
+            let word_start = match prev {
+                None => true,
+                Some(p) => p.is_whitespace() || (shell == ShellKind::Sh && ";|&".contains(p)),
+            };
+            if word_start {
+                return Some(idx);
             }
         }
+        prev = Some(c);
     }
 
-    Err(format!("Failed to parse LLM response. Raw response: {}", response))
+    None
 }
 
 // Extract command and explanation from text format
-fn extract_command_from_text(text: &str) -> Option<(String, String)> {
+fn extract_command_from_text(text: &str, max_fallback_len: usize) -> Option<(String, String)> {
     // Look for patterns like "Command: xxx" and "Explanation: yyy"
     let mut command = None;
     let mut explanation = None;
@@ -71,14 +193,18 @@ fn extract_command_from_text(text: &str) -> Option<(String, String)> {
     if let (Some(cmd), Some(exp)) = (command, explanation) {
         Some((cmd, exp))
     } else {
-        // Fallback: treat first line as command, rest as explanation
+        // Fallback: treat first line as command, rest as explanation. A huge first line
+        // is prose or garbage rather than a command.
         let lines: Vec<&str> = text.lines().collect();
         if !lines.is_empty() {
             let cmd = lines[0].trim().to_string();
+            if cmd.chars().count() > max_fallback_len {
+                return None;
+            }
             let exp = if lines.len() > 1 {
                 lines[1..].join("\n").trim().to_string()
             } else {
-                "No explanation provided".to_string()
+                NO_EXPLANATION.to_string()
             };
             Some((cmd, exp))
         } else {
@@ -91,15 +217,61 @@ fn extract_command_from_text(text: &str) -> Option<(String, String)> {
 pub async fn generate_command(
     provider: &LLMProvider,
     prompt: &str,
+    options: &ParseOptions,
 ) -> Result<(String, String), LLMError> {
     let response = provider.generate_with_fallback(prompt).await?;
 
-    match parse_response(&response) {
+    match parse_response_with_options(&response, options) {
         Ok((command, explanation)) => Ok((command, explanation)),
         Err(e) => Err(LLMError::ParsingError(e)),
     }
 }
 
+// Call each LLM in the chain in order to generate a shell command from natural language
+pub async fn generate_command_with_chain(
+    providers: &[Arc<LLMProvider>],
+    prompt: &str,
+    options: &ParseOptions,
+) -> Result<(String, String), LLMError> {
+    let response = generate_with_chain(providers, prompt).await?;
+
+    parse_response_with_options(&response, options).map_err(LLMError::ParsingError)
+}
+
+/// Generate commands for many prompts, with at most `max_concurrent` requests in flight.
+/// Results are returned in the order of `prompts`, whichever request finishes first.
+pub async fn generate_commands(
+    providers: &[Arc<LLMProvider>],
+    use_chain: bool,
+    prompts: &[String],
+    options: &ParseOptions,
+    max_concurrent: usize,
+) -> Vec<Result<(String, String), LLMError>> {
+    run_limited(prompts, max_concurrent, |prompt| async move {
+        if use_chain {
+            generate_command_with_chain(providers, prompt, options).await
+        } else {
+            generate_command(&providers[0], prompt, options).await
+        }
+    })
+    .await
+}
+
+// Run `task` for every item with at most `limit` running at once, keeping the input order
+async fn run_limited<'a, T, R, F, Fut>(items: &'a [T], limit: usize, task: F) -> Vec<R>
+where
+    F: Fn(&'a T) -> Fut,
+    Fut: Future<Output = R>,
+{
+    let semaphore = Semaphore::new(limit.max(1));
+    let (semaphore, task) = (&semaphore, &task);
+    join_all(items.iter().map(|item| async move {
+        let _permit = semaphore.acquire().await.expect("the semaphore is never closed");
+        task(item).await
+    }))
+    .await
+}
+
 // Mock function to simulate LLM response (for testing)
 pub async fn mock_llm_call(_prompt: &str) -> Result<String, String> {
     // For the end-to-end test, always return the same response
@@ -116,3 +288,130 @@ pub async fn mock_llm_call(_prompt: &str) -> Result<String, String> {
         Err(e) => Err(format!("Failed to serialize response: {}", e)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_comment_sh() {
+        let (cmd, comment) = strip_trailing_comment("ls -la  # list files", ShellKind::Sh);
+        assert_eq!(cmd, "ls -la");
+        assert_eq!(comment.as_deref(), Some("list files"));
+
+        let (cmd, _) = strip_trailing_comment("cd /tmp;# go to tmp", ShellKind::Sh);
+        assert_eq!(cmd, "cd /tmp;");
+    }
+
+    #[test]
+    fn test_strip_comment_powershell() {
+        let (cmd, comment) =
+            strip_trailing_comment("Get-ChildItem -Force # show hidden", ShellKind::PowerShell);
+        assert_eq!(cmd, "Get-ChildItem -Force");
+        assert_eq!(comment.as_deref(), Some("show hidden"));
+    }
+
+    #[test]
+    fn test_hash_inside_quotes_or_words_is_kept() {
+        for (command, shell) in [
+            ("echo \"a # b\"", ShellKind::Sh),
+            ("grep '#include' main.c", ShellKind::Sh),
+            ("echo a#b", ShellKind::Sh),
+            ("echo \\# not a comment", ShellKind::Sh),
+            ("Write-Output \"a # b\"", ShellKind::PowerShell),
+            ("Write-Output 'it''s # fine'", ShellKind::PowerShell),
+            ("Write-Output `# not a comment", ShellKind::PowerShell),
+        ] {
+            assert_eq!(strip_trailing_comment(command, shell), (command.to_string(), None));
+        }
+    }
+
+    #[test]
+    fn test_parse_response_strips_comment_and_keeps_explanation() {
+        let options =
+            ParseOptions { strip_comments: true, shell: ShellKind::Sh, ..Default::default() };
+        let response = r#"{"command": "du -sh *  # sizes", "explanation": "Shows sizes"}"#;
+        let (cmd, exp) = parse_response_with_options(response, &options).unwrap();
+        assert_eq!(cmd, "du -sh *");
+        assert_eq!(exp, "Shows sizes");
+
+        // The comment is used when the model gave no explanation
+        let (cmd, exp) = parse_response_with_options("df -h # disk usage", &options).unwrap();
+        assert_eq!(cmd, "df -h");
+        assert_eq!(exp, "disk usage");
+
+        let options =
+            ParseOptions { strip_comments: false, shell: ShellKind::Sh, ..Default::default() };
+        let (cmd, _) = parse_response_with_options(response, &options).unwrap();
+        assert_eq!(cmd, "du -sh *  # sizes");
+    }
+
+    #[test]
+    fn test_null_byte_is_rejected() {
+        let response = r#"{"command": "ls\u0000; rm -rf ~", "explanation": "Lists files"}"#;
+        let err = parse_response(response).unwrap_err();
+        assert!(err.contains("null byte"), "{}", err);
+
+        // Other control characters are escaped when shown, not rejected
+        let response = r#"{"command": "ls \u001b[8mhidden", "explanation": "Lists files"}"#;
+        assert_eq!(parse_response(response).unwrap().0, "ls \x1b[8mhidden");
+    }
+
+    #[test]
+    fn test_huge_single_line_is_not_a_command() {
+        let response = "word ".repeat(2048);
+        let error = parse_response(&response).unwrap_err();
+        assert!(error.len() < 1000);
+
+        // Short free-form responses still fall back to the first line
+        let (cmd, _) = parse_response("ls -la\nLists all files").unwrap();
+        assert_eq!(cmd, "ls -la");
+
+        let options = ParseOptions { max_fallback_command_len: 3, ..Default::default() };
+        assert!(parse_response_with_options("ls -la", &options).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_run_limited_keeps_input_order() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        // Earlier items take longer, so they finish last
+        let delays = [40u64, 30, 20, 10];
+        let (running, peak) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let results = run_limited(&delays, 2, |&delay| {
+            let (running, peak) = (&running, &peak);
+            async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                delay
+            }
+        })
+        .await;
+
+        assert_eq!(results, delays);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_generate_commands_in_prompt_order() {
+        use crate::llm::{RecordedResponse, ReplayProvider};
+
+        let recordings = (0..5)
+            .map(|i| RecordedResponse {
+                prompt: format!("prompt {}", i),
+                response: format!(r#"{{"command": "echo {}", "explanation": "Prints {}."}}"#, i, i),
+            })
+            .collect();
+        let providers =
+            [Arc::new(LLMProvider::Replay(ReplayProvider::from_recordings(recordings)))];
+        let prompts: Vec<String> = (0..5).map(|i| format!("prompt {}", i)).collect();
+
+        let results =
+            generate_commands(&providers, false, &prompts, &ParseOptions::default(), 3).await;
+        let commands: Vec<String> = results.into_iter().map(|r| r.unwrap().0).collect();
+        assert_eq!(commands, ["echo 0", "echo 1", "echo 2", "echo 3", "echo 4"]);
+    }
+}
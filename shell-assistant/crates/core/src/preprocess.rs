@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+/// Transforms a request before it's matched against plugins and sent to the LLM
+pub trait InputPreprocessor {
+    /// Returns the name of the preprocessor
+    fn name(&self) -> &str;
+
+    /// Returns the transformed input
+    fn process(&self, input: &str) -> String;
+}
+
+/// Run the input through each preprocessor in order
+pub fn preprocess_input(preprocessors: &[Box<dyn InputPreprocessor>], input: &str) -> String {
+    preprocessors.iter().fold(input.to_string(), |input, preprocessor| preprocessor.process(&input))
+}
+
+/// Expands the user's own abbreviations, such as `k8s` → `kubernetes`. Only whole words
+/// are expanded, ignoring case and surrounding punctuation.
+pub struct AbbreviationExpander {
+    abbreviations: HashMap<String, String>,
+}
+
+impl AbbreviationExpander {
+    pub fn new(abbreviations: HashMap<String, String>) -> Self {
+        let abbreviations =
+            abbreviations.into_iter().map(|(short, long)| (short.to_lowercase(), long)).collect();
+        AbbreviationExpander { abbreviations }
+    }
+}
+
+impl InputPreprocessor for AbbreviationExpander {
+    fn name(&self) -> &str {
+        "abbreviations"
+    }
+
+    fn process(&self, input: &str) -> String {
+        if self.abbreviations.is_empty() {
+            return input.to_string();
+        }
+
+        input
+            .split(' ')
+            .map(|word| {
+                // Keep punctuation around the word, as in "(k8s)" or "img,"
+                let start = word.find(|c: char| c.is_alphanumeric()).unwrap_or(word.len());
+                let end = word
+                    .char_indices()
+                    .rev()
+                    .find(|(_, c)| c.is_alphanumeric())
+                    .map_or(start, |(i, c)| i + c.len_utf8());
+                match self.abbreviations.get(&word[start..end].to_lowercase()) {
+                    Some(long) => format!("{}{}{}", &word[..start], long, &word[end..]),
+                    None => word.to_string(),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expander() -> AbbreviationExpander {
+        AbbreviationExpander::new(HashMap::from([
+            ("k8s".to_string(), "kubernetes".to_string()),
+            ("img".to_string(), "image".to_string()),
+        ]))
+    }
+
+    #[test]
+    fn test_abbreviation_expansion() {
+        let expander = expander();
+
+        assert_eq!(expander.process("list k8s pods"), "list kubernetes pods");
+        assert_eq!(
+            expander.process("remove the img (K8S), now"),
+            "remove the image (kubernetes), now"
+        );
+        // Only whole words are expanded
+        assert_eq!(expander.process("show images"), "show images");
+
+        // Words ending in multi-byte characters are left alone, or expanded whole
+        assert_eq!(expander.process("ouvre le café…"), "ouvre le café…");
+        let expander = AbbreviationExpander::new(HashMap::from([(
+            "réseau".to_string(),
+            "network".to_string(),
+        )]));
+        assert_eq!(expander.process("show «réseau» stats"), "show «network» stats");
+    }
+
+    #[test]
+    fn test_preprocessors_run_in_order() {
+        struct StripWakeWord;
+
+        impl InputPreprocessor for StripWakeWord {
+            fn name(&self) -> &str {
+                "wake-word"
+            }
+
+            fn process(&self, input: &str) -> String {
+                input.strip_prefix("hey shell ").unwrap_or(input).to_string()
+            }
+        }
+
+        let preprocessors: Vec<Box<dyn InputPreprocessor>> =
+            vec![Box::new(StripWakeWord), Box::new(expander())];
+        assert_eq!(preprocess_input(&preprocessors, "hey shell pull img"), "pull image");
+        assert_eq!(preprocess_input(&[], "pull img"), "pull img");
+    }
+}
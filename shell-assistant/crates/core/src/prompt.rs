@@ -1,4 +1,20 @@
+use crate::os::OsInfo;
+use crate::shell::{Shell, TargetOs};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Errors that can occur while attaching a file to a request
+#[derive(Error, Debug)]
+pub enum AttachmentError {
+    #[error("Could not read attached file {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    #[error("Attached file {0} is binary; only text files can be attached")]
+    Binary(PathBuf),
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Prompt {
@@ -13,12 +29,173 @@ impl Prompt {
     }
 }
 
-// Construct a prompt for the LLM that asks it to generate a shell command
-pub fn construct_prompt(user_input: &str) -> String {
-    let os_type = if cfg!(windows) { "Windows PowerShell" } else { "Unix/Linux bash" };
+/// A command the user previously corrected for a similar request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Correction {
+    /// The natural language request
+    pub input: String,
+    /// The command the user corrected the generated one to
+    pub command: String,
+}
+
+/// A generated command that failed when it was executed
+#[derive(Debug, Clone, PartialEq)]
+pub struct FailedAttempt {
+    pub command: String,
+    /// The command's error output
+    pub error: String,
+}
+
+/// A text file the user attached as context for the request
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttachedFile {
+    /// The name the file is shown under in the prompt
+    pub name: String,
+    pub contents: String,
+}
+
+impl AttachedFile {
+    /// Read a file to attach, rejecting binary files
+    pub fn read(path: &Path) -> Result<Self, AttachmentError> {
+        let bytes = fs::read(path).map_err(|e| AttachmentError::Io(path.to_path_buf(), e))?;
+        if bytes.contains(&0) {
+            return Err(AttachmentError::Binary(path.to_path_buf()));
+        }
+        let contents =
+            String::from_utf8(bytes).map_err(|_| AttachmentError::Binary(path.to_path_buf()))?;
+
+        Ok(AttachedFile { name: path.display().to_string(), contents })
+    }
+}
+
+// Keep long error output and attached files from crowding out the rest of the prompt
+const MAX_FAILURE_CHARS: usize = 500;
+const MAX_FILE_CHARS: usize = 4000;
+
+// The last `max` characters of `text`
+fn tail_chars(text: &str, max: usize) -> &str {
+    match text.char_indices().nth_back(max - 1) {
+        Some((start, _)) if start > 0 => &text[start..],
+        _ => text,
+    }
+}
+
+// The first `max` characters of `text`, and whether anything was cut off
+fn head_chars(text: &str, max: usize) -> (&str, bool) {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => (&text[..end], true),
+        None => (text, false),
+    }
+}
+
+/// Extra context included in the prompt
+#[derive(Debug, Clone, Default)]
+pub struct PromptContext {
+    /// Prior user corrections for similar requests, used as few-shot examples
+    pub corrections: Vec<Correction>,
+    /// The previous command of the session, if it failed, so the model can self-correct
+    pub previous_failure: Option<FailedAttempt>,
+    /// Files the user attached for the model to refer to
+    pub files: Vec<AttachedFile>,
+    /// Ask the model to avoid privilege escalation where possible
+    pub prefer_no_sudo: bool,
+    /// Generate commands for this OS instead of the one the assistant runs on
+    pub target_os: Option<TargetOs>,
+    /// The shell commands are written for, named in the prompt. Defaults to bash, or
+    /// PowerShell for Windows.
+    pub shell: Option<Shell>,
+    /// House style or constraints from `llm.persona`, placed before the instructions
+    pub persona: Option<String>,
+    /// The OS version commands will run on, so the model can pick modern or legacy tools
+    pub os: Option<OsInfo>,
+    /// A model-specific template from `llm.prompt_templates`, used instead of the built-in
+    /// instructions. See `template_for_model`.
+    pub template: Option<String>,
+}
+
+// Construct a prompt for the LLM that asks it to generate a command for `shell`
+pub fn construct_prompt(user_input: &str, shell: Shell) -> String {
+    construct_prompt_with_context(
+        user_input,
+        &PromptContext { shell: Some(shell), ..Default::default() },
+    )
+}
+
+/// Construct a prompt that also includes the given context
+pub fn construct_prompt_with_context(user_input: &str, context: &PromptContext) -> String {
+    let os = context.target_os.unwrap_or_else(TargetOs::host);
+    let shell = context.shell.unwrap_or_else(|| Shell::default_for(os));
+    let os_name = match os {
+        TargetOs::Windows => "Windows",
+        TargetOs::Unix => "Unix/Linux",
+    };
+    let os_type = format!("{} {}", os_name, shell.name());
+
+    let mut examples = String::new();
+    if !context.corrections.is_empty() {
+        examples.push_str(
+            "\nPreviously, for similar requests, the user corrected the command. Prefer these:\n",
+        );
+        for correction in &context.corrections {
+            examples.push_str(&format!(
+                "- For \"{}\" the correct command was: {}\n",
+                correction.input, correction.command
+            ));
+        }
+    }
+
+    if let Some(failure) = &context.previous_failure {
+        let error = tail_chars(failure.error.trim(), MAX_FAILURE_CHARS);
+        examples.push_str(&format!(
+            "\nThe previous attempt `{}` failed with: `{}`\nAvoid repeating that mistake.\n",
+            failure.command, error
+        ));
+    }
+
+    if let Some(os) = &context.os {
+        examples.push_str(&format!(
+            "\nThe user's system is {}. Use commands and options available on it.\n",
+            os.describe()
+        ));
+    }
+
+    if context.prefer_no_sudo {
+        examples.push_str(
+            "\nAvoid sudo and other privilege escalation unless the task cannot be done without it. \
+             If elevation is genuinely required, say so in the explanation.\n",
+        );
+    }
+
+    if !context.files.is_empty() {
+        examples.push_str("\nThe user attached these files. Refer to them where relevant:\n");
+        for file in &context.files {
+            let (contents, truncated) = head_chars(&file.contents, MAX_FILE_CHARS);
+            examples.push_str(&format!("--- BEGIN FILE {} ---\n", file.name));
+            examples.push_str(contents.trim_end());
+            if truncated {
+                examples.push_str(&format!("\n[truncated after {} characters]", MAX_FILE_CHARS));
+            }
+            examples.push_str(&format!("\n--- END FILE {} ---\n", file.name));
+        }
+    }
+
+    let persona = match context.persona.as_deref().map(str::trim) {
+        Some(persona) if !persona.is_empty() => format!("{}\n\n", persona),
+        _ => String::new(),
+    };
+
+    if let Some(template) = &context.template {
+        let context = format!("{}{}", persona, examples);
+        return fill_template(template, |name| match name {
+            "user_input" => Some(user_input),
+            "os_type" => Some(os_type.as_str()),
+            "context" => Some(context.trim()),
+            _ => None,
+        });
+    }
 
     format!(
-        r#"You are a shell command assistant. Convert the following natural language query into a {os_type} command.
+        r#"{persona}You are a shell command assistant. Convert the following natural language query into a {os_type} command.
 Your response must be in this JSON format:
 {{
   "command": "the actual shell command",
@@ -26,10 +203,264 @@ Your response must be in this JSON format:
 }}
 
 The command should be valid for {os_type}. Do not include any markdown formatting, just return valid JSON.
-
+{examples}
 USER QUERY: {user_input}
 "#,
+        persona = persona,
         os_type = os_type,
+        examples = examples,
         user_input = user_input
     )
 }
+
+/// The template for `model` from `templates`, which are keyed by model name prefix, e.g.
+/// `llama` for `llama3:8b`. Prefixes match case-insensitively and the longest one wins.
+pub fn template_for_model<'a>(
+    templates: &'a HashMap<String, String>,
+    model: &str,
+) -> Option<&'a str> {
+    let model = model.to_lowercase();
+    templates
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(&prefix.to_lowercase()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, template)| template.as_str())
+}
+
+/// Construct a prompt with the template for `model`, or the default prompt if none matches.
+/// Templates fill in `{user_input}`, `{os_type}` (e.g. "Unix/Linux bash") and `{context}`.
+pub fn construct_prompt_for_model(
+    user_input: &str,
+    model: &str,
+    templates: &HashMap<String, String>,
+) -> String {
+    let context = PromptContext {
+        template: template_for_model(templates, model).map(str::to_string),
+        ..Default::default()
+    };
+    construct_prompt_with_context(user_input, &context)
+}
+
+// Replace each `{name}` in the template that `value` knows. Values are inserted in one pass,
+// so a request containing `{context}` stays as it is.
+fn fill_template<'a>(template: &str, value: impl Fn(&str) -> Option<&'a str>) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let placeholder = rest[start + 1..]
+            .find('}')
+            .and_then(|len| value(&rest[start + 1..start + 1 + len]).map(|value| (len, value)));
+        match placeholder {
+            Some((len, value)) => {
+                filled.push_str(value);
+                rest = &rest[start + len + 2..];
+            }
+            None => {
+                filled.push('{');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// Construct a prompt that asks the LLM to explain an existing command
+pub fn construct_explanation_prompt(command: &str) -> String {
+    let os_type = if cfg!(windows) { "Windows PowerShell" } else { "Unix/Linux bash" };
+
+    format!(
+        r#"You are a shell command assistant. Explain what the following {os_type} command does, including what each part and flag is for and anything the user should be careful about.
+Your response must be in this JSON format:
+{{
+  "command": "the command, unchanged",
+  "explanation": "the explanation"
+}}
+
+Do not include any markdown formatting, just return valid JSON.
+
+COMMAND: {command}
+"#,
+        os_type = os_type,
+        command = command
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_model_specific_template() {
+        let templates = HashMap::from([
+            (
+                "wizardcoder".to_string(),
+                "### Instruction:\nWrite a {os_type} command as JSON with \"command\" and \
+                 \"explanation\" for: {user_input}\n### Response:"
+                    .to_string(),
+            ),
+            ("llama".to_string(), "Llama: {user_input}".to_string()),
+            ("llama3".to_string(), "Llama 3: {user_input} {unknown}".to_string()),
+        ]);
+
+        let prompt = construct_prompt_for_model("list {context} files", "wizardcoder", &templates);
+        assert!(prompt.starts_with("### Instruction:\nWrite a "), "{}", prompt);
+        assert!(prompt.ends_with("for: list {context} files\n### Response:"), "{}", prompt);
+
+        // The longest matching prefix wins, whatever the case
+        assert_eq!(
+            construct_prompt_for_model("ls", "Llama3:8b", &templates),
+            "Llama 3: ls {unknown}"
+        );
+        assert_eq!(construct_prompt_for_model("ls", "llama2", &templates), "Llama: ls");
+
+        // Other models get the default prompt
+        assert_eq!(
+            construct_prompt_for_model("ls", "codellama", &templates),
+            construct_prompt("ls", Shell::default_for(TargetOs::host()))
+        );
+    }
+
+    #[test]
+    fn test_prompt_includes_corrections() {
+        let context = PromptContext {
+            corrections: vec![Correction {
+                input: "show disk usage".to_string(),
+                command: "du -sh .".to_string(),
+            }],
+            ..Default::default()
+        };
+
+        let prompt = construct_prompt_with_context("show disk usage here", &context);
+        assert!(prompt.contains("For \"show disk usage\" the correct command was: du -sh ."));
+        assert!(prompt.contains("USER QUERY: show disk usage here"));
+
+        assert!(!construct_prompt("show disk usage", Shell::Bash).contains("corrected"));
+    }
+
+    #[test]
+    fn test_explanation_prompt() {
+        let prompt = construct_explanation_prompt("git log --oneline -5");
+        assert!(prompt.contains("COMMAND: git log --oneline -5"));
+        assert!(prompt.contains("\"explanation\""));
+    }
+
+    #[test]
+    fn test_prompt_includes_previous_failure() {
+        let context = PromptContext {
+            previous_failure: Some(FailedAttempt {
+                command: "ls --colour".to_string(),
+                error: "ls: unrecognized option '--colour'\n".to_string(),
+            }),
+            ..Default::default()
+        };
+
+        let prompt = construct_prompt_with_context("list files with colors", &context);
+        assert!(prompt.contains(
+            "The previous attempt `ls --colour` failed with: `ls: unrecognized option '--colour'`"
+        ));
+
+        // Only the tail of long error output is kept
+        let context = PromptContext {
+            previous_failure: Some(FailedAttempt {
+                command: "make".to_string(),
+                error: format!("{}END", "x".repeat(2000)),
+            }),
+            ..Default::default()
+        };
+        let prompt = construct_prompt_with_context("build it", &context);
+        assert!(prompt.contains("END`"));
+        assert!(!prompt.contains(&"x".repeat(MAX_FAILURE_CHARS)));
+    }
+
+    #[test]
+    fn test_prompt_prefers_no_sudo() {
+        let context = PromptContext { prefer_no_sudo: true, ..Default::default() };
+        let prompt = construct_prompt_with_context("install htop", &context);
+        assert!(prompt.contains("Avoid sudo and other privilege escalation"));
+
+        assert!(!construct_prompt("install htop", Shell::Bash).contains("Avoid sudo"));
+    }
+
+    #[test]
+    fn test_prompt_for_target_os() {
+        let context = PromptContext { target_os: Some(TargetOs::Windows), ..Default::default() };
+        let prompt = construct_prompt_with_context("list files", &context);
+        assert!(prompt.contains("Windows PowerShell"));
+        assert!(!prompt.contains("Unix/Linux bash"));
+
+        let context = PromptContext { target_os: Some(TargetOs::Unix), ..Default::default() };
+        assert!(construct_prompt_with_context("list files", &context).contains("Unix/Linux bash"));
+    }
+
+    #[test]
+    fn test_prompt_names_shell() {
+        let prompt = construct_prompt("set a variable", Shell::Fish);
+        assert!(prompt.contains("into a Unix/Linux fish command"), "{}", prompt);
+        assert!(construct_prompt("list files", Shell::Zsh).contains("Unix/Linux zsh"));
+
+        let context = PromptContext {
+            target_os: Some(TargetOs::Windows),
+            shell: Some(Shell::Cmd),
+            ..Default::default()
+        };
+        let prompt = construct_prompt_with_context("list files", &context);
+        assert!(prompt.contains("valid for Windows cmd.exe"), "{}", prompt);
+    }
+
+    #[test]
+    fn test_prompt_includes_os_version() {
+        let os =
+            OsInfo::new(crate::os::OsFamily::Linux).with_release("centos", "CentOS Linux", "6");
+        let context = PromptContext { os: Some(os), ..Default::default() };
+        let prompt = construct_prompt_with_context("show my ip", &context);
+        assert!(prompt.contains("The user's system is CentOS Linux 6."));
+
+        assert!(!construct_prompt("show my ip", Shell::Bash).contains("The user's system"));
+    }
+
+    #[test]
+    fn test_prompt_includes_persona() {
+        let context = PromptContext {
+            persona: Some("Always use long flag names.".to_string()),
+            ..Default::default()
+        };
+        let prompt = construct_prompt_with_context("list files", &context);
+        assert!(prompt.starts_with("Always use long flag names.\n\nYou are a shell command"));
+
+        assert!(!construct_prompt("list files", Shell::Bash).contains("long flag names"));
+        assert!(construct_prompt("list files", Shell::Bash).starts_with("You are a shell command"));
+    }
+
+    #[test]
+    fn test_prompt_includes_attached_files() {
+        let context = PromptContext {
+            files: vec![
+                AttachedFile {
+                    name: "build.log".to_string(),
+                    contents: "error: E0425\n".to_string(),
+                },
+                AttachedFile { name: "big.txt".to_string(), contents: "y".repeat(10_000) },
+            ],
+            ..Default::default()
+        };
+
+        let prompt = construct_prompt_with_context("fix the errors in this output", &context);
+        assert!(prompt
+            .contains("--- BEGIN FILE build.log ---\nerror: E0425\n--- END FILE build.log ---"));
+        assert!(prompt.contains(&format!("[truncated after {} characters]", MAX_FILE_CHARS)));
+        assert!(!prompt.contains(&"y".repeat(MAX_FILE_CHARS + 1)));
+    }
+
+    #[test]
+    fn test_binary_attachment_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("attachment.bin");
+        fs::write(&path, [0x7f, b'E', b'L', b'F', 0, 1]).unwrap();
+        assert!(matches!(AttachedFile::read(&path), Err(AttachmentError::Binary(_))));
+
+        fs::write(&path, "hello\n").unwrap();
+        assert_eq!(AttachedFile::read(&path).unwrap().contents, "hello\n");
+    }
+}
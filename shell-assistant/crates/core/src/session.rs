@@ -0,0 +1,82 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a session stays alive between invocations
+pub const SESSION_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// A working session that spans invocations made within `SESSION_TTL` of each other
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Session {
+    pub id: String,
+    /// Seconds since the Unix epoch of the last invocation in this session
+    pub last_used: u64,
+}
+
+impl Session {
+    /// Start a new session with a random ID
+    pub fn new() -> Self {
+        Session { id: format!("{:016x}", rand::thread_rng().gen::<u64>()), last_used: now() }
+    }
+
+    /// Resume the session stored at `path` if it was used within `ttl`, otherwise
+    /// (or with `force_new`) start a new one. The session is saved back to `path`;
+    /// failing to save only means the next invocation starts a new session.
+    pub fn resume_or_start(path: &Path, ttl: Duration, force_new: bool) -> Self {
+        let stored = if force_new {
+            None
+        } else {
+            fs::read_to_string(path)
+                .ok()
+                .and_then(|contents| serde_json::from_str::<Session>(&contents).ok())
+                .filter(|session| now().saturating_sub(session.last_used) <= ttl.as_secs())
+        };
+
+        let mut session = stored.unwrap_or_else(Session::new);
+        session.last_used = now();
+
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if let Ok(contents) = serde_json::to_string(&session) {
+            let _ = fs::write(path, contents);
+        }
+
+        session
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_reuse_and_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session");
+
+        let first = Session::resume_or_start(&path, SESSION_TTL, false);
+        let second = Session::resume_or_start(&path, SESSION_TTL, false);
+        assert_eq!(first.id, second.id);
+
+        let forced = Session::resume_or_start(&path, SESSION_TTL, true);
+        assert_ne!(forced.id, second.id);
+
+        // An expired session is replaced
+        let stale = Session { id: "stale".to_string(), last_used: now() - 3600 };
+        fs::write(&path, serde_json::to_string(&stale).unwrap()).unwrap();
+        assert_ne!(Session::resume_or_start(&path, SESSION_TTL, false).id, "stale");
+    }
+}
@@ -0,0 +1,373 @@
+use serde::{Deserialize, Serialize};
+
+/// The shell family a command is written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellKind {
+    /// POSIX-style shells (sh, bash, zsh)
+    Sh,
+    /// Windows PowerShell / PowerShell Core
+    PowerShell,
+}
+
+impl ShellKind {
+    /// The shell that commands are executed with on this platform
+    pub fn host() -> Self {
+        if cfg!(windows) {
+            ShellKind::PowerShell
+        } else {
+            ShellKind::Sh
+        }
+    }
+
+    /// A short name for messages
+    pub fn name(self) -> &'static str {
+        match self {
+            ShellKind::Sh => "sh",
+            ShellKind::PowerShell => "PowerShell",
+        }
+    }
+}
+
+/// The operating system commands are generated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum TargetOs {
+    Windows,
+    Unix,
+}
+
+impl TargetOs {
+    /// The operating system this build runs on
+    pub fn host() -> Self {
+        if cfg!(windows) {
+            TargetOs::Windows
+        } else {
+            TargetOs::Unix
+        }
+    }
+
+    /// The shell that commands for this OS are written for
+    pub fn shell(self) -> ShellKind {
+        match self {
+            TargetOs::Windows => ShellKind::PowerShell,
+            TargetOs::Unix => ShellKind::Sh,
+        }
+    }
+
+    /// A short name for messages
+    pub fn name(self) -> &'static str {
+        match self {
+            TargetOs::Windows => "Windows",
+            TargetOs::Unix => "Unix",
+        }
+    }
+}
+
+/// Parses OS names case-insensitively, e.g. `windows`
+impl std::str::FromStr for TargetOs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "windows" => Ok(TargetOs::Windows),
+            "unix" => Ok(TargetOs::Unix),
+            _ => Err(format!("unknown OS '{}' (expected windows or unix)", s)),
+        }
+    }
+}
+
+/// A shell commands are generated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    PowerShell,
+    Cmd,
+}
+
+impl Shell {
+    /// The user's shell. On Windows that is PowerShell when `PSModulePath` is set and cmd
+    /// otherwise; elsewhere it's the shell named by `SHELL`, or bash when that isn't one of
+    /// these.
+    pub fn detect() -> Self {
+        if cfg!(windows) {
+            return match std::env::var_os("PSModulePath") {
+                Some(_) => Shell::PowerShell,
+                None => Shell::Cmd,
+            };
+        }
+        Shell::detect_from(std::env::var("SHELL").ok().as_deref())
+    }
+
+    // The shell named by a `SHELL` value, or bash
+    fn detect_from(shell_var: Option<&str>) -> Self {
+        shell_var.and_then(Shell::from_path).unwrap_or(Shell::Bash)
+    }
+
+    /// The shell a program path such as `/usr/bin/zsh` or `pwsh.exe` runs, if it's one of
+    /// these
+    pub fn from_path(path: &str) -> Option<Self> {
+        let program = path.rsplit(['/', '\\']).next()?.to_lowercase();
+        match program.strip_suffix(".exe").unwrap_or(&program) {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            "pwsh" | "powershell" => Some(Shell::PowerShell),
+            "cmd" => Some(Shell::Cmd),
+            _ => None,
+        }
+    }
+
+    /// The shell commands for `os` are written for when the user's shell isn't known
+    pub fn default_for(os: TargetOs) -> Self {
+        match os {
+            TargetOs::Windows => Shell::PowerShell,
+            TargetOs::Unix => Shell::Bash,
+        }
+    }
+
+    /// The family of the shell's syntax, or None for cmd, which is neither
+    pub fn kind(self) -> Option<ShellKind> {
+        match self {
+            Shell::Bash | Shell::Zsh | Shell::Fish => Some(ShellKind::Sh),
+            Shell::PowerShell => Some(ShellKind::PowerShell),
+            Shell::Cmd => None,
+        }
+    }
+
+    /// A short name for messages and prompts
+    pub fn name(self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+            Shell::PowerShell => "PowerShell",
+            Shell::Cmd => "cmd.exe",
+        }
+    }
+}
+
+/// Parses shell names case-insensitively, e.g. `zsh` or `pwsh`
+impl std::str::FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Shell::from_path(s).ok_or_else(|| {
+            format!("unknown shell '{}' (expected bash, zsh, fish, powershell or cmd)", s)
+        })
+    }
+}
+
+// Verbs that start PowerShell cmdlet names, as in `Get-ChildItem`
+const POWERSHELL_VERBS: &[&str] = &[
+    "add",
+    "clear",
+    "compress",
+    "convertfrom",
+    "convertto",
+    "copy",
+    "expand",
+    "export",
+    "foreach",
+    "format",
+    "get",
+    "import",
+    "invoke",
+    "join",
+    "measure",
+    "move",
+    "new",
+    "out",
+    "remove",
+    "rename",
+    "resolve",
+    "restart",
+    "select",
+    "set",
+    "sort",
+    "split",
+    "start",
+    "stop",
+    "test",
+    "update",
+    "where",
+    "write",
+];
+
+// Programs and builtins that only exist in POSIX shells and Unix userlands
+const SH_PROGRAMS: &[&str] = &[
+    "apt", "apt-get", "awk", "brew", "chmod", "chown", "dnf", "export", "grep", "sed", "source",
+    "sudo", "xargs", "yum",
+];
+
+/// Guess which shell a command was written for from cmdlet names and syntax. Returns None
+/// when the command could be either (like `ls` or `git status`) or shows signs of both.
+pub fn apparent_shell(command: &str) -> Option<ShellKind> {
+    let words: Vec<&str> = command.split_whitespace().collect();
+
+    let powershell = words.iter().any(|word| is_cmdlet(word))
+        || command.contains("$env:")
+        || command.contains("$_")
+        || command.contains("$PSVersionTable");
+
+    let sh = command
+        .split(['|', ';', '&'])
+        .filter_map(|segment| segment.split_whitespace().next())
+        .any(|program| SH_PROGRAMS.contains(&program.rsplit('/').next().unwrap_or(program)))
+        || command.contains("/dev/null")
+        || command.contains("${");
+
+    match (powershell, sh) {
+        (true, false) => Some(ShellKind::PowerShell),
+        (false, true) => Some(ShellKind::Sh),
+        _ => None,
+    }
+}
+
+fn is_cmdlet(word: &str) -> bool {
+    let Some((verb, noun)) = word.split_once('-') else {
+        return false;
+    };
+    POWERSHELL_VERBS.contains(&verb.to_lowercase().as_str())
+        && noun.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+/// Quote an argument so the given shell passes it to the command as a single word,
+/// without expanding variables, globs or other metacharacters in it
+pub fn shell_quote(arg: &str, shell: ShellKind) -> String {
+    let is_plain = |c: char| match shell {
+        ShellKind::Sh => c.is_ascii_alphanumeric() || "_-./:@%+=,".contains(c),
+        ShellKind::PowerShell => c.is_ascii_alphanumeric() || "_-./:\\".contains(c),
+    };
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        return arg.to_string();
+    }
+
+    // Single quotes are literal in both shells; only the quote itself needs escaping.
+    // PowerShell also treats typographic single quotes as quotes, escaped by doubling.
+    let escaped = match shell {
+        ShellKind::Sh => arg.replace('\'', "'\\''"),
+        ShellKind::PowerShell => arg.chars().fold(String::new(), |mut escaped, c| {
+            if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+                escaped.push(c);
+            }
+            escaped.push(c);
+            escaped
+        }),
+    };
+    format!("'{}'", escaped)
+}
+
+/// Make a command safe to print: control characters, such as the escape sequences that
+/// move the cursor or hide text, are shown as escapes like `\x1b` instead of acting on the
+/// terminal, and so are the Unicode controls that reorder text. Newlines and tabs are kept.
+pub fn escape_control_chars(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        let reorders = matches!(c, '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}')
+            || matches!(c, '\u{2066}'..='\u{2069}');
+        if c == '\n' || c == '\t' || !(c.is_control() || reorders) {
+            escaped.push(c);
+        } else if (c as u32) < 0x80 {
+            escaped.push_str(&format!("\\x{:02x}", c as u32));
+        } else {
+            escaped.push_str(&format!("\\u{{{:x}}}", c as u32));
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("main.rs", ShellKind::Sh), "main.rs");
+        assert_eq!(shell_quote("src/main.rs", ShellKind::PowerShell), "src/main.rs");
+
+        assert_eq!(shell_quote("my file.txt", ShellKind::Sh), "'my file.txt'");
+        assert_eq!(shell_quote("my file.txt", ShellKind::PowerShell), "'my file.txt'");
+
+        assert_eq!(shell_quote("it's $HOME", ShellKind::Sh), "'it'\\''s $HOME'");
+        assert_eq!(shell_quote("it's $HOME", ShellKind::PowerShell), "'it''s $HOME'");
+        assert_eq!(shell_quote("it\u{2019}s", ShellKind::PowerShell), "'it\u{2019}\u{2019}s'");
+        assert_eq!(shell_quote("a;rm -rf /", ShellKind::Sh), "'a;rm -rf /'");
+        assert_eq!(shell_quote("", ShellKind::Sh), "''");
+    }
+
+    #[test]
+    fn test_escape_control_chars() {
+        // An escape sequence that would clear the line and hide the start of the command
+        assert_eq!(
+            escape_control_chars("rm -rf ~ \x1b[2K\rls -la"),
+            "rm -rf ~ \\x1b[2K\\x0dls -la"
+        );
+        assert_eq!(escape_control_chars("echo \u{202e}txt.exe"), "echo \\u{202e}txt.exe");
+        assert_eq!(
+            escape_control_chars("for f in *;\n\tdo ls; done"),
+            "for f in *;\n\tdo ls; done"
+        );
+    }
+
+    #[test]
+    fn test_shell_from_path() {
+        let cases = [
+            ("/bin/bash", Some(Shell::Bash)),
+            ("/usr/local/bin/bash", Some(Shell::Bash)),
+            ("/bin/zsh", Some(Shell::Zsh)),
+            ("/opt/homebrew/bin/fish", Some(Shell::Fish)),
+            ("/usr/bin/pwsh", Some(Shell::PowerShell)),
+            ("C:\\Windows\\System32\\cmd.exe", Some(Shell::Cmd)),
+            ("PowerShell", Some(Shell::PowerShell)),
+            ("/bin/sh", None),
+            ("", None),
+        ];
+        for (path, shell) in cases {
+            assert_eq!(Shell::from_path(path), shell, "{}", path);
+        }
+        assert_eq!("ZSH".parse::<Shell>(), Ok(Shell::Zsh));
+        assert!("tcsh".parse::<Shell>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shell_detect() {
+        for (value, shell) in [
+            ("/bin/bash", Shell::Bash),
+            ("/usr/bin/zsh", Shell::Zsh),
+            ("/usr/local/bin/fish", Shell::Fish),
+            ("/bin/dash", Shell::Bash),
+        ] {
+            assert_eq!(Shell::detect_from(Some(value)), shell, "{}", value);
+        }
+        assert_eq!(Shell::detect_from(None), Shell::Bash);
+    }
+
+    #[test]
+    fn test_apparent_shell() {
+        let powershell = [
+            "Get-ChildItem -Force",
+            "Get-Process | Where-Object { $_.CPU -gt 100 }",
+            "Write-Output $env:PATH",
+        ];
+        for command in powershell {
+            assert_eq!(apparent_shell(command), Some(ShellKind::PowerShell), "{}", command);
+        }
+
+        let sh = ["sudo apt-get update", "ps aux | grep nginx", "find . -name '*.log' 2>/dev/null"];
+        for command in sh {
+            assert_eq!(apparent_shell(command), Some(ShellKind::Sh), "{}", command);
+        }
+
+        // Commands that work in both, or options that merely contain a dash
+        for command in ["ls", "git status", "docker ps --format json", "git commit -m get-started"]
+        {
+            assert_eq!(apparent_shell(command), None, "{}", command);
+        }
+    }
+}
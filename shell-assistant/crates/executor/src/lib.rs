@@ -1,2 +1,9 @@
 pub mod shell;
-pub use shell::{ShellExecutor, UserAction};
+pub mod theme;
+pub mod wrap;
+pub use shell::{
+    decode_output, is_interactive_program, CommandOutput, DecodedOutput, SequenceAction,
+    SequenceStep, ShellExecutor, UserAction, DEFAULT_MAX_COMMAND_LINES,
+};
+pub use theme::{Role, Theme};
+pub use wrap::{output_width, wrap_command, WRAP_MARKER};
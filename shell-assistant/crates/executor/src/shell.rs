@@ -1,10 +1,22 @@
-use colored::*;
-use core::safety::CommandSafetyChecker;
+use console::measure_text_width;
+use core::safety::{explain_risk, CommandSafetyChecker, SafetyLevel};
+use core::shell::{apparent_shell, ShellKind};
 use dialoguer::{theme::ColorfulTheme, Confirm, Input, Select};
-use std::io;
-use std::process::Command;
+use std::fmt;
+use std::future::Future;
+use std::io::{self, IsTerminal};
+use std::process::{Command, ExitStatus};
 use storage::persistence::FeedbackType;
 
+use crate::theme::{Role, Theme};
+use crate::wrap::{output_width, wrap_command};
+
+// The width of the "Command: " label commands are shown after
+const COMMAND_LABEL_WIDTH: usize = 9;
+
+/// How many lines of a long command are shown before confirming, unless configured
+pub const DEFAULT_MAX_COMMAND_LINES: usize = 10;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum UserAction {
     Run,
@@ -20,8 +32,40 @@ pub enum FeedbackAction {
     Skip,
 }
 
+/// What to do with the rest of a sequence once one of its commands has run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SequenceAction {
+    Continue,
+    SkipNext,
+    AbortRemaining,
+}
+
+/// A command of a sequence that has just run, with more left to go
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SequenceStep {
+    /// Counting from 1
+    pub number: usize,
+    pub total: usize,
+    pub failed: bool,
+}
+
+impl SequenceStep {
+    /// The choice when nobody is asked: stop after a failure, as `&&` would
+    pub fn default_action(&self) -> SequenceAction {
+        if self.failed {
+            SequenceAction::AbortRemaining
+        } else {
+            SequenceAction::Continue
+        }
+    }
+}
+
 pub struct ShellExecutor {
     safety_checker: CommandSafetyChecker,
+    theme: Theme,
+    read_only: bool,
+    max_command_lines: usize,
+    compact: bool,
 }
 
 impl Default for ShellExecutor {
@@ -32,87 +76,365 @@ impl Default for ShellExecutor {
 
 impl ShellExecutor {
     pub fn new() -> Self {
-        ShellExecutor { safety_checker: CommandSafetyChecker::new() }
+        Self::with_safety_checker(CommandSafetyChecker::new())
+    }
+
+    /// Create an executor that uses a custom safety checker (e.g. with enterprise lists)
+    pub fn with_safety_checker(safety_checker: CommandSafetyChecker) -> Self {
+        ShellExecutor {
+            safety_checker,
+            theme: Theme::default(),
+            read_only: false,
+            max_command_lines: DEFAULT_MAX_COMMAND_LINES,
+            compact: false,
+        }
+    }
+
+    /// Use the given theme for colored output
+    pub fn with_theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    /// Only show commands: nothing is ever run or copied, whatever the action menu or
+    /// `--force` would otherwise allow. Stronger than a dry run, which still offers to run.
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Show at most this many lines of a wrapped command before asking what to do with it.
+    /// Longer commands can still be viewed in full from the action menu.
+    pub fn with_max_command_lines(mut self, max_command_lines: usize) -> Self {
+        self.max_command_lines = max_command_lines.max(1);
+        self
+    }
+
+    /// Show a command and its explanation on a single `command — explanation` line before
+    /// asking what to do with it. Multi-line commands are still shown in full.
+    pub fn with_compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
+    /// Whether commands are shown on one line with their explanation
+    pub fn is_compact(&self) -> bool {
+        self.compact
+    }
+
+    /// A command and its explanation as one `command — explanation` line, or None when the
+    /// command spans several lines or the line doesn't fit the output, so the command should
+    /// be shown wrapped instead
+    pub fn compact_line(&self, command: &str, explanation: &str) -> Option<String> {
+        self.compact_line_within(command, explanation, output_width())
+    }
+
+    fn compact_line_within(
+        &self,
+        command: &str,
+        explanation: &str,
+        width: usize,
+    ) -> Option<String> {
+        if command.contains('\n') {
+            return None;
+        }
+        let explanation = explanation.split_whitespace().collect::<Vec<_>>().join(" ");
+        let line =
+            format!("{} — {}", self.theme.command(command), self.theme.explanation(&explanation));
+        (measure_text_width(&line) <= width).then_some(line)
+    }
+
+    /// Whether commands are only shown, never run or copied
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Get the theme used for colored output
+    pub fn theme(&self) -> &Theme {
+        &self.theme
     }
 
-    pub async fn execute_command(&self, command: &str, dry_run: bool) -> io::Result<String> {
+    /// Get the safety checker used to assess commands
+    pub fn safety_checker(&self) -> &CommandSafetyChecker {
+        &self.safety_checker
+    }
+
+    pub async fn execute_command(&self, command: &str, dry_run: bool) -> io::Result<CommandOutput> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Command refused: read-only mode never runs commands",
+            ));
+        }
+        if command.contains('\0') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Command refused: it contains a null byte",
+            ));
+        }
+
+        // Blocked commands never run, whatever was confirmed or forced before. A dry run
+        // is refused the same way, so it shows what a real run would do.
+        let safety = self.safety_checker.check_command_detailed(command);
+        if safety.level == SafetyLevel::Blocked {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Command refused: {}", safety.reason.unwrap_or_default()),
+            ));
+        }
+
         if dry_run {
-            return Ok(format!("{} {}", "🔍 Dry run:".bright_blue(), command));
+            let rating = match &safety.reason {
+                Some(reason) => format!("{} ({})", safety.level, reason),
+                None => safety.level.to_string(),
+            };
+            return Ok(CommandOutput::message(format!(
+                "{} {}\n{} {}",
+                self.theme.info("🔍 Dry run:"),
+                self.theme.command(command),
+                self.theme.info("🛡️ Safety:"),
+                rating
+            )));
         }
 
-        println!("{} {}", "🚀 Executing:".bright_green(), command);
+        if is_interactive_program(command) {
+            return self.execute_interactive(command).await;
+        }
 
-        // Use cmd.exe on Windows
-        #[cfg(target_os = "windows")]
-        let output = Command::new("powershell.exe").args(["-Command", command]).output()?;
+        if runs_indefinitely(command) {
+            return self.execute_long_running(command).await;
+        }
+
+        println!("{} {}", self.theme.success("🚀 Executing:"), self.theme.command(command));
 
-        // Use sh on Unix-like systems
-        #[cfg(not(target_os = "windows"))]
-        let output = Command::new("sh").arg("-c").arg(command).output()?;
+        let output = shell_command(command).output()?;
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        let stdout = decode_output(&output.stdout).with_note();
+        let stderr = decode_output(&output.stderr).with_note();
 
         if output.status.success() {
-            Ok(stdout)
+            Ok(CommandOutput { stdout, stderr, exit_code: output.status.code() })
         } else {
-            Err(io::Error::new(io::ErrorKind::Other, stderr))
+            Err(command_failed(output.status, Some(stderr)))
         }
     }
 
-    pub fn prompt_for_action(
+    // Interactive programs misbehave with captured stdio, so either run them with the
+    // terminal attached or hand the command back for the user to run themselves
+    async fn execute_interactive(&self, command: &str) -> io::Result<CommandOutput> {
+        println!(
+            "{} {}",
+            self.theme.badge(Role::Warning, " ⚠️ INTERACTIVE:"),
+            self.theme.warning("This command needs an interactive terminal.")
+        );
+
+        let run_attached = io::stdin().is_terminal()
+            && run_blocking(|| {
+                Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Run it attached to this terminal?")
+                    .default(true)
+                    .interact()
+                    .unwrap_or(false)
+            })
+            .await?;
+
+        if !run_attached {
+            return Ok(CommandOutput::message(format!(
+                "{} {}",
+                self.theme.info("📋 Run it in your shell:"),
+                command
+            )));
+        }
+
+        println!("{} {}", self.theme.success("🚀 Executing:"), self.theme.command(command));
+        let status = shell_command(command).status()?;
+
+        if status.success() {
+            Ok(CommandOutput { exit_code: status.code(), ..CommandOutput::default() })
+        } else {
+            Err(command_failed(status, None))
+        }
+    }
+
+    // Capturing the output of a command that never exits would block forever, so it runs
+    // attached to the terminal until it exits or the user stops it with Ctrl-C
+    async fn execute_long_running(&self, command: &str) -> io::Result<CommandOutput> {
+        println!(
+            "{} {}",
+            self.theme.badge(Role::Warning, " ⏳ LONG-RUNNING:"),
+            self.theme.warning("This command runs until you stop it with Ctrl-C.")
+        );
+
+        println!("{} {}", self.theme.success("🚀 Executing:"), self.theme.command(command));
+        let mut child = tokio::process::Command::from(shell_command(command)).spawn()?;
+
+        // The command gets the Ctrl-C too; catching it here keeps the assistant running
+        let status = tokio::select! {
+            status = child.wait() => status?,
+            _ = tokio::signal::ctrl_c() => {
+                let status = child.wait().await?;
+                return Ok(CommandOutput {
+                    exit_code: status.code(),
+                    ..CommandOutput::message(self.theme.info("⏹️ Stopped.").to_string())
+                });
+            }
+        };
+
+        if status.success() {
+            Ok(CommandOutput { exit_code: status.code(), ..CommandOutput::default() })
+        } else {
+            Err(command_failed(status, None))
+        }
+    }
+
+    pub async fn prompt_for_action(
         &self,
         command: &str,
         explanation: &str,
         force: bool,
     ) -> io::Result<UserAction> {
-        println!("{}: {}", "Command".bright_green(), command);
-        println!("{}: {}", "Explanation".bright_green(), explanation);
+        let lines = command_lines(command);
+        let compact = self.compact.then(|| self.compact_line(command, explanation)).flatten();
+        let hidden = match compact {
+            Some(_) => 0,
+            None => lines.len().saturating_sub(self.max_command_lines),
+        };
+        if let Some(line) = compact {
+            println!("{}", line);
+        } else {
+            self.print_command_lines(&lines[..lines.len() - hidden]);
+            if hidden > 0 {
+                println!(
+                    "{:width$}{}",
+                    "",
+                    self.theme.info(&format!(
+                        "… {} more line{}",
+                        hidden,
+                        if hidden == 1 { "" } else { "s" }
+                    )),
+                    width = COMMAND_LABEL_WIDTH
+                );
+            }
+            println!(
+                "{}: {}",
+                self.theme.success("Explanation"),
+                self.theme.explanation(explanation)
+            );
+        }
 
         // Check if the command is potentially unsafe
-        let (is_unsafe, reason) = self.safety_checker.check_command(command);
+        let safety = self.safety_checker.check_command_detailed(command);
+        if safety.level == SafetyLevel::Blocked {
+            println!(
+                "\n{} {}",
+                self.theme.badge(Role::Error, " 🚫 BLOCKED:"),
+                self.theme.error(&safety.reason.unwrap_or_default())
+            );
+            return Ok(UserAction::Abort);
+        }
+
+        // Running a command written for the other shell only produces confusing errors
+        if let Some(shell) = apparent_shell(command).filter(|&shell| shell != ShellKind::host()) {
+            println!(
+                "\n{} {}",
+                self.theme.badge(Role::Warning, " ⚠️ WRONG SHELL:"),
+                self.theme.warning(&format!(
+                    "This looks like a {} command, but commands run with {} here.",
+                    shell.name(),
+                    ShellKind::host().name()
+                ))
+            );
+        }
+
+        let is_unsafe = safety.is_unsafe();
         if is_unsafe {
             println!(
                 "\n{} {}",
-                " ⚠️ WARNING:".on_yellow().black(),
-                "This command may be destructive!".yellow()
+                self.theme.badge(Role::Warning, " ⚠️ WARNING:"),
+                self.theme.warning("This command may be destructive!")
             );
-            if let Some(reason) = reason {
-                println!("{}: {}", "Reason".yellow(), reason);
+            if let Some(reason) = &safety.reason {
+                println!("{}: {}", self.theme.warning("Reason"), reason);
             }
-            println!("{}", "Please confirm you understand the risks.".yellow());
+            println!("{}", self.theme.warning("Please confirm you understand the risks."));
+        }
+
+        // Read-only mode has nothing to offer beyond showing the command
+        if self.read_only {
+            println!(
+                "\n{}",
+                self.theme.info("🔒 Read-only mode - commands are shown, never run or copied.")
+            );
+            return Ok(UserAction::Abort);
         }
 
         if force && !is_unsafe {
             // If force is enabled and the command is safe, execute without prompting
-            println!("{}", "🚀 Force mode enabled - executing without confirmation".bright_blue());
+            println!(
+                "{}",
+                self.theme.info("🚀 Force mode enabled - executing without confirmation")
+            );
             return Ok(UserAction::Run);
         }
 
-        let options = vec!["▶️  Run", "📋 Copy", "❌ Abort"];
-        let selection = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("Choose an action")
-            .default(0)
-            .items(&options)
-            .interact()
-            .unwrap_or(2); // Default to Abort if interaction fails
+        // Flagged commands get an extra option to explain the risk before choosing, and
+        // commands too long to show whole one to view them in full
+        let mut options = vec!["▶️  Run", "📋 Copy", "❌ Abort"];
+        let explainable = if is_unsafe { safety.reason_code } else { None };
+        let explain_option = explainable.map(|_| {
+            options.push("ℹ️  Explain risk");
+            options.len() - 1
+        });
+        let view_option = (hidden > 0).then(|| {
+            options.push("🔎 View full command");
+            options.len() - 1
+        });
+
+        let selection = loop {
+            let items = options.clone();
+            let selection = run_blocking(move || {
+                Select::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Choose an action")
+                    .default(0)
+                    .items(&items)
+                    .interact()
+                    .unwrap_or(2) // Default to Abort if interaction fails
+            })
+            .await?;
+
+            match (selection, explainable) {
+                (_, Some(reason_code)) if Some(selection) == explain_option => {
+                    println!("\n{}", self.theme.warning("Why this command was flagged:"));
+                    println!("{}\n", explain_risk(reason_code));
+                }
+                _ if Some(selection) == view_option => {
+                    println!();
+                    self.print_command_lines(&lines);
+                    println!();
+                }
+                _ => break selection,
+            }
+        };
 
         // If the command is unsafe, require double confirmation
         if is_unsafe && selection == 0 && !force {
             println!(
                 "\n{} {}",
-                " ⚠️ DOUBLE-CHECK:".on_red().black(),
-                "This command is potentially unsafe!".red()
+                self.theme.badge(Role::Error, " ⚠️ DOUBLE-CHECK:"),
+                self.theme.error("This command is potentially unsafe!")
             );
 
-            let confirm = Confirm::with_theme(&ColorfulTheme::default())
-                .with_prompt("Proceed anyway?")
-                .default(false)
-                .interact()
-                .unwrap_or(false);
+            let confirm = run_blocking(|| {
+                Confirm::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Proceed anyway?")
+                    .default(false)
+                    .interact()
+                    .unwrap_or(false)
+            })
+            .await?;
 
             if !confirm {
-                println!("{}", "Command execution aborted for safety.".bright_red());
+                println!("{}", self.theme.error("Command execution aborted for safety."));
                 return Ok(UserAction::Abort);
             }
         }
@@ -124,37 +446,152 @@ impl ShellExecutor {
         }
     }
 
-    pub fn prompt_for_feedback(
+    /// Run commands in order, calling `choose` after each one that has others after it to
+    /// decide whether to go on. Returns the combined output of the commands that ran, with the
+    /// exit code of the last one, or the error of the first one that failed.
+    pub async fn execute_sequence<F, Fut>(
+        &self,
+        commands: &[String],
+        dry_run: bool,
+        mut choose: F,
+    ) -> io::Result<CommandOutput>
+    where
+        F: FnMut(SequenceStep) -> Fut,
+        Fut: Future<Output = io::Result<SequenceAction>>,
+    {
+        let total = commands.len();
+        let mut outputs: Vec<CommandOutput> = Vec::new();
+        let mut first_error = None;
+        let mut skip = false;
+
+        for (i, command) in commands.iter().enumerate() {
+            if std::mem::take(&mut skip) {
+                println!("{} {}", self.theme.info("⏭️  Skipped:"), self.theme.command(command));
+                continue;
+            }
+
+            let failed = match self.execute_command(command, dry_run).await {
+                Ok(output) => {
+                    outputs.push(output);
+                    false
+                }
+                Err(e) => {
+                    println!(
+                        "{} {}",
+                        self.theme.error(&format!("❌ Step {} failed:", i + 1)),
+                        self.theme.error(&e.to_string())
+                    );
+                    first_error.get_or_insert(e);
+                    true
+                }
+            };
+
+            let remaining = total - i - 1;
+            if remaining == 0 {
+                break;
+            }
+            match choose(SequenceStep { number: i + 1, total, failed }).await? {
+                SequenceAction::Continue => {}
+                SequenceAction::SkipNext => skip = true,
+                SequenceAction::AbortRemaining => {
+                    println!(
+                        "{}",
+                        self.theme.warning(&format!(
+                            "⏹️  Aborted the remaining {} step{}.",
+                            remaining,
+                            if remaining == 1 { "" } else { "s" }
+                        ))
+                    );
+                    break;
+                }
+            }
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(CommandOutput {
+                stdout: outputs.iter().map(|o| o.stdout.as_str()).collect::<Vec<_>>().join("\n"),
+                stderr: outputs.iter().map(|o| o.stderr.as_str()).collect::<Vec<_>>().join("\n"),
+                exit_code: outputs.last().and_then(|o| o.exit_code),
+            }),
+        }
+    }
+
+    /// Ask whether to go on with a sequence after one of its commands. Aborting is the
+    /// default after a failure.
+    pub async fn prompt_for_next_step(&self, step: SequenceStep) -> io::Result<SequenceAction> {
+        let prompt = format!(
+            "Step {} of {} {}. What next?",
+            step.number,
+            step.total,
+            if step.failed { "failed" } else { "finished" }
+        );
+        let default = if step.failed { 2 } else { 0 };
+
+        let selection = run_blocking(move || {
+            Select::with_theme(&ColorfulTheme::default())
+                .with_prompt(prompt)
+                .default(default)
+                .items(&["▶️  Continue", "⏭️  Skip next", "⏹️  Abort remaining"])
+                .interact()
+                .unwrap_or(2) // Default to aborting if interaction fails
+        })
+        .await?;
+
+        match selection {
+            0 => Ok(SequenceAction::Continue),
+            1 => Ok(SequenceAction::SkipNext),
+            _ => Ok(SequenceAction::AbortRemaining),
+        }
+    }
+
+    // Print wrapped command lines, continuation lines lined up under the first
+    fn print_command_lines(&self, lines: &[String]) {
+        for (i, line) in lines.iter().enumerate() {
+            if i == 0 {
+                println!("{}: {}", self.theme.success("Command"), self.theme.command(line));
+            } else {
+                println!("{:width$}{}", "", self.theme.command(line), width = COMMAND_LABEL_WIDTH);
+            }
+        }
+    }
+
+    pub async fn prompt_for_feedback(
         &self,
         _command: &str,
     ) -> io::Result<(FeedbackAction, Option<String>)> {
-        println!("\n{}", "Was this command helpful?".bright_cyan());
+        println!("\n{}", self.theme.info("Was this command helpful?"));
 
-        let options = vec!["👍 Yes", "👎 No", "✏️  Edit", "⏭️  Skip"];
-        let selection = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("Your feedback")
-            .default(0)
-            .items(&options)
-            .interact()
-            .unwrap_or(3); // Default to Skip if interaction fails
+        let selection = run_blocking(|| {
+            Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Your feedback")
+                .default(0)
+                .items(&["👍 Yes", "👎 No", "✏️  Edit", "⏭️  Skip"])
+                .interact()
+                .unwrap_or(3) // Default to Skip if interaction fails
+        })
+        .await?;
 
         match selection {
             0 => Ok((FeedbackAction::Helpful, None)),
             1 => Ok((FeedbackAction::NotHelpful, None)),
             2 => {
-                println!("\n{}", "Please enter your corrected command:".bright_cyan());
+                println!("\n{}", self.theme.info("Please enter your corrected command:"));
 
-                let edited = Input::<String>::with_theme(&ColorfulTheme::default())
-                    .with_prompt(">")
-                    .allow_empty(true)
-                    .interact()
-                    .unwrap_or_default();
+                let edited = run_blocking(|| {
+                    Input::<String>::with_theme(&ColorfulTheme::default())
+                        .with_prompt(">")
+                        .allow_empty(true)
+                        .interact()
+                        .unwrap_or_default()
+                })
+                .await?;
 
                 if edited.is_empty() {
-                    println!("{}", "No changes made.".yellow());
+                    println!("{}", self.theme.warning("No changes made."));
                     Ok((FeedbackAction::Skip, None))
                 } else {
-                    println!("{}: {}", "Command updated".bright_green(), edited);
+                    println!("{}: {}", self.theme.success("Command updated"), edited);
                     Ok((FeedbackAction::Edit, Some(edited)))
                 }
             }
@@ -163,6 +600,79 @@ impl ShellExecutor {
     }
 }
 
+/// What `execute_command` got from a command that succeeded. It displays as the command's
+/// output.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    /// None if the command didn't run to completion, e.g. in a dry run or when it was left
+    /// for the user to run themselves
+    pub exit_code: Option<i32>,
+}
+
+impl fmt::Display for CommandOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.stdout)
+    }
+}
+
+impl CommandOutput {
+    // A message in place of output, for commands that weren't run here
+    fn message(message: String) -> Self {
+        CommandOutput { stdout: message, ..CommandOutput::default() }
+    }
+}
+
+/// The error of a command that ran but failed, carried inside the `io::Error` returned by
+/// `execute_command`. It displays as the command's error output.
+#[derive(Debug)]
+pub struct CommandFailed {
+    /// None if the command was stopped by a signal
+    pub exit_code: Option<i32>,
+    pub stderr: String,
+}
+
+impl fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.stderr)
+    }
+}
+
+impl std::error::Error for CommandFailed {}
+
+impl CommandFailed {
+    /// The failure inside an error from `execute_command`, if the command ran at all
+    pub fn from_io_error(error: &io::Error) -> Option<&CommandFailed> {
+        error.get_ref()?.downcast_ref::<CommandFailed>()
+    }
+}
+
+// The command wrapped to fit the terminal after the "Command: " label, with control
+// characters escaped so nothing in it can change what is shown
+fn command_lines(command: &str) -> Vec<String> {
+    let command = core::escape_control_chars(command);
+    wrap_command(&command, output_width().saturating_sub(COMMAND_LABEL_WIDTH), 2)
+}
+
+// Without error output, the exit status is the best description of what went wrong
+fn command_failed(status: ExitStatus, stderr: Option<String>) -> io::Error {
+    let stderr = stderr
+        .filter(|stderr| !stderr.trim().is_empty())
+        .unwrap_or_else(|| format!("Command exited with {}", status));
+    io::Error::other(CommandFailed { exit_code: status.code(), stderr })
+}
+
+/// Run a blocking terminal prompt on a blocking thread, so it doesn't stall other async
+/// work (e.g. spinners or streamed output) on the runtime thread
+pub async fn run_blocking<T, F>(prompt: F) -> io::Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(prompt).await.map_err(io::Error::other)
+}
+
 impl FeedbackAction {
     /// Convert FeedbackAction to storage's FeedbackType
     pub fn to_feedback_type(&self) -> FeedbackType {
@@ -174,3 +684,430 @@ impl FeedbackAction {
         }
     }
 }
+
+/// Command output decoded as UTF-8, with invalid bytes replaced by U+FFFD
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedOutput {
+    pub text: String,
+    /// How many bytes were not valid UTF-8
+    pub invalid_bytes: usize,
+}
+
+impl DecodedOutput {
+    /// Returns true if any bytes had to be replaced
+    pub fn lossy(&self) -> bool {
+        self.invalid_bytes > 0
+    }
+
+    /// The text, followed by a note on how many bytes were replaced if any were
+    pub fn with_note(self) -> String {
+        if !self.lossy() {
+            return self.text;
+        }
+
+        let mut text = self.text;
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str(&format!("(output contained {} non-UTF-8 bytes)", self.invalid_bytes));
+        text
+    }
+}
+
+/// Decode command output as UTF-8, counting the bytes that had to be replaced
+pub fn decode_output(bytes: &[u8]) -> DecodedOutput {
+    let mut text = String::with_capacity(bytes.len());
+    let mut invalid_bytes = 0;
+    let mut rest = bytes;
+
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                break;
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                // `valid_up_to` guarantees this prefix is valid UTF-8
+                text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                text.push(char::REPLACEMENT_CHARACTER);
+
+                // A missing error length means the input ends mid-sequence
+                let skip = e.error_len().unwrap_or(after.len());
+                invalid_bytes += skip;
+                rest = &after[skip..];
+            }
+        }
+    }
+
+    DecodedOutput { text, invalid_bytes }
+}
+
+// Build the platform shell invocation for a command
+fn shell_command(command: &str) -> Command {
+    // Use PowerShell on Windows
+    #[cfg(target_os = "windows")]
+    {
+        let mut cmd = Command::new("powershell.exe");
+        cmd.args(["-Command", command]);
+        cmd
+    }
+
+    // Use sh on Unix-like systems
+    #[cfg(not(target_os = "windows"))]
+    {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+}
+
+// Programs that always take over the terminal
+const INTERACTIVE_PROGRAMS: &[&str] = &[
+    "vi", "vim", "nvim", "nano", "emacs", "pico", "micro", "top", "htop", "btop", "atop", "less",
+    "more", "most", "man", "watch", "tmux", "screen", "mc", "ranger", "fzf", "tig", "ncdu",
+];
+
+// Programs that start an interactive session only when run without arguments
+const INTERACTIVE_WITHOUT_ARGS: &[&str] = &[
+    "python",
+    "python3",
+    "node",
+    "irb",
+    "ghci",
+    "psql",
+    "mysql",
+    "sqlite3",
+    "redis-cli",
+    "bash",
+    "sh",
+    "zsh",
+    "fish",
+    "pwsh",
+    "powershell",
+];
+
+// Single-letter ssh options that take a value
+const SSH_OPTIONS_WITH_VALUE: &str = "BbcDEeFIiJLlmOopQRSWw";
+
+/// Returns true if any part of the command (including pipeline stages) is a
+/// program that needs an interactive terminal, such as an editor, a pager,
+/// or `ssh` without a remote command.
+pub fn is_interactive_program(command: &str) -> bool {
+    command
+        .split(['|', ';', '&'])
+        .map(|segment| segment.split_whitespace().collect::<Vec<&str>>())
+        .any(|words| is_interactive_segment(&words))
+}
+
+fn is_interactive_segment(words: &[&str]) -> bool {
+    // Look past `sudo` and leading environment variable assignments
+    let words = match words.iter().position(|w| *w != "sudo" && !w.contains('=')) {
+        Some(start) => &words[start..],
+        None => return false,
+    };
+    let Some(program) = words.first() else {
+        return false;
+    };
+    let program = program.rsplit('/').next().unwrap_or(program);
+    let args = &words[1..];
+
+    if INTERACTIVE_PROGRAMS.contains(&program) {
+        return true;
+    }
+
+    if INTERACTIVE_WITHOUT_ARGS.contains(&program) {
+        return args.is_empty();
+    }
+
+    // `ssh host` opens a shell, `ssh host cmd` runs cmd remotely
+    if program == "ssh" {
+        let mut positional = 0;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix('-') {
+                // Options such as `-p 2222` take the following word as their value
+                Some(flag) if flag.len() == 1 && SSH_OPTIONS_WITH_VALUE.contains(flag) => {
+                    args.next();
+                }
+                Some(_) => {}
+                None => positional += 1,
+            }
+        }
+        return positional <= 1;
+    }
+
+    false
+}
+
+// Programs that keep running until they're interrupted
+const NEVER_EXITING_PROGRAMS: &[&str] = &["watch", "yes"];
+
+// Programs that run until interrupted when given a follow option
+const FOLLOWING_PROGRAMS: &[&str] = &["tail", "journalctl", "logs", "Get-Content"];
+
+// Options that make a command keep waiting for new output
+const FOLLOW_OPTIONS: &[&str] = &["-f", "-F", "--follow", "-Wait"];
+
+/// Returns true if any part of the command is known to run until interrupted, such as
+/// `tail -f`, `journalctl -f`, `docker logs -f`, `watch` or `ping` without a count.
+pub fn runs_indefinitely(command: &str) -> bool {
+    command
+        .split(['|', ';', '&'])
+        .map(|segment| segment.split_whitespace().collect::<Vec<&str>>())
+        .any(|words| runs_indefinitely_segment(&words))
+}
+
+fn runs_indefinitely_segment(words: &[&str]) -> bool {
+    let words = match words.iter().position(|w| *w != "sudo" && !w.contains('=')) {
+        Some(start) => &words[start..],
+        None => return false,
+    };
+    let Some(program) = words.first() else {
+        return false;
+    };
+    let program = program.rsplit('/').next().unwrap_or(program);
+    let args = &words[1..];
+
+    if NEVER_EXITING_PROGRAMS.contains(&program) {
+        return true;
+    }
+
+    // `docker logs -f` and `kubectl logs -f` follow too, so look for `logs` as an argument
+    let follows =
+        args.iter().any(|arg| FOLLOW_OPTIONS.iter().any(|option| arg.eq_ignore_ascii_case(option)));
+    if follows
+        && (FOLLOWING_PROGRAMS.iter().any(|p| p.eq_ignore_ascii_case(program))
+            || args.contains(&"logs"))
+    {
+        return true;
+    }
+
+    // Windows ping stops after 4 requests unless given -t, ping elsewhere needs a count
+    // (-c) or a deadline (-w)
+    if program == "ping" {
+        return if cfg!(windows) {
+            args.iter().any(|arg| arg.eq_ignore_ascii_case("-t"))
+        } else {
+            !args.iter().any(|arg| arg.starts_with("-c") || arg.starts_with("-w"))
+        };
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_output() {
+        let decoded = decode_output("héllo".as_bytes());
+        assert_eq!(decoded.text, "héllo");
+        assert!(!decoded.lossy());
+
+        let decoded = decode_output(b"ok \xff\xfe done\n\xe2\x82");
+        assert_eq!(decoded.invalid_bytes, 4);
+        assert_eq!(decoded.text, "ok \u{fffd}\u{fffd} done\n\u{fffd}");
+        assert!(decoded.with_note().ends_with("\n(output contained 4 non-UTF-8 bytes)"));
+    }
+
+    #[tokio::test]
+    async fn test_block_at_level_refuses_even_with_force() {
+        let executor = ShellExecutor::with_safety_checker(
+            CommandSafetyChecker::new().with_block_at_level(Some(SafetyLevel::Dangerous)),
+        );
+
+        assert_eq!(
+            executor.prompt_for_action("rm -rf /", "", true).await.unwrap(),
+            UserAction::Abort
+        );
+        let err = executor.execute_command("rm -rf /", false).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_never_spawns_a_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("ran");
+        let command = format!("echo ran > {}", marker.display());
+
+        let output = ShellExecutor::new().execute_command(&command, true).await.unwrap();
+        assert_eq!(output.exit_code, None);
+        let output = output.stdout;
+        assert!(output.contains(&command));
+        assert!(output.contains("Warning (File redirection (>) will overwrite existing files)"));
+        assert!(!marker.exists());
+
+        // The safety rules still apply
+        let blocked =
+            CommandSafetyChecker::new().with_enterprise_lists(vec![], vec!["echo".into()]);
+        let err = ShellExecutor::with_safety_checker(blocked)
+            .execute_command(&command, true)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_sequence_flow_control() {
+        let blocked = CommandSafetyChecker::new().with_enterprise_lists(vec![], vec!["rm".into()]);
+        let executor = ShellExecutor::with_safety_checker(blocked);
+        let response = core::parser::mock_llm_call("list files").await.unwrap();
+        let (generated, _) = core::parser::parse_response(&response).unwrap();
+        let commands =
+            vec![generated, "rm -rf build".into(), "echo three".into(), "echo four".into()];
+
+        // Aborting after the failed step leaves the rest unrun
+        let mut steps = Vec::new();
+        let err = executor
+            .execute_sequence(&commands, true, |step| {
+                steps.push(step);
+                let action = if step.failed {
+                    SequenceAction::AbortRemaining
+                } else {
+                    SequenceAction::Continue
+                };
+                async move { Ok(action) }
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            steps,
+            vec![
+                SequenceStep { number: 1, total: 4, failed: false },
+                SequenceStep { number: 2, total: 4, failed: true },
+            ]
+        );
+
+        // Skipping goes past the next step only, and aborting after a success isn't an error
+        let mut actions = vec![SequenceAction::AbortRemaining, SequenceAction::SkipNext];
+        let commands: Vec<String> =
+            ["echo one", "echo two", "echo three", "echo four"].map(String::from).to_vec();
+        let output = executor
+            .execute_sequence(&commands, true, |_| {
+                let action = actions.pop().unwrap();
+                async move { Ok(action) }
+            })
+            .await
+            .unwrap();
+        let output = output.stdout;
+        assert!(output.contains("echo one") && output.contains("echo three"));
+        assert!(!output.contains("echo two") && !output.contains("echo four"));
+
+        // Commands that ran report their combined output and the last exit code
+        let commands: Vec<String> = ["echo one", "echo two"].map(String::from).to_vec();
+        let output = executor
+            .execute_sequence(&commands, false, |_| async { Ok(SequenceAction::Continue) })
+            .await
+            .unwrap();
+        assert!(output.stdout.contains("one") && output.stdout.contains("two"));
+        assert_eq!(output.exit_code, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_read_only_never_spawns_a_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("ran");
+        let command = format!("echo ran > {}", marker.display());
+        let executor = ShellExecutor::new().with_read_only(true);
+
+        // Neither forcing a safe command nor asking to run it for real gets it run
+        assert_eq!(
+            executor.prompt_for_action(&command, "", true).await.unwrap(),
+            UserAction::Abort
+        );
+        let err = executor.execute_command(&command, false).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!marker.exists());
+    }
+
+    #[tokio::test]
+    async fn test_null_byte_is_refused() {
+        let executor = ShellExecutor::new();
+        for dry_run in [true, false] {
+            let err = executor.execute_command("ls\0; rm -rf ~", dry_run).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(err.to_string().contains("null byte"));
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_exit_codes() {
+        let output = ShellExecutor::new().execute_command("echo done", false).await.unwrap();
+        assert_eq!(output.exit_code, Some(0));
+        assert_eq!(output.stdout.trim(), "done");
+
+        let err = ShellExecutor::new().execute_command("echo oops >&2; exit 3", false).await;
+        let err = err.unwrap_err();
+        assert_eq!(err.to_string().trim(), "oops");
+
+        let failed = CommandFailed::from_io_error(&err).unwrap();
+        assert_eq!(failed.exit_code, Some(3));
+        assert_eq!(failed.stderr.trim(), "oops");
+    }
+
+    #[test]
+    fn test_compact_line() {
+        let executor = ShellExecutor::new().with_compact(true);
+        assert!(executor.is_compact());
+        assert_eq!(
+            executor.compact_line("du -sh *", "Show the size of each\n  item here").as_deref(),
+            Some("du -sh * — Show the size of each item here")
+        );
+        assert_eq!(
+            executor.compact_line("printf 'a\x1b[2Jb'", "Print").as_deref(),
+            Some("printf 'a\\x1b[2Jb' — Print")
+        );
+
+        // Heredocs and other multi-line commands can't be squeezed onto one line
+        assert_eq!(executor.compact_line("cat <<EOF\nhello\nEOF", "Print hello"), None);
+
+        // Neither can a line wider than the output, which would wrap mid-command
+        assert!(executor.compact_line_within("du -sh *", "Show sizes", 21).is_some());
+        assert_eq!(executor.compact_line_within("du -sh *", "Show sizes", 20), None);
+    }
+
+    #[test]
+    fn test_interactive_programs() {
+        for command in [
+            "vim notes.txt",
+            "top",
+            "sudo htop",
+            "cat big.log | less",
+            "/usr/bin/nano file",
+            "ssh user@host",
+            "ssh -p 2222 host",
+            "python3",
+        ] {
+            assert!(is_interactive_program(command), "{}", command);
+        }
+
+        for command in
+            ["ls -la", "ssh user@host uptime", "python3 script.py", "grep -r vim .", "echo top", ""]
+        {
+            assert!(!is_interactive_program(command), "{}", command);
+        }
+    }
+
+    #[test]
+    fn test_runs_indefinitely() {
+        for command in [
+            "tail -f app.log",
+            "sudo journalctl -u nginx --follow",
+            "docker logs -f web",
+            "watch -n 1 df -h",
+            "Get-Content app.log -Wait",
+        ] {
+            assert!(runs_indefinitely(command), "{}", command);
+        }
+
+        for command in ["tail -n 20 app.log", "docker logs web", "ls -f", "ping -c 4 example.com"] {
+            assert!(!runs_indefinitely(command), "{}", command);
+        }
+
+        assert_eq!(runs_indefinitely("ping example.com"), !cfg!(windows));
+    }
+}
@@ -0,0 +1,195 @@
+use colored::{Color, ColoredString, Colorize};
+use core::config::ThemeConfig;
+use core::escape_control_chars;
+
+/// Semantic roles that output is styled by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Success,
+    Warning,
+    Error,
+    Command,
+    Explanation,
+    Info,
+}
+
+/// Maps semantic roles to colors. A role without a color is printed unstyled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub success: Option<Color>,
+    pub warning: Option<Color>,
+    pub error: Option<Color>,
+    pub command: Option<Color>,
+    pub explanation: Option<Color>,
+    pub info: Option<Color>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    /// Bright colors for dark terminal backgrounds (the default)
+    pub fn dark() -> Self {
+        Theme {
+            success: Some(Color::BrightGreen),
+            warning: Some(Color::Yellow),
+            error: Some(Color::BrightRed),
+            command: Some(Color::BrightYellow),
+            explanation: None,
+            info: Some(Color::BrightCyan),
+        }
+    }
+
+    /// Darker colors that stay readable on light terminal backgrounds
+    pub fn light() -> Self {
+        Theme {
+            success: Some(Color::Green),
+            warning: Some(Color::Magenta),
+            error: Some(Color::Red),
+            command: Some(Color::Blue),
+            explanation: None,
+            info: Some(Color::Cyan),
+        }
+    }
+
+    /// No colors at all (used for `--no-color`)
+    pub fn plain() -> Self {
+        Theme {
+            success: None,
+            warning: None,
+            error: None,
+            command: None,
+            explanation: None,
+            info: None,
+        }
+    }
+
+    /// Build a theme from a preset (`dark` or `light`) and per-role overrides.
+    /// Color names are those understood by `colored`, e.g. `green` or `bright blue`.
+    pub fn from_config(config: &ThemeConfig) -> Result<Self, String> {
+        let mut theme = match config.preset.as_deref().unwrap_or("dark") {
+            "dark" => Self::dark(),
+            "light" => Self::light(),
+            other => return Err(format!("Unknown theme preset '{}' (use dark or light)", other)),
+        };
+
+        let overrides = [
+            (&config.success, &mut theme.success),
+            (&config.warning, &mut theme.warning),
+            (&config.error, &mut theme.error),
+            (&config.command, &mut theme.command),
+            (&config.explanation, &mut theme.explanation),
+            (&config.info, &mut theme.info),
+        ];
+        for (name, color) in overrides {
+            if let Some(name) = name {
+                let parsed =
+                    name.parse::<Color>().map_err(|_| format!("Unknown theme color '{}'", name))?;
+                *color = Some(parsed);
+            }
+        }
+
+        Ok(theme)
+    }
+
+    /// Get the color of a role
+    pub fn color(&self, role: Role) -> Option<Color> {
+        match role {
+            Role::Success => self.success,
+            Role::Warning => self.warning,
+            Role::Error => self.error,
+            Role::Command => self.command,
+            Role::Explanation => self.explanation,
+            Role::Info => self.info,
+        }
+    }
+
+    /// Style text with the color of a role
+    pub fn paint(&self, role: Role, text: &str) -> ColoredString {
+        match self.color(role) {
+            Some(color) => text.color(color),
+            None => text.normal(),
+        }
+    }
+
+    /// Style text as a badge: black text on the role's color
+    pub fn badge(&self, role: Role, text: &str) -> ColoredString {
+        match self.color(role) {
+            Some(color) => text.on_color(color).black(),
+            None => text.normal(),
+        }
+    }
+
+    pub fn success(&self, text: &str) -> ColoredString {
+        self.paint(Role::Success, text)
+    }
+
+    pub fn warning(&self, text: &str) -> ColoredString {
+        self.paint(Role::Warning, text)
+    }
+
+    pub fn error(&self, text: &str) -> ColoredString {
+        self.paint(Role::Error, text)
+    }
+
+    /// Style a command. Control characters in it are escaped, so a command can't change
+    /// what the terminal shows, e.g. by hiding part of itself before confirmation.
+    pub fn command(&self, text: &str) -> ColoredString {
+        self.paint(Role::Command, &escape_control_chars(text))
+    }
+
+    /// Style an explanation, escaping control characters like `command` does: it comes
+    /// from the LLM and is shown right next to the command being confirmed.
+    pub fn explanation(&self, text: &str) -> ColoredString {
+        self.paint(Role::Explanation, &escape_control_chars(text))
+    }
+
+    pub fn info(&self, text: &str) -> ColoredString {
+        self.paint(Role::Info, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_from_config() {
+        let config = ThemeConfig {
+            preset: Some("light".to_string()),
+            error: Some("bright magenta".to_string()),
+            ..Default::default()
+        };
+
+        let theme = Theme::from_config(&config).unwrap();
+        assert_eq!(theme.error, Some(Color::BrightMagenta));
+        assert_eq!(theme.success, Theme::light().success);
+
+        let config = ThemeConfig { preset: Some("solarized".to_string()), ..Default::default() };
+        assert!(Theme::from_config(&config).is_err());
+
+        let config = ThemeConfig { info: Some("not-a-color".to_string()), ..Default::default() };
+        assert!(Theme::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_plain_theme_has_no_styling() {
+        let theme = Theme::plain();
+        assert_eq!(theme.success("ok").fgcolor, None);
+        assert_eq!(theme.badge(Role::Warning, "WARNING").bgcolor, None);
+    }
+
+    #[test]
+    fn test_control_chars_are_escaped() {
+        let theme = Theme::plain();
+        assert_eq!(theme.command("ls\x1b[2K").to_string(), "ls\\x1b[2K");
+        assert_eq!(
+            theme.explanation("Lists files\x1b[1A\rSafe").to_string(),
+            "Lists files\\x1b[1A\\x0dSafe"
+        );
+        assert_eq!(theme.explanation("Line one\nLine two").to_string(), "Line one\nLine two");
+    }
+}
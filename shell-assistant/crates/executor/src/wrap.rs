@@ -0,0 +1,110 @@
+use console::{measure_text_width, Term};
+
+/// The width output is fitted to when it isn't a terminal
+pub const FIXED_WIDTH: usize = 120;
+
+// Lines are never wrapped narrower than this, however little room is left
+const MIN_WIDTH: usize = 20;
+
+/// Ends a line that breaks in the middle of a word, so it isn't mistaken for two words
+pub const WRAP_MARKER: &str = "↩";
+
+/// The width of the terminal standard output goes to, or `FIXED_WIDTH` when it isn't one
+pub fn output_width() -> usize {
+    Term::stdout().size_checked().map_or(FIXED_WIDTH, |(_, columns)| columns as usize)
+}
+
+/// Break a command into lines of at most `width` columns for display, at spaces where
+/// possible. Continuation lines are indented by `indent` spaces to set them apart. Only the
+/// space a line is broken at is left out, so no part of the command is hidden, and a word
+/// longer than a line is broken with `WRAP_MARKER` at the end of the line.
+pub fn wrap_command(command: &str, width: usize, indent: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut rest = command;
+
+    loop {
+        let prefix = if lines.is_empty() { 0 } else { indent };
+        let (line, remainder) = split_at_width(rest, width.saturating_sub(prefix).max(MIN_WIDTH));
+        // No space was left out between the parts, so the break is inside a word
+        let marker = match remainder {
+            Some(remainder) if line.len() + remainder.len() == rest.len() => WRAP_MARKER,
+            _ => "",
+        };
+        lines.push(format!("{}{}{}", " ".repeat(prefix), line, marker));
+        match remainder {
+            Some(remainder) => rest = remainder,
+            None => return lines,
+        }
+    }
+}
+
+// The start of `text` that fits in `width` columns, and what's left after it (None when it
+// all fits). The break is at the last space that fits, which belongs to neither part, or
+// in the middle of a word longer than the line, leaving room for `WRAP_MARKER`.
+fn split_at_width(text: &str, width: usize) -> (&str, Option<&str>) {
+    let marker_width = measure_text_width(WRAP_MARKER);
+    let mut used = 0;
+    let mut last_space = None;
+    let mut word_break = None;
+
+    for (i, c) in text.char_indices() {
+        if i > 0 && used + marker_width <= width {
+            word_break = Some(i);
+        }
+        used += measure_text_width(c.encode_utf8(&mut [0; 4]));
+        if used > width {
+            if c == ' ' {
+                return (&text[..i], Some(&text[i + 1..]));
+            }
+            if let Some(space) = last_space {
+                return (&text[..space], Some(&text[space + 1..]));
+            }
+            // Always take at least one character, so a narrow width can't loop forever
+            let end = word_break.unwrap_or_else(|| text.chars().next().map_or(0, char::len_utf8));
+            return (&text[..end], Some(&text[end..]));
+        }
+        if c == ' ' {
+            last_space = Some(i);
+        }
+    }
+
+    (text, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_long_command() {
+        let command = "docker run -d --name web --restart unless-stopped -p 8080:80 \
+                       -v /srv/www:/usr/share/nginx/html:ro nginx:latest";
+        let lines = wrap_command(command, 30, 2);
+        assert_eq!(
+            lines,
+            [
+                "docker run -d --name web",
+                "  --restart unless-stopped -p",
+                "  8080:80 -v",
+                "  /srv/www:/usr/share/nginx/h↩",
+                "  tml:ro nginx:latest",
+            ]
+        );
+        assert!(lines.iter().all(|line| measure_text_width(line) <= 30));
+
+        // Putting the lines back together gives the whole command: lines ending in the
+        // marker continue the same word, the others end at a space
+        let mut joined = String::new();
+        for line in &lines {
+            match line.trim_start().strip_suffix(WRAP_MARKER) {
+                Some(part) => joined.push_str(part),
+                None => joined.push_str(&format!("{} ", line.trim_start())),
+            }
+        }
+        assert_eq!(joined.trim_end(), command);
+
+        // Short commands are left alone
+        assert_eq!(wrap_command("ls -la", 30, 2), ["ls -la"]);
+        assert_eq!(wrap_command("", 30, 2), [""]);
+    }
+}
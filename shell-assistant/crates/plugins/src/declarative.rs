@@ -0,0 +1,331 @@
+use crate::traits::{quote, CommandResult, Plugin, PluginContext};
+use crate::util::extract_after_keywords;
+use core::Shell;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Errors that can occur while loading a declarative plugin definition
+#[derive(Error, Debug)]
+pub enum PluginLoadError {
+    #[error("Could not read plugin file {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+
+    #[error("Invalid plugin file {0}: {1}")]
+    Parse(PathBuf, #[source] toml::de::Error),
+}
+
+/// A single input → command mapping of a declarative plugin
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rule {
+    /// Phrases that select this rule when any of them appears in the input
+    pub patterns: Vec<String>,
+    /// The shell command to suggest
+    pub command: String,
+    /// An explanation of what the command does
+    #[serde(default)]
+    pub explanation: String,
+    /// Where the values of the `{name}` placeholders in the command come from, e.g.
+    /// `capture = { container = "after:logs" }`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub capture: BTreeMap<String, Capture>,
+    /// Whether the command needs network access, which is pointed out in offline mode
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub requires_network: bool,
+}
+
+impl Rule {
+    /// A rule suggesting `command` when the input contains any of `patterns`
+    pub fn new(patterns: &[&str], command: &str, explanation: &str) -> Self {
+        Rule {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+            command: command.to_string(),
+            explanation: explanation.to_string(),
+            capture: BTreeMap::new(),
+            requires_network: false,
+        }
+    }
+
+    /// Fill the `{name}` placeholder with the word after `keyword`
+    pub fn with_capture_after(mut self, name: &str, keyword: &str) -> Self {
+        self.capture.insert(name.to_string(), Capture::After(keyword.to_string()));
+        self
+    }
+
+    /// Mark the command as needing network access
+    pub fn with_requires_network(mut self) -> Self {
+        self.requires_network = true;
+        self
+    }
+
+    // The rule's command and explanation with each captured placeholder filled in from the
+    // input. Placeholders without a value are left as they are.
+    fn fill(&self, input: &str, shell: Shell) -> CommandResult {
+        let mut command = self.command.clone();
+        let mut explanation = self.explanation.clone();
+        for (name, capture) in &self.capture {
+            let Some(value) = capture.extract(input) else {
+                continue;
+            };
+            let placeholder = format!("{{{}}}", name);
+            command = command.replace(&placeholder, &quote(&value, shell));
+            explanation = explanation.replace(&placeholder, &value);
+        }
+
+        CommandResult {
+            command,
+            explanation,
+            requires_network: self.requires_network,
+            ..CommandResult::default()
+        }
+    }
+}
+
+/// Where a rule takes a placeholder's value from in the input
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Capture {
+    /// The word after a keyword, written `after:<keyword>`, as "web" in "logs of web" for
+    /// `after:of`. Quoted phrases count as one word.
+    After(String),
+}
+
+impl Capture {
+    fn extract(&self, input: &str) -> Option<String> {
+        let Capture::After(keyword) = self;
+        let keep = |c: char| c.is_alphanumeric() || "-_./:@~".contains(c);
+        let value = extract_after_keywords(input, &[keyword], keep)?;
+        let value = value.trim_end_matches('.');
+        (!value.is_empty()).then(|| value.to_string())
+    }
+}
+
+impl TryFrom<String> for Capture {
+    type Error = String;
+
+    fn try_from(spec: String) -> Result<Self, Self::Error> {
+        match spec.split_once(':') {
+            Some(("after", keyword)) if !keyword.trim().is_empty() => {
+                Ok(Capture::After(keyword.trim().to_string()))
+            }
+            _ => Err(format!("invalid capture '{}' (expected after:<keyword>)", spec)),
+        }
+    }
+}
+
+impl From<Capture> for String {
+    fn from(capture: Capture) -> Self {
+        match capture {
+            Capture::After(keyword) => format!("after:{}", keyword),
+        }
+    }
+}
+
+/// A plugin defined in a `*.toml` file instead of Rust code, e.g.
+///
+/// ```toml
+/// name = "kubectl"
+/// description = "Kubernetes shortcuts"
+/// keywords = ["kubectl", "pod"]
+///
+/// [[rules]]
+/// patterns = ["list pods", "show pods"]
+/// command = "kubectl get pods"
+/// explanation = "Lists the pods in the current namespace."
+///
+/// [[rules]]
+/// patterns = ["logs of"]
+/// command = "kubectl logs {pod}"
+/// capture = { pod = "after:of" }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeclarativePlugin {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Words that mark an input as one this plugin can handle
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+impl DeclarativePlugin {
+    /// Parse a plugin definition from a TOML string
+    pub fn from_toml(contents: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    /// Load a plugin definition from a TOML file
+    pub fn from_file(path: &Path) -> Result<Self, PluginLoadError> {
+        let contents =
+            fs::read_to_string(path).map_err(|e| PluginLoadError::Io(path.to_path_buf(), e))?;
+        Self::from_toml(&contents).map_err(|e| PluginLoadError::Parse(path.to_path_buf(), e))
+    }
+
+    /// Write the plugin definition as TOML, in the format `from_toml` reads
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string(self)
+    }
+}
+
+impl Plugin for DeclarativePlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn description(&self) -> &str {
+        &self.description
+    }
+
+    fn to_declarative(&self) -> Option<DeclarativePlugin> {
+        Some(self.clone())
+    }
+
+    fn can_handle(&self, input: &str) -> bool {
+        let input_lower = input.to_lowercase();
+        self.keywords.iter().any(|keyword| input_lower.contains(&keyword.to_lowercase()))
+    }
+
+    fn handle(&self, input: &str) -> Option<CommandResult> {
+        self.handle_with_context(input, &PluginContext::default())
+    }
+
+    fn handle_with_context(&self, input: &str, context: &PluginContext) -> Option<CommandResult> {
+        let input_lower = input.to_lowercase();
+
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.patterns.iter().any(|pattern| input_lower.contains(&pattern.to_lowercase()))
+            })
+            .map(|rule| rule.fill(input, context.shell()))
+    }
+
+    // A placeholder the input had no value for leaves the command incomplete
+    fn validate(&self, result: &CommandResult) -> Result<(), String> {
+        let missing = self
+            .rules
+            .iter()
+            .flat_map(|rule| rule.capture.keys())
+            .find(|name| result.command.contains(&format!("{{{}}}", name)));
+        match missing {
+            Some(name) => Err(format!("'{}' is missing a value for {{{}}}", result.command, name)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// List the `*.toml` files in a directory, sorted
+pub(crate) fn plugin_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "toml") {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_declarative_plugin() {
+        let plugin = DeclarativePlugin::from_toml(
+            r#"
+name = "kubectl"
+keywords = ["kubectl", "pod"]
+
+[[rules]]
+patterns = ["list pods", "show pods"]
+command = "kubectl get pods"
+explanation = "Lists the pods in the current namespace."
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(plugin.name(), "kubectl");
+        assert!(plugin.can_handle("Show Pods please"));
+        assert!(!plugin.can_handle("list files"));
+        assert_eq!(plugin.handle("show pods please").unwrap().command, "kubectl get pods");
+        assert!(plugin.handle("delete the pod").is_none());
+    }
+
+    #[test]
+    fn test_captures() {
+        let plugin = DeclarativePlugin::from_toml(
+            r#"
+name = "docker-shortcuts"
+keywords = ["logs", "copy"]
+
+[[rules]]
+patterns = ["logs"]
+command = "docker logs {container}"
+explanation = "Shows the logs of {container}."
+capture = { container = "after:logs" }
+
+[[rules]]
+patterns = ["copy"]
+command = "docker cp {container}:{path} ."
+capture = { container = "after:from", path = "after:copy" }
+"#,
+        )
+        .unwrap();
+
+        let result = plugin.handle("show logs web-1 please").unwrap();
+        assert_eq!(result.command, "docker logs web-1");
+        assert_eq!(result.explanation, "Shows the logs of web-1.");
+        assert!(plugin.validate(&result).is_ok());
+
+        let result = plugin.handle("copy /var/log/app.log from api.").unwrap();
+        assert_eq!(result.command, "docker cp api:/var/log/app.log .");
+        assert!(plugin.validate(&result).is_ok());
+
+        // A value that isn't in the input leaves the command incomplete
+        let result = plugin.handle("copy /etc/hosts").unwrap();
+        assert_eq!(result.command, "docker cp {container}:/etc/hosts .");
+        assert!(plugin.validate(&result).unwrap_err().contains("{container}"));
+
+        let invalid = r#"
+name = "broken"
+keywords = ["logs"]
+
+[[rules]]
+patterns = ["logs"]
+command = "docker logs {container}"
+capture = { container = "before:logs" }
+"#;
+        assert!(DeclarativePlugin::from_toml(invalid).is_err());
+    }
+
+    #[test]
+    fn test_builtin_plugins_dump_to_toml() {
+        let git = crate::GitPlugin::new().to_declarative().unwrap();
+        let dumped = DeclarativePlugin::from_toml(&git.to_toml().unwrap()).unwrap();
+        assert_eq!(dumped, git);
+        assert_eq!(dumped.handle("show git status").unwrap().command, "git status");
+        assert_eq!(dumped.handle("create branch foo").unwrap().command, "git branch foo");
+        assert_eq!(dumped.handle("switch to branch main").unwrap().command, "git checkout main");
+
+        let docker = crate::DockerPlugin::new().to_declarative().unwrap();
+        let dumped = DeclarativePlugin::from_toml(&docker.to_toml().unwrap()).unwrap();
+        assert_eq!(dumped, docker);
+        assert!(dumped.can_handle("list all containers"));
+        assert_eq!(dumped.handle("list all containers").unwrap().command, "docker ps -a");
+        assert_eq!(dumped.handle("stop container web").unwrap().command, "docker stop web");
+
+        // Plugins without a definition can't be dumped
+        assert!(crate::NetworkPlugin::new().to_declarative().is_none());
+    }
+
+    #[test]
+    fn test_missing_keywords_is_rejected() {
+        assert!(DeclarativePlugin::from_toml("name = \"broken\"\n").is_err());
+    }
+}
@@ -1,4 +1,10 @@
-use crate::traits::{CommandResult, Plugin};
+use crate::declarative::{DeclarativePlugin, Rule};
+use crate::traits::{
+    prefer_machine_readable, quote, reject_trailing_placeholder, CommandResult, Plugin,
+    PluginContext,
+};
+use crate::util::extract_after_keywords;
+use core::Shell;
 
 pub struct DockerPlugin;
 
@@ -24,14 +30,98 @@ impl Plugin for DockerPlugin {
     }
 
     fn can_handle(&self, input: &str) -> bool {
-        input.to_lowercase().contains("docker")
-            || input.to_lowercase().contains("container")
-            || input.to_lowercase().contains("image")
-            || input.to_lowercase().contains("volume")
-            || input.to_lowercase().contains("compose")
+        let input_lower = input.to_lowercase();
+        KEYWORDS.iter().any(|keyword| input_lower.contains(keyword))
+    }
+
+    // Covers the common requests; names are only captured after a single keyword
+    fn to_declarative(&self) -> Option<DeclarativePlugin> {
+        let rules = vec![
+            Rule::new(
+                &["list all container"],
+                "docker ps -a",
+                "Lists all containers, including stopped ones.",
+            ),
+            Rule::new(&["list container"], "docker ps", "Lists running containers."),
+            Rule::new(&["list image"], "docker images", "Lists all available Docker images."),
+            Rule::new(&["pull image"], "docker pull {image}", "Pulls the Docker image '{image}'.")
+                .with_capture_after("image", "image")
+                .with_requires_network(),
+            Rule::new(
+                &["run image", "run container", "start container"],
+                "docker run {image}",
+                "Runs a container from the '{image}' image.",
+            )
+            .with_capture_after("image", "image"),
+            Rule::new(
+                &["stop container"],
+                "docker stop {container}",
+                "Stops the running container '{container}'.",
+            )
+            .with_capture_after("container", "container"),
+            Rule::new(
+                &["remove container", "delete container"],
+                "docker rm {container}",
+                "Removes the container '{container}'.",
+            )
+            .with_capture_after("container", "container"),
+            Rule::new(
+                &["remove image", "delete image"],
+                "docker rmi {image}",
+                "Removes the image '{image}'.",
+            )
+            .with_capture_after("image", "image"),
+            Rule::new(
+                &["compose up"],
+                "docker-compose up",
+                "Starts all services defined in docker-compose.yml.",
+            ),
+            Rule::new(
+                &["compose down"],
+                "docker-compose down",
+                "Stops and removes all services defined in docker-compose.yml.",
+            ),
+            Rule::new(
+                &["build image"],
+                "docker build -t {tag} .",
+                "Builds a Docker image with the tag '{tag}'.",
+            )
+            .with_capture_after("tag", "tag"),
+        ];
+
+        Some(DeclarativePlugin {
+            name: self.name().to_string(),
+            description: self.description().to_string(),
+            keywords: KEYWORDS.iter().map(|keyword| keyword.to_string()).collect(),
+            rules,
+        })
+    }
+
+    // Without Docker the command can't run, so the LLM gets a chance to suggest another way
+    fn handle_with_context(&self, input: &str, context: &PluginContext) -> Option<CommandResult> {
+        if !context.tools.is_installed("docker") {
+            return None;
+        }
+
+        let result = self.command_for(input, context.shell())?;
+        if context.machine_readable {
+            return Some(prefer_machine_readable(result, MACHINE_READABLE));
+        }
+        Some(result)
+    }
+
+    fn validate(&self, result: &CommandResult) -> Result<(), String> {
+        reject_trailing_placeholder(result)
     }
 
     fn handle(&self, input: &str) -> Option<CommandResult> {
+        self.command_for(input, Shell::detect())
+    }
+}
+
+impl DockerPlugin {
+    // The command for the input, with names and paths quoted for `shell`
+    fn command_for(&self, input: &str, shell: Shell) -> Option<CommandResult> {
         if !self.can_handle(input) {
             return None;
         }
@@ -53,8 +143,7 @@ impl Plugin for DockerPlugin {
             return Some(CommandResult {
                 command: cmd.to_string(),
                 explanation: explanation.to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
@@ -63,18 +152,17 @@ impl Plugin for DockerPlugin {
             return Some(CommandResult {
                 command: "docker images".to_string(),
                 explanation: "Lists all available Docker images.".to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
         if input_lower.contains("pull") && input_lower.contains("image") {
             if let Some(image) = extract_image_name(input) {
                 return Some(CommandResult {
-                    command: format!("docker pull {}", image),
+                    command: format!("docker pull {}", quote(&image, shell)),
                     explanation: format!("Pulls the Docker image '{}'.", image),
-                    executed: false,
-                    output: None,
+                    requires_network: true,
+                    ..CommandResult::default()
                 });
             }
 
@@ -82,8 +170,8 @@ impl Plugin for DockerPlugin {
                 command: "docker pull ".to_string(),
                 explanation: "Pulls a Docker image. You'll need to specify the image name."
                     .to_string(),
-                executed: false,
-                output: None,
+                requires_network: true,
+                ..CommandResult::default()
             });
         }
 
@@ -93,10 +181,9 @@ impl Plugin for DockerPlugin {
         {
             if let Some(image) = extract_image_name(input) {
                 return Some(CommandResult {
-                    command: format!("docker run {}", image),
+                    command: format!("docker run {}", quote(&image, shell)),
                     explanation: format!("Runs a container from the '{}' image.", image),
-                    executed: false,
-                    output: None,
+                    ..CommandResult::default()
                 });
             }
 
@@ -104,8 +191,7 @@ impl Plugin for DockerPlugin {
                 command: "docker run ".to_string(),
                 explanation: "Runs a Docker container. You'll need to specify the image name."
                     .to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
@@ -113,10 +199,9 @@ impl Plugin for DockerPlugin {
         if input_lower.contains("stop") && input_lower.contains("container") {
             if let Some(container) = extract_container_name(input) {
                 return Some(CommandResult {
-                    command: format!("docker stop {}", container),
+                    command: format!("docker stop {}", quote(&container, shell)),
                     explanation: format!("Stops the running container '{}'.", container),
-                    executed: false,
-                    output: None,
+                    ..CommandResult::default()
                 });
             }
 
@@ -125,8 +210,7 @@ impl Plugin for DockerPlugin {
                 explanation:
                     "Stops a running container. You'll need to specify the container ID or name."
                         .to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
@@ -136,10 +220,9 @@ impl Plugin for DockerPlugin {
         {
             if let Some(container) = extract_container_name(input) {
                 return Some(CommandResult {
-                    command: format!("docker rm {}", container),
+                    command: format!("docker rm {}", quote(&container, shell)),
                     explanation: format!("Removes the container '{}'.", container),
-                    executed: false,
-                    output: None,
+                    ..CommandResult::default()
                 });
             }
 
@@ -148,8 +231,7 @@ impl Plugin for DockerPlugin {
                 explanation:
                     "Removes a container. You'll need to specify the container ID or name."
                         .to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
@@ -159,10 +241,9 @@ impl Plugin for DockerPlugin {
         {
             if let Some(image) = extract_image_name(input) {
                 return Some(CommandResult {
-                    command: format!("docker rmi {}", image),
+                    command: format!("docker rmi {}", quote(&image, shell)),
                     explanation: format!("Removes the image '{}'.", image),
-                    executed: false,
-                    output: None,
+                    ..CommandResult::default()
                 });
             }
 
@@ -170,8 +251,7 @@ impl Plugin for DockerPlugin {
                 command: "docker rmi ".to_string(),
                 explanation: "Removes a Docker image. You'll need to specify the image ID or name."
                     .to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
@@ -180,8 +260,7 @@ impl Plugin for DockerPlugin {
             return Some(CommandResult {
                 command: "docker-compose up".to_string(),
                 explanation: "Starts all services defined in docker-compose.yml.".to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
@@ -190,8 +269,7 @@ impl Plugin for DockerPlugin {
                 command: "docker-compose down".to_string(),
                 explanation: "Stops and removes all services defined in docker-compose.yml."
                     .to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
@@ -199,18 +277,16 @@ impl Plugin for DockerPlugin {
         if input_lower.contains("build") && input_lower.contains("image") {
             if let Some(tag) = extract_tag(input) {
                 return Some(CommandResult {
-                    command: format!("docker build -t {} .", tag),
+                    command: format!("docker build -t {} .", quote(&tag, shell)),
                     explanation: format!("Builds a Docker image with the tag '{}'.", tag),
-                    executed: false,
-                    output: None,
+                    ..CommandResult::default()
                 });
             }
 
             return Some(CommandResult {
                 command: "docker build -t ".to_string(),
                 explanation: "Builds a Docker image. You'll need to specify a tag.".to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
@@ -218,73 +294,104 @@ impl Plugin for DockerPlugin {
         Some(CommandResult {
             command: "docker ".to_string(),
             explanation: "Docker is a platform for developing, shipping, and running applications in containers.".to_string(),
-            executed: false,
-            output: None,
+            ..CommandResult::default()
         })
     }
 }
 
-// Helper functions for extracting information from input
+// Words that mark a request as a Docker one
+const KEYWORDS: &[&str] = &["docker", "container", "image", "volume", "compose"];
+
+// Commands with a machine-readable variant, used with `--machine-readable`
+const MACHINE_READABLE: &[(&str, &str)] = &[
+    ("docker ps", "docker ps --format '{{json .}}'"),
+    ("docker ps -a", "docker ps -a --format '{{json .}}'"),
+    ("docker images", "docker images --format '{{json .}}'"),
+];
+
+// Helper functions for extracting information from input. Quoted phrases count as one word,
+// so names with spaces are extracted whole.
 fn extract_image_name(input: &str) -> Option<String> {
-    let words: Vec<&str> = input.split_whitespace().collect();
-    let idx = words.iter().position(|&w| {
-        w.to_lowercase() == "image"
-            || w.to_lowercase() == "from"
-            || w.to_lowercase() == "called"
-            || w.to_lowercase() == "named"
-    })?;
-
-    if idx + 1 < words.len() {
-        Some(
-            words[idx + 1]
-                .trim_matches(|c: char| {
-                    !c.is_alphanumeric() && c != '.' && c != '_' && c != ':' && c != '/'
-                })
-                .to_string(),
-        )
-    } else {
-        None
-    }
+    extract_after_keywords(input, &["image", "from", "called", "named"], is_image_char)
 }
 
 fn extract_container_name(input: &str) -> Option<String> {
-    let words: Vec<&str> = input.split_whitespace().collect();
-    let idx = words.iter().position(|&w| {
-        w.to_lowercase() == "container"
-            || w.to_lowercase() == "named"
-            || w.to_lowercase() == "called"
-            || w.to_lowercase() == "id"
-    })?;
-
-    if idx + 1 < words.len() {
-        Some(
-            words[idx + 1]
-                .trim_matches(|c: char| !c.is_alphanumeric() && c != '.' && c != '_' && c != '-')
-                .to_string(),
-        )
-    } else {
-        None
-    }
+    extract_after_keywords(input, &["container", "named", "called", "id"], |c| {
+        c.is_alphanumeric() || c == '.' || c == '_' || c == '-'
+    })
 }
 
 fn extract_tag(input: &str) -> Option<String> {
-    let words: Vec<&str> = input.split_whitespace().collect();
-    let idx = words.iter().position(|&w| {
-        w.to_lowercase() == "tag"
-            || w.to_lowercase() == "as"
-            || w.to_lowercase() == "name"
-            || w.to_lowercase() == "named"
-    })?;
-
-    if idx + 1 < words.len() {
-        Some(
-            words[idx + 1]
-                .trim_matches(|c: char| {
-                    !c.is_alphanumeric() && c != '.' && c != '_' && c != ':' && c != '/'
-                })
-                .to_string(),
-        )
-    } else {
-        None
+    extract_after_keywords(input, &["tag", "as", "name", "named"], is_image_char)
+}
+
+fn is_image_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '.' || c == '_' || c == ':' || c == '/'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::ToolAvailability;
+
+    fn with_docker(installed: bool) -> PluginContext {
+        PluginContext {
+            tools: ToolAvailability::new().with_tool("docker", installed),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_machine_readable() {
+        let plugin = DockerPlugin::new();
+        let context = PluginContext { machine_readable: true, ..with_docker(true) };
+
+        let result = plugin.handle_with_context("list all containers", &context).unwrap();
+        assert_eq!(result.command, "docker ps -a --format '{{json .}}'");
+
+        let result = plugin.handle_with_context("list all containers", &with_docker(true)).unwrap();
+        assert_eq!(result.command, "docker ps -a");
+
+        // Commands without a machine-readable variant are unchanged
+        let result = plugin.handle_with_context("stop container web", &context).unwrap();
+        assert_eq!(result.command, "docker stop web");
+    }
+
+    #[test]
+    fn test_quoted_arguments() {
+        let plugin = DockerPlugin::new();
+
+        let result = plugin.handle("run image \"my custom image\"").unwrap();
+        assert_eq!(result.command, "docker run 'my custom image'");
+
+        let result = plugin.handle("stop the container named 'web server'").unwrap();
+        assert_eq!(result.command, "docker stop 'web server'");
+
+        // Unquoted names still end at the first word
+        let result = plugin.handle("remove container web, please").unwrap();
+        assert_eq!(result.command, "docker rm web");
+    }
+
+    #[test]
+    fn test_validate_rejects_placeholders() {
+        let plugin = DockerPlugin::new();
+
+        let result = plugin.handle("pull a docker image").unwrap();
+        assert_eq!(result.command, "docker pull ");
+        assert!(plugin.validate(&result).is_err());
+
+        let result = plugin.handle("pull image nginx").unwrap();
+        assert!(plugin.validate(&result).is_ok());
+        assert!(result.requires_network);
+        assert!(!plugin.handle("list all containers").unwrap().requires_network);
+    }
+
+    #[test]
+    fn test_declines_without_docker() {
+        let plugin = DockerPlugin::new();
+        let context = with_docker(false);
+
+        assert!(plugin.handle_with_context("list all containers", &context).is_none());
+        assert!(plugin.handle_multi("list all containers", &context).is_empty());
     }
 }
@@ -1,4 +1,10 @@
-use crate::traits::{CommandResult, Plugin};
+use crate::declarative::{DeclarativePlugin, Rule};
+use crate::traits::{
+    prefer_machine_readable, quote, reject_trailing_placeholder, CommandResult, Plugin,
+    PluginContext,
+};
+use crate::util::{extract_after_keywords, split_words};
+use core::Shell;
 
 pub struct GitPlugin;
 
@@ -24,16 +30,97 @@ impl Plugin for GitPlugin {
     }
 
     fn can_handle(&self, input: &str) -> bool {
-        input.to_lowercase().contains("git")
-            || input.to_lowercase().contains("commit")
-            || input.to_lowercase().contains("repository")
-            || input.to_lowercase().contains("branch")
-            || input.to_lowercase().contains("push")
-            || input.to_lowercase().contains("pull")
-            || input.to_lowercase().contains("clone")
+        let input_lower = input.to_lowercase();
+        KEYWORDS.iter().any(|keyword| input_lower.contains(keyword))
+    }
+
+    // Covers the common requests; names are only captured after a single keyword
+    fn to_declarative(&self) -> Option<DeclarativePlugin> {
+        let rules = vec![
+            Rule::new(
+                &["status", "what changed"],
+                "git status",
+                "Shows the working tree status, including tracked and untracked files.",
+            ),
+            Rule::new(&["commit"], "git commit -m {message}", "Commits changes with the specified message.")
+                .with_capture_after("message", "message"),
+            Rule::new(
+                &["add all", "stage all", "add everything", "stage everything"],
+                "git add .",
+                "Stages all changes in the working directory.",
+            ),
+            Rule::new(&["add", "stage"], "git add {file}", "Stages changes to the file '{file}'.")
+                .with_capture_after("file", "file"),
+            Rule::new(&["log", "history"], "git log", "Shows the commit history."),
+            Rule::new(&["list branch", "show branch"], "git branch", "Lists all local branches."),
+            Rule::new(
+                &["create branch", "new branch"],
+                "git branch {branch}",
+                "Creates a new branch named '{branch}'.",
+            )
+            .with_capture_after("branch", "branch"),
+            Rule::new(
+                &["switch", "checkout"],
+                "git checkout {branch}",
+                "Switches to the branch named '{branch}'.",
+            )
+            .with_capture_after("branch", "branch"),
+            Rule::new(&["push"], "git push", "Pushes commits to the remote repository.")
+                .with_requires_network(),
+            Rule::new(
+                &["pull"],
+                "git pull",
+                "Fetches changes from the remote repository and merges them into the current branch.",
+            )
+            .with_requires_network(),
+            Rule::new(&["clone"], "git clone {url}", "Clones the repository from '{url}'.")
+                .with_capture_after("url", "clone")
+                .with_requires_network(),
+        ];
+
+        Some(DeclarativePlugin {
+            name: self.name().to_string(),
+            description: self.description().to_string(),
+            keywords: KEYWORDS.iter().map(|keyword| keyword.to_string()).collect(),
+            rules,
+        })
+    }
+
+    fn handle_multi(&self, input: &str, context: &PluginContext) -> Vec<CommandResult> {
+        match create_and_switch(input, context.shell()) {
+            Some(steps) => steps,
+            None => self.handle_with_context(input, context).into_iter().collect(),
+        }
+    }
+
+    fn handle_with_context(&self, input: &str, context: &PluginContext) -> Option<CommandResult> {
+        let result = self.command_for(input, context.shell())?;
+        if context.machine_readable {
+            return Some(prefer_machine_readable(result, MACHINE_READABLE));
+        }
+        Some(result)
+    }
+
+    fn validate(&self, result: &CommandResult) -> Result<(), String> {
+        reject_trailing_placeholder(result)?;
+        if result.command.contains("-m \"\"") {
+            return Err("The commit message is empty".to_string());
+        }
+        Ok(())
     }
 
     fn handle(&self, input: &str) -> Option<CommandResult> {
+        self.command_for(input, Shell::detect())
+    }
+}
+
+impl GitPlugin {
+    // The command for the input, with names and paths quoted for `shell`
+    fn command_for(&self, input: &str, shell: Shell) -> Option<CommandResult> {
+        if let Some(steps) = create_and_switch(input, shell) {
+            return CommandResult::combine(steps);
+        }
+
         let input_lower = input.to_lowercase();
 
         // Pattern matching for common Git operations
@@ -43,20 +130,18 @@ impl Plugin for GitPlugin {
                 explanation:
                     "Shows the working tree status, including tracked and untracked files."
                         .to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
         if input_lower.contains("commit") {
-            if input_lower.contains("message") && input_lower.contains("\"") {
-                // Extract message between quotes if present
+            if input_lower.contains("message") {
+                // Extract the message between quotes if present
                 if let Some(message) = extract_quoted_text(input) {
                     return Some(CommandResult {
-                        command: format!("git commit -m \"{}\"", message),
+                        command: format!("git commit -m {}", quote(&message, shell)),
                         explanation: "Commits changes with the specified message.".to_string(),
-                        executed: false,
-                        output: None,
+                        ..CommandResult::default()
                     });
                 }
             }
@@ -65,8 +150,7 @@ impl Plugin for GitPlugin {
                 command: "git commit -m \"\"".to_string(),
                 explanation: "Commits the staged changes. You'll need to provide a commit message."
                     .to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
@@ -75,18 +159,16 @@ impl Plugin for GitPlugin {
                 return Some(CommandResult {
                     command: "git add .".to_string(),
                     explanation: "Stages all changes in the working directory.".to_string(),
-                    executed: false,
-                    output: None,
+                    ..CommandResult::default()
                 });
             }
 
             // Try to extract specific files
             if let Some(file) = extract_file_reference(input) {
                 return Some(CommandResult {
-                    command: format!("git add {}", file),
+                    command: format!("git add {}", quote(&file, shell)),
                     explanation: format!("Stages changes to the file '{}'.", file),
-                    executed: false,
-                    output: None,
+                    ..CommandResult::default()
                 });
             }
 
@@ -94,8 +176,7 @@ impl Plugin for GitPlugin {
                 command: "git add ".to_string(),
                 explanation: "Stages changes. You'll need to specify which files to stage."
                     .to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
@@ -103,8 +184,7 @@ impl Plugin for GitPlugin {
             return Some(CommandResult {
                 command: "git log".to_string(),
                 explanation: "Shows the commit history.".to_string(),
-                executed: false,
-                output: None,
+                ..CommandResult::default()
             });
         }
 
@@ -113,18 +193,16 @@ impl Plugin for GitPlugin {
                 return Some(CommandResult {
                     command: "git branch".to_string(),
                     explanation: "Lists all local branches.".to_string(),
-                    executed: false,
-                    output: None,
+                    ..CommandResult::default()
                 });
             }
 
             if input_lower.contains("create") || input_lower.contains("new") {
                 if let Some(branch_name) = extract_branch_name(input) {
                     return Some(CommandResult {
-                        command: format!("git branch {}", branch_name),
+                        command: format!("git branch {}", quote(&branch_name, shell)),
                         explanation: format!("Creates a new branch named '{}'.", branch_name),
-                        executed: false,
-                        output: None,
+                        ..CommandResult::default()
                     });
                 }
             }
@@ -132,10 +210,9 @@ impl Plugin for GitPlugin {
             if input_lower.contains("switch") || input_lower.contains("checkout") {
                 if let Some(branch_name) = extract_branch_name(input) {
                     return Some(CommandResult {
-                        command: format!("git checkout {}", branch_name),
+                        command: format!("git checkout {}", quote(&branch_name, shell)),
                         explanation: format!("Switches to the branch named '{}'.", branch_name),
-                        executed: false,
-                        output: None,
+                        ..CommandResult::default()
                     });
                 }
             }
@@ -145,8 +222,8 @@ impl Plugin for GitPlugin {
             return Some(CommandResult {
                 command: "git push".to_string(),
                 explanation: "Pushes commits to the remote repository.".to_string(),
-                executed: false,
-                output: None,
+                requires_network: true,
+                ..CommandResult::default()
             });
         }
 
@@ -154,18 +231,18 @@ impl Plugin for GitPlugin {
             return Some(CommandResult {
                 command: "git pull".to_string(),
                 explanation: "Fetches changes from the remote repository and merges them into the current branch.".to_string(),
-                executed: false,
-                output: None,
+                requires_network: true,
+                ..CommandResult::default()
             });
         }
 
         if input_lower.contains("clone") {
             if let Some(url) = extract_url(input) {
                 return Some(CommandResult {
-                    command: format!("git clone {}", url),
+                    command: format!("git clone {}", quote(&url, shell)),
                     explanation: format!("Clones the repository from '{}'.", url),
-                    executed: false,
-                    output: None,
+                    requires_network: true,
+                    ..CommandResult::default()
                 });
             }
 
@@ -173,8 +250,8 @@ impl Plugin for GitPlugin {
                 command: "git clone ".to_string(),
                 explanation: "Clones a repository. You'll need to specify the repository URL."
                     .to_string(),
-                executed: false,
-                output: None,
+                requires_network: true,
+                ..CommandResult::default()
             });
         }
 
@@ -182,57 +259,66 @@ impl Plugin for GitPlugin {
         Some(CommandResult {
             command: "git ".to_string(),
             explanation: "Git is a distributed version control system.".to_string(),
-            executed: false,
-            output: None,
+            ..CommandResult::default()
         })
     }
 }
 
-// Helper functions for extracting information from input
-fn extract_quoted_text(input: &str) -> Option<String> {
-    let parts: Vec<&str> = input.split('"').collect();
-    if parts.len() >= 3 {
-        Some(parts[1].to_string())
-    } else {
-        None
+// Words that mark a request as a Git one
+const KEYWORDS: &[&str] = &["git", "commit", "repository", "branch", "push", "pull", "clone"];
+
+// Commands with a machine-readable variant, used with `--machine-readable`
+const MACHINE_READABLE: &[(&str, &str)] = &[
+    ("git status", "git status --porcelain=v2"),
+    ("git log", "git log --format='%H%x09%an%x09%aI%x09%s'"),
+    ("git branch", "git branch --format='%(refname:short)'"),
+];
+
+// "Create and switch to branch foo" needs the branch to be created before checking it out
+fn create_and_switch(input: &str, shell: Shell) -> Option<Vec<CommandResult>> {
+    let input_lower = input.to_lowercase();
+    let creates = input_lower.contains("create") || input_lower.contains("new");
+    let switches = input_lower.contains("switch") || input_lower.contains("checkout");
+    if !input_lower.contains("branch") || !creates || !switches {
+        return None;
     }
+
+    let branch_name = extract_branch_name(input)?;
+    Some(vec![
+        CommandResult {
+            command: format!("git branch {}", quote(&branch_name, shell)),
+            explanation: format!("Creates a new branch named '{}'.", branch_name),
+            ..CommandResult::default()
+        },
+        CommandResult {
+            command: format!("git checkout {}", quote(&branch_name, shell)),
+            explanation: format!("Switches to the branch named '{}'.", branch_name),
+            ..CommandResult::default()
+        },
+    ])
+}
+
+// Helper functions for extracting information from input. Quoted phrases count as one word,
+// so names and messages with spaces are extracted whole.
+fn extract_quoted_text(input: &str) -> Option<String> {
+    split_words(input).into_iter().find(|word| word.quoted).map(|word| word.text.to_string())
 }
 
 fn extract_file_reference(input: &str) -> Option<String> {
-    // Very simple extraction - would need to be more sophisticated in a real implementation
-    let words: Vec<&str> = input.split_whitespace().collect();
-    let idx =
-        words.iter().position(|&w| w.to_lowercase() == "file" || w.to_lowercase() == "files")?;
-
-    if idx + 1 < words.len() {
-        Some(
-            words[idx + 1]
-                .trim_matches(|c: char| !c.is_alphanumeric() && c != '.' && c != '_')
-                .to_string(),
-        )
-    } else {
-        None
-    }
+    extract_after_keywords(input, &["file", "files"], |c| {
+        c.is_alphanumeric() || c == '.' || c == '_'
+    })
 }
 
+// Words that can come between a keyword and the name, as in "switch to a new branch", are
+// skipped along with the keywords
 fn extract_branch_name(input: &str) -> Option<String> {
-    let words: Vec<&str> = input.split_whitespace().collect();
-    let idx = words.iter().position(|&w| {
-        w.to_lowercase() == "branch"
-            || w.to_lowercase() == "to"
-            || w.to_lowercase() == "named"
-            || w.to_lowercase() == "called"
-    })?;
-
-    if idx + 1 < words.len() {
-        Some(
-            words[idx + 1]
-                .trim_matches(|c: char| !c.is_alphanumeric() && c != '-' && c != '_')
-                .to_string(),
-        )
-    } else {
-        None
-    }
+    extract_after_keywords(
+        input,
+        &["branch", "to", "named", "called", "a", "the", "new", "it"],
+        |c| c.is_alphanumeric() || c == '-' || c == '_',
+    )
+    .filter(|name| !name.is_empty())
 }
 
 fn extract_url(input: &str) -> Option<String> {
@@ -243,3 +329,88 @@ fn extract_url(input: &str) -> Option<String> {
         .find(|w| w.starts_with("http://") || w.starts_with("https://") || w.starts_with("git@"))
         .map(|s| s.to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_and_switch_branch() {
+        let plugin = GitPlugin::new();
+
+        let context = PluginContext::default();
+        let steps = plugin.handle_multi("create and switch to branch foo", &context);
+        let commands: Vec<&str> = steps.iter().map(|step| step.command.as_str()).collect();
+        assert_eq!(commands, vec!["git branch foo", "git checkout foo"]);
+
+        let combined = plugin.handle("create a new branch called foo and checkout it").unwrap();
+        assert_eq!(combined.command, "git branch foo && git checkout foo");
+
+        // Single-step requests are unchanged
+        let steps = plugin.handle_multi("switch to branch main", &context);
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].command, "git checkout main");
+    }
+
+    #[test]
+    fn test_remote_commands_require_network() {
+        let plugin = GitPlugin::new();
+        let declarative = plugin.to_declarative().unwrap();
+
+        for input in ["git push", "git pull", "git clone https://github.com/rust-lang/rust"] {
+            assert!(plugin.handle(input).unwrap().requires_network, "{}", input);
+            assert!(declarative.handle(input).unwrap().requires_network, "{}", input);
+        }
+        assert!(!plugin.handle("git status").unwrap().requires_network);
+        assert!(!declarative.handle("git status").unwrap().requires_network);
+    }
+
+    #[test]
+    fn test_commit_message_is_quoted() {
+        let result = GitPlugin::new().handle("commit with message \"fix the $PATH bug\"").unwrap();
+        assert_eq!(result.command, "git commit -m 'fix the $PATH bug'");
+    }
+
+    #[test]
+    fn test_quoted_arguments() {
+        let plugin = GitPlugin::new();
+
+        let result = plugin.handle("commit with message 'add the search page'").unwrap();
+        assert_eq!(result.command, "git commit -m 'add the search page'");
+
+        // A quoted name that looks like a keyword is still the name
+        let result = plugin.handle("switch to branch \"it\"").unwrap();
+        assert_eq!(result.command, "git checkout it");
+
+        let result = plugin.handle("create branch \"feature/search-v2\"").unwrap();
+        assert_eq!(result.command, "git branch feature/search-v2");
+
+        let result = plugin.handle("stage the file \"release notes.md\"").unwrap();
+        assert_eq!(result.command, "git add 'release notes.md'");
+
+        // Names are quoted for the shell in the context
+        let quoted = |shell| {
+            let context = PluginContext { shell: Some(shell), ..Default::default() };
+            plugin
+                .handle_with_context("commit with message \"it's done\"", &context)
+                .unwrap()
+                .command
+        };
+        assert_eq!(quoted(Shell::Bash), "git commit -m 'it'\\''s done'");
+        assert_eq!(quoted(Shell::PowerShell), "git commit -m 'it''s done'");
+        assert_eq!(quoted(Shell::Cmd), "git commit -m \"it's done\"");
+    }
+
+    #[test]
+    fn test_machine_readable() {
+        let plugin = GitPlugin::new();
+        let context = PluginContext { machine_readable: true, ..Default::default() };
+
+        let result = plugin.handle_with_context("show git status", &context).unwrap();
+        assert_eq!(result.command, "git status --porcelain=v2");
+
+        // Commands without a machine-readable variant are unchanged
+        let result = plugin.handle_with_context("git push", &context).unwrap();
+        assert_eq!(result.command, "git push");
+    }
+}
@@ -1,9 +1,18 @@
+pub mod declarative;
 pub mod docker;
 pub mod git;
 pub mod manager;
+pub mod network;
 pub mod traits;
+pub mod translate;
+mod util;
+pub mod watcher;
 
+pub use declarative::{DeclarativePlugin, PluginLoadError, Rule};
 pub use docker::DockerPlugin;
 pub use git::GitPlugin;
 pub use manager::PluginManager;
-pub use traits::{CommandResult, Plugin};
+pub use network::NetworkPlugin;
+pub use traits::{CommandResult, Plugin, PluginContext, ToolAvailability};
+pub use translate::ShellTranslatePlugin;
+pub use watcher::PluginWatcher;
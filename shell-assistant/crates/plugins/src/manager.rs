@@ -1,9 +1,14 @@
-use crate::traits::{CommandResult, Plugin};
+use crate::declarative::{plugin_files, DeclarativePlugin, PluginLoadError};
+use crate::traits::{CommandResult, Plugin, PluginContext};
+use crate::{DockerPlugin, GitPlugin, NetworkPlugin, ShellTranslatePlugin};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 /// Plugin manager that maintains a registry of plugins and handles dispatching
 pub struct PluginManager {
     plugins: Vec<Arc<dyn Plugin + Send + Sync>>,
+    /// Plugins loaded from `*.toml` files, keyed by their file, tried after the built-in ones
+    declarative: Vec<(PathBuf, Arc<DeclarativePlugin>)>,
 }
 
 impl Default for PluginManager {
@@ -15,7 +20,54 @@ impl Default for PluginManager {
 impl PluginManager {
     /// Create a new plugin manager
     pub fn new() -> Self {
-        PluginManager { plugins: Vec::new() }
+        PluginManager { plugins: Vec::new(), declarative: Vec::new() }
+    }
+
+    /// Create a plugin manager with the built-in plugins the CLI uses, so library users get
+    /// the same baseline. More plugins can be added with `register_plugin`, and are tried
+    /// after the built-in ones.
+    ///
+    /// ```
+    /// use plugins::{CommandResult, Plugin, PluginManager};
+    ///
+    /// struct UptimePlugin;
+    ///
+    /// impl Plugin for UptimePlugin {
+    ///     fn name(&self) -> &str {
+    ///         "uptime"
+    ///     }
+    ///
+    ///     fn description(&self) -> &str {
+    ///         "Shows how long the machine has been running"
+    ///     }
+    ///
+    ///     fn can_handle(&self, input: &str) -> bool {
+    ///         input.contains("uptime")
+    ///     }
+    ///
+    ///     fn handle(&self, _input: &str) -> Option<CommandResult> {
+    ///         Some(CommandResult {
+    ///             command: "uptime".to_string(),
+    ///             explanation: "Shows how long the machine has been running.".to_string(),
+    ///             ..CommandResult::default()
+    ///         })
+    ///     }
+    /// }
+    ///
+    /// let mut manager = PluginManager::with_default_plugins();
+    /// manager.register_plugin(UptimePlugin);
+    ///
+    /// assert!(manager.get_plugin("git").is_some());
+    /// assert_eq!(manager.process("show the uptime").unwrap().command, "uptime");
+    /// ```
+    pub fn with_default_plugins() -> Self {
+        let mut manager = Self::new();
+        // Translation requests name the command to translate, which other plugins would grab
+        manager.register_plugin(ShellTranslatePlugin::new());
+        manager.register_plugin(GitPlugin::new());
+        manager.register_plugin(DockerPlugin::new());
+        manager.register_plugin(NetworkPlugin::new());
+        manager
     }
 
     /// Register a plugin with the manager
@@ -26,34 +78,223 @@ impl PluginManager {
         self.plugins.push(Arc::new(plugin));
     }
 
+    /// Load the declarative plugins in `dir`, replacing any loaded before.
+    /// Built-in plugins are kept. A file that fails to load keeps its previously
+    /// loaded version (if any), and its error is returned so it can be reported.
+    /// A missing directory simply means there are no declarative plugins.
+    pub fn reload_from_dir(&mut self, dir: &Path) -> Vec<PluginLoadError> {
+        if !dir.exists() {
+            self.declarative.clear();
+            return Vec::new();
+        }
+
+        let files = match plugin_files(dir) {
+            Ok(files) => files,
+            Err(e) => return vec![PluginLoadError::Io(dir.to_path_buf(), e)],
+        };
+
+        let mut errors = Vec::new();
+        let mut declarative = Vec::new();
+        for path in files {
+            match DeclarativePlugin::from_file(&path) {
+                Ok(plugin) => declarative.push((path, Arc::new(plugin))),
+                Err(e) => {
+                    if let Some((_, previous)) = self.declarative.iter().find(|(p, _)| *p == path) {
+                        declarative.push((path, previous.clone()));
+                    }
+                    errors.push(e);
+                }
+            }
+        }
+
+        self.declarative = declarative;
+        errors
+    }
+
+    /// Get the number of loaded declarative plugins
+    pub fn declarative_count(&self) -> usize {
+        self.declarative.len()
+    }
+
+    // Built-in plugins first, then declarative ones
+    fn all_plugins(&self) -> impl Iterator<Item = &(dyn Plugin + Send + Sync)> {
+        self.plugins
+            .iter()
+            .map(|p| p.as_ref())
+            .chain(self.declarative.iter().map(|(_, p)| p.as_ref() as &(dyn Plugin + Send + Sync)))
+    }
+
     /// Process input through all registered plugins
-    /// Returns the first matching result, or None if no plugin can handle the input
+    /// Returns the first valid result, or None if no plugin can handle the input
     pub fn process(&self, input: &str) -> Option<CommandResult> {
-        for plugin in &self.plugins {
+        for plugin in self.all_plugins() {
             if plugin.can_handle(input) {
                 if let Some(result) = plugin.handle(input) {
-                    return Some(result);
+                    if plugin.validate(&result).is_ok() {
+                        return Some(result);
+                    }
                 }
             }
         }
         None
     }
 
+    /// Run every plugin that can handle the input, returning each one's outcome from
+    /// `run_plugin` with the name of its plugin, in the order `process` tries them.
+    /// Plugins that produce no commands are left out. Lets the caller choose when
+    /// several plugins match, rather than relying on registration order.
+    pub fn process_all(
+        &self,
+        input: &str,
+        context: &PluginContext,
+    ) -> Vec<(String, Result<Vec<CommandResult>, String>)> {
+        self.all_plugins()
+            .filter(|plugin| plugin.can_handle(input))
+            .filter_map(|plugin| match self.run_plugin(plugin, input, context) {
+                Ok(steps) if steps.is_empty() => None,
+                outcome => Some((plugin.name().to_string(), outcome)),
+            })
+            .collect()
+    }
+
+    /// Run a plugin on the input and validate each command it produces.
+    /// Returns the commands to run in order (empty if the plugin had none), or
+    /// the reason the plugin rejected one of its own commands.
+    ///
+    /// In a dry run, results a plugin claims to have executed are handed back as plain
+    /// commands, so their output is never presented as if it had been run.
+    pub fn run_plugin(
+        &self,
+        plugin: &(dyn Plugin + Send + Sync),
+        input: &str,
+        context: &PluginContext,
+    ) -> Result<Vec<CommandResult>, String> {
+        let mut steps = plugin.handle_multi(input, context);
+        for step in &mut steps {
+            plugin.validate(step)?;
+            if context.dry_run {
+                step.executed = false;
+                step.output = None;
+            }
+        }
+        Ok(steps)
+    }
+
     /// Get a reference to a plugin by name
     pub fn get_plugin(&self, name: &str) -> Option<&(dyn Plugin + Send + Sync)> {
-        self.plugins
-            .iter()
-            .find(|p| p.name().to_lowercase() == name.to_lowercase())
-            .map(|p| p.as_ref())
+        self.all_plugins().find(|p| p.name().to_lowercase() == name.to_lowercase())
     }
 
     /// Get a list of all registered plugins
     pub fn list_plugins(&self) -> Vec<(&str, &str)> {
-        self.plugins.iter().map(|p| (p.name(), p.description())).collect()
+        self.all_plugins().map(|p| (p.name(), p.description())).collect()
     }
 
     /// Get the number of registered plugins
     pub fn plugin_count(&self) -> usize {
-        self.plugins.len()
+        self.plugins.len() + self.declarative.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    // A plugin that (wrongly) claims to have run its command even in a dry run
+    struct EagerPlugin;
+
+    impl Plugin for EagerPlugin {
+        fn name(&self) -> &str {
+            "eager"
+        }
+
+        fn description(&self) -> &str {
+            "Runs commands itself"
+        }
+
+        fn can_handle(&self, _input: &str) -> bool {
+            true
+        }
+
+        fn handle(&self, _input: &str) -> Option<CommandResult> {
+            Some(CommandResult {
+                command: "date".to_string(),
+                explanation: "Shows the date.".to_string(),
+                executed: true,
+                output: Some("Mon Jan  1 00:00:00 UTC 2024".to_string()),
+                requires_network: false,
+            })
+        }
+    }
+
+    #[test]
+    fn test_dry_run_results_are_not_executed() {
+        let manager = PluginManager::new();
+        let context = PluginContext { dry_run: true, ..Default::default() };
+
+        let steps = manager.run_plugin(&EagerPlugin, "what day is it", &context).unwrap();
+        assert_eq!(steps[0].command, "date");
+        assert!(!steps[0].executed);
+        assert_eq!(steps[0].output, None);
+    }
+
+    #[test]
+    fn test_process_all_returns_every_match() {
+        let mut manager = PluginManager::new();
+        manager.register_plugin(GitPlugin::new());
+        manager.register_plugin(EagerPlugin);
+
+        let context = PluginContext::default();
+        let matches = manager.process_all("git status", &context);
+        let names: Vec<&str> = matches.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["git", "eager"]);
+        assert_eq!(
+            matches[0].1.as_ref().unwrap()[0].command,
+            manager.process("git status").unwrap().command
+        );
+
+        // Invalid results are reported with the reason
+        let matches = manager.process_all("git add", &context);
+        assert_eq!(
+            matches.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(),
+            ["git", "eager"]
+        );
+        assert!(matches[0].1.is_err());
+        assert!(matches[1].1.is_ok());
+    }
+
+    #[test]
+    fn test_reload_keeps_builtins_and_last_good_version() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let file = dir.join("greet.toml");
+        fs::write(
+            &file,
+            "name = \"greet\"\nkeywords = [\"greet\"]\n\n[[rules]]\npatterns = [\"greet\"]\ncommand = \"echo hi\"\n",
+        )
+        .unwrap();
+
+        let mut manager = PluginManager::new();
+        manager.register_plugin(GitPlugin::new());
+        assert!(manager.reload_from_dir(dir).is_empty());
+        assert_eq!(manager.plugin_count(), 2);
+        assert_eq!(manager.process("greet me").unwrap().command, "echo hi");
+
+        // Invalid results are rejected
+        let git = manager.get_plugin("git").unwrap();
+        assert!(manager.run_plugin(git, "git add", &PluginContext::default()).is_err());
+        assert_eq!(manager.process("git add").map(|r| r.command), None);
+
+        // A malformed edit keeps the previous definition
+        fs::write(&file, "name = \"greet\"\nkeywords = [").unwrap();
+        assert_eq!(manager.reload_from_dir(dir).len(), 1);
+        assert_eq!(manager.process("greet me").unwrap().command, "echo hi");
+
+        // Removed files are unloaded, built-ins stay
+        fs::remove_file(&file).unwrap();
+        assert!(manager.reload_from_dir(dir).is_empty());
+        assert!(manager.get_plugin("greet").is_none());
+        assert!(manager.get_plugin("git").is_some());
     }
 }
@@ -0,0 +1,275 @@
+use crate::traits::{quote, reject_trailing_placeholder, CommandResult, Plugin, PluginContext};
+use core::{detect_os_version, OsInfo, Shell, ShellKind};
+
+pub struct NetworkPlugin;
+
+impl Default for NetworkPlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NetworkPlugin {
+    pub fn new() -> Self {
+        NetworkPlugin
+    }
+}
+
+impl Plugin for NetworkPlugin {
+    fn name(&self) -> &str {
+        "network"
+    }
+
+    fn description(&self) -> &str {
+        "Provides network diagnostics commands"
+    }
+
+    fn can_handle(&self, input: &str) -> bool {
+        // Whole words only, so "report" or "shipping" don't count
+        let input_lower = input.to_lowercase();
+        ["ping", "port", "ip", "traceroute", "dns", "lookup"]
+            .iter()
+            .any(|word| has_word(&input_lower, word))
+            || input_lower.contains("trace route")
+    }
+
+    fn validate(&self, result: &CommandResult) -> Result<(), String> {
+        reject_trailing_placeholder(result)
+    }
+
+    fn handle(&self, input: &str) -> Option<CommandResult> {
+        if !self.can_handle(input) {
+            return None;
+        }
+        network_command(input, Shell::detect(), &detect_os_version())
+    }
+
+    fn handle_with_context(&self, input: &str, context: &PluginContext) -> Option<CommandResult> {
+        if !self.can_handle(input) {
+            return None;
+        }
+        network_command(input, context.shell(), &context.os())
+    }
+}
+
+// Build the command for the given shell and OS version, since Windows has its own diagnostics
+// tools and older Linux releases lack `ip`
+fn network_command(input: &str, shell: Shell, os: &OsInfo) -> Option<CommandResult> {
+    let input_lower = input.to_lowercase();
+    let windows = shell.kind() != Some(ShellKind::Sh);
+    // cmd runs the Windows tools too, but the PowerShell cmdlets only through PowerShell
+    let cmdlet = if shell == Shell::Cmd { "powershell " } else { "" };
+    let host = extract_host(input).map(|host| quote(&host, shell)).unwrap_or_default();
+
+    if has_word(&input_lower, "port") {
+        let Some(port) = extract_port(input).filter(|_| !host.is_empty()) else {
+            return Some(network_result(
+                &if windows {
+                    format!("{}Test-NetConnection ", cmdlet)
+                } else {
+                    "nc -zv ".to_string()
+                },
+                "Checks whether a port is open. You'll need to specify the host and port.",
+            ));
+        };
+
+        let command = if windows {
+            format!("{}Test-NetConnection {} -Port {}", cmdlet, host, port)
+        } else {
+            format!("nc -zv {} {}", host, port)
+        };
+        return Some(network_result(
+            &command,
+            &format!("Checks whether port {} accepts connections.", port),
+        ));
+    }
+
+    if has_word(&input_lower, "traceroute") || input_lower.contains("trace route") {
+        let tool = if windows { "tracert" } else { "traceroute" };
+        return Some(network_result(
+            &format!("{} {}", tool, host),
+            "Shows the route packets take to the host, one hop per line.",
+        ));
+    }
+
+    if has_word(&input_lower, "dns") || has_word(&input_lower, "lookup") {
+        let tool = if windows { format!("{}Resolve-DnsName", cmdlet) } else { "dig".to_string() };
+        return Some(network_result(
+            &format!("{} {}", tool, host),
+            "Looks up the DNS records of the host.",
+        ));
+    }
+
+    if has_word(&input_lower, "ip") {
+        if input_lower.contains("public") || input_lower.contains("external") {
+            let curl = if windows { "curl.exe" } else { "curl" };
+            return Some(network_result(
+                &format!("{} -s ifconfig.me", curl),
+                "Shows your public IP address, as seen by ifconfig.me.",
+            ));
+        }
+
+        let command = if windows {
+            "ipconfig"
+        } else if os.is_modern_linux() {
+            "ip addr"
+        } else {
+            "ifconfig"
+        };
+        return Some(CommandResult {
+            command: command.to_string(),
+            explanation: "Shows the IP addresses of this machine's network interfaces.".to_string(),
+            ..CommandResult::default()
+        });
+    }
+
+    if has_word(&input_lower, "ping") {
+        // Windows ping stops after 4 requests by default, ping elsewhere runs until interrupted
+        let command =
+            if windows { format!("ping {}", host) } else { format!("ping -c 4 {}", host) };
+        return Some(network_result(
+            &command,
+            "Sends 4 ping requests to check the host is reachable.",
+        ));
+    }
+
+    None
+}
+
+fn network_result(command: &str, explanation: &str) -> CommandResult {
+    CommandResult {
+        command: command.to_string(),
+        explanation: explanation.to_string(),
+        requires_network: true,
+        ..CommandResult::default()
+    }
+}
+
+fn has_word(input_lower: &str, word: &str) -> bool {
+    input_lower.split_whitespace().any(|w| w.trim_matches(|c: char| !c.is_alphanumeric()) == word)
+}
+
+// Helper functions for extracting information from input
+fn extract_host(input: &str) -> Option<String> {
+    const KEYWORDS: [&str; 7] = ["ping", "host", "to", "on", "for", "lookup", "of"];
+    // Words that can follow a keyword without being the host, as in "open on host example.com"
+    const FILLERS: [&str; 5] = ["a", "the", "host", "server", "is"];
+
+    let words: Vec<&str> = input.split_whitespace().collect();
+    words.windows(2).find_map(|pair| {
+        if !KEYWORDS.contains(&pair[0].to_lowercase().as_str()) {
+            return None;
+        }
+
+        let host = pair[1].trim_matches(|c: char| {
+            !c.is_alphanumeric() && c != '.' && c != '-' && c != ':' && c != '[' && c != ']'
+        });
+        let host = host.trim_end_matches('.');
+        let host_lower = host.to_lowercase();
+        if host.is_empty()
+            || KEYWORDS.contains(&host_lower.as_str())
+            || FILLERS.contains(&host_lower.as_str())
+        {
+            return None;
+        }
+
+        Some(host.to_string())
+    })
+}
+
+fn extract_port(input: &str) -> Option<u16> {
+    let words: Vec<&str> = input.split_whitespace().collect();
+    let idx = words.iter().position(|&w| w.to_lowercase() == "port")?;
+
+    words.get(idx + 1)?.trim_matches(|c: char| !c.is_ascii_digit()).parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{OsFamily, TargetOs};
+
+    fn modern_linux() -> OsInfo {
+        OsInfo::new(OsFamily::Linux).with_release("ubuntu", "Ubuntu", "22.04")
+    }
+
+    fn command(input: &str, shell: Shell) -> String {
+        network_command(input, shell, &modern_linux()).unwrap().command
+    }
+
+    #[test]
+    fn test_commands_per_shell() {
+        let cases = [
+            ("ping example.com", "ping -c 4 example.com", "ping example.com"),
+            (
+                "check if port 443 is open on host example.com",
+                "nc -zv example.com 443",
+                "Test-NetConnection example.com -Port 443",
+            ),
+            ("trace route to 10.0.0.5", "traceroute 10.0.0.5", "tracert 10.0.0.5"),
+            ("dns lookup example.com", "dig example.com", "Resolve-DnsName example.com"),
+            ("show my public ip", "curl -s ifconfig.me", "curl.exe -s ifconfig.me"),
+        ];
+
+        for (input, sh, powershell) in cases {
+            assert_eq!(command(input, Shell::Bash), sh, "{}", input);
+            assert_eq!(command(input, Shell::PowerShell), powershell, "{}", input);
+        }
+        assert_eq!(command("show my ip", Shell::PowerShell), "ipconfig");
+
+        // cmd gets the Windows tools, running the cmdlets through PowerShell
+        assert_eq!(command("show my ip", Shell::Cmd), "ipconfig");
+        assert_eq!(command("trace route to 10.0.0.5", Shell::Cmd), "tracert 10.0.0.5");
+        assert_eq!(
+            command("dns lookup example.com", Shell::Cmd),
+            "powershell Resolve-DnsName example.com"
+        );
+    }
+
+    #[test]
+    fn test_ip_command_per_os_version() {
+        let ip = |os: OsInfo| network_command("show my ip", Shell::Bash, &os).unwrap().command;
+
+        assert_eq!(ip(modern_linux()), "ip addr");
+        assert_eq!(
+            ip(OsInfo::new(OsFamily::Linux).with_release("centos", "CentOS Linux", "6")),
+            "ifconfig"
+        );
+        assert_eq!(ip(OsInfo::new(OsFamily::MacOs)), "ifconfig");
+
+        // The plugin uses the OS from the context
+        let context = PluginContext { os: Some(modern_linux()), ..Default::default() };
+        let result = NetworkPlugin::new().handle_with_context("show my ip", &context).unwrap();
+        assert_eq!(result.command, "ip addr");
+
+        // and generates commands for the target OS rather than the host
+        let context = PluginContext { target_os: Some(TargetOs::Windows), ..Default::default() };
+        let result = NetworkPlugin::new().handle_with_context("show my ip", &context).unwrap();
+        assert_eq!(result.command, "ipconfig");
+    }
+
+    #[test]
+    fn test_requires_network() {
+        assert!(
+            network_command("ping example.com", Shell::Bash, &modern_linux())
+                .unwrap()
+                .requires_network
+        );
+        assert!(
+            !network_command("show my ip", Shell::Bash, &modern_linux()).unwrap().requires_network
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_missing_host() {
+        let plugin = NetworkPlugin::new();
+
+        let result = plugin.handle("ping the server").unwrap();
+        assert!(plugin.validate(&result).is_err());
+
+        let result = plugin.handle("is port 22 open").unwrap();
+        assert!(plugin.validate(&result).is_err());
+
+        assert!(!plugin.can_handle("generate a report of shipping costs"));
+    }
+}
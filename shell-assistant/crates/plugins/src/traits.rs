@@ -1,5 +1,11 @@
+use crate::declarative::DeclarativePlugin;
+use core::{detect_os_version, OsFamily, OsInfo, Shell, TargetOs};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
 /// Represents the result of a command execution by a plugin
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct CommandResult {
     /// The actual shell command to be executed
     pub command: String,
@@ -12,6 +18,134 @@ pub struct CommandResult {
 
     /// The output of the command if it was executed
     pub output: Option<String>,
+
+    /// Whether the command needs network access to do anything useful
+    pub requires_network: bool,
+}
+
+impl CommandResult {
+    /// Combine steps that must run in order into a single result. The commands are
+    /// chained with `&&`, so later steps only run if the earlier ones succeed.
+    pub fn combine(steps: Vec<CommandResult>) -> Option<CommandResult> {
+        if steps.len() <= 1 {
+            return steps.into_iter().next();
+        }
+
+        let outputs: Vec<String> = steps.iter().filter_map(|step| step.output.clone()).collect();
+        Some(CommandResult {
+            command: steps
+                .iter()
+                .map(|step| step.command.as_str())
+                .collect::<Vec<_>>()
+                .join(" && "),
+            explanation: steps
+                .iter()
+                .map(|step| step.explanation.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+            executed: steps.iter().all(|step| step.executed),
+            output: if outputs.is_empty() { None } else { Some(outputs.join("\n")) },
+            requires_network: steps.iter().any(|step| step.requires_network),
+        })
+    }
+}
+
+/// Options that affect how plugins generate commands
+#[derive(Debug, Clone, Default)]
+pub struct PluginContext {
+    /// Prefer machine-readable (JSON/porcelain) variants of commands where they exist
+    pub machine_readable: bool,
+    /// Nothing may be executed. Plugins that run commands themselves must return them
+    /// with `executed: false` and no output instead.
+    pub dry_run: bool,
+    /// Which tools are installed, so plugins can leave requests for missing tools to the LLM
+    pub tools: ToolAvailability,
+    /// The OS version, so plugins can pick modern or legacy tools. None means the host's,
+    /// detected when needed, or just the family of `target_os` when that isn't the host.
+    pub os: Option<OsInfo>,
+    /// The OS commands are generated for. None means the host.
+    pub target_os: Option<TargetOs>,
+    /// The shell commands are written for. None means the usual shell of `target_os`, or
+    /// the user's own shell when generating for the host.
+    pub shell: Option<Shell>,
+}
+
+impl PluginContext {
+    /// The OS version commands are generated for
+    pub fn os(&self) -> OsInfo {
+        if let Some(os) = &self.os {
+            return os.clone();
+        }
+        match self.target_os {
+            Some(TargetOs::Windows) if TargetOs::host() != TargetOs::Windows => {
+                OsInfo::new(OsFamily::Windows)
+            }
+            Some(TargetOs::Unix) if TargetOs::host() != TargetOs::Unix => {
+                OsInfo::new(OsFamily::Other)
+            }
+            _ => detect_os_version(),
+        }
+    }
+
+    /// The shell commands are written for
+    pub fn shell(&self) -> Shell {
+        self.shell.unwrap_or_else(|| match self.target_os {
+            Some(target) if target != TargetOs::host() => Shell::default_for(target),
+            _ => Shell::detect(),
+        })
+    }
+}
+
+/// Checks whether command-line tools are installed, like `which`. Each tool is looked up
+/// on the PATH once; clones share the results, so one instance can serve a whole run.
+#[derive(Debug, Clone, Default)]
+pub struct ToolAvailability {
+    known: Arc<Mutex<HashMap<String, bool>>>,
+}
+
+impl ToolAvailability {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Treat `tool` as installed or missing without looking it up
+    pub fn with_tool(self, tool: &str, installed: bool) -> Self {
+        if let Ok(mut known) = self.known.lock() {
+            known.insert(tool.to_string(), installed);
+        }
+        self
+    }
+
+    /// Whether `tool` is an executable on the PATH
+    pub fn is_installed(&self, tool: &str) -> bool {
+        let Ok(mut known) = self.known.lock() else {
+            return on_path(tool);
+        };
+        *known.entry(tool.to_string()).or_insert_with(|| on_path(tool))
+    }
+}
+
+fn on_path(tool: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    // Windows executables need an extension, e.g. docker.exe
+    let extensions: &[&str] = if cfg!(windows) { &[".exe", ".cmd", ".bat"] } else { &[""] };
+
+    std::env::split_paths(&path).any(|dir| {
+        extensions.iter().any(|ext| is_executable(&dir.join(format!("{}{}", tool, ext))))
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
 }
 
 pub trait Plugin {
@@ -27,6 +161,30 @@ pub trait Plugin {
     /// Processes the natural language input and returns a command result if applicable
     fn handle(&self, input: &str) -> Option<CommandResult>;
 
+    /// Processes the input taking the context into account. Defaults to `handle`.
+    fn handle_with_context(&self, input: &str, _context: &PluginContext) -> Option<CommandResult> {
+        self.handle(input)
+    }
+
+    /// Processes the input into one or more commands that must run in order,
+    /// e.g. a setup command followed by the main one. Defaults to the result of
+    /// `handle_with_context`.
+    fn handle_multi(&self, input: &str, context: &PluginContext) -> Vec<CommandResult> {
+        self.handle_with_context(input, context).into_iter().collect()
+    }
+
+    /// Checks a result this plugin produced, returning why it is unusable (e.g. an
+    /// incomplete command) so the caller can fall back to something else
+    fn validate(&self, _result: &CommandResult) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// A declarative definition that approximates this plugin, as a starting point for
+    /// writing one. None if the plugin can't be described that way.
+    fn to_declarative(&self) -> Option<DeclarativePlugin> {
+        None
+    }
+
     /// Process method that calls handle if can_handle returns true
     fn process(&self, input: &str) -> Option<CommandResult> {
         if self.can_handle(input) {
@@ -36,3 +194,42 @@ pub trait Plugin {
         }
     }
 }
+
+/// Swap a result's command for its machine-readable variant from `variants`
+/// (pairs of normal and machine-readable commands), if it has one
+pub(crate) fn prefer_machine_readable(
+    mut result: CommandResult,
+    variants: &[(&str, &str)],
+) -> CommandResult {
+    if let Some((_, machine_readable)) =
+        variants.iter().find(|(command, _)| *command == result.command)
+    {
+        result.command = machine_readable.to_string();
+        result.explanation.push_str(" Output is in a machine-readable format.");
+    }
+    result
+}
+
+/// Quote an extracted name or path for `shell`. An empty name stays empty so
+/// `reject_trailing_placeholder` still catches it.
+pub(crate) fn quote(arg: &str, shell: Shell) -> String {
+    if arg.is_empty() {
+        return String::new();
+    }
+    match shell.kind() {
+        Some(kind) => core::shell_quote(arg, kind),
+        // cmd only groups words with double quotes, which programs read `""` inside of as one
+        None if arg.chars().any(|c| c.is_whitespace() || "\"&|<>^%".contains(c)) => {
+            format!("\"{}\"", arg.replace('"', "\"\""))
+        }
+        None => arg.to_string(),
+    }
+}
+
+/// Reject commands that end in a blank placeholder, like `git add `
+pub(crate) fn reject_trailing_placeholder(result: &CommandResult) -> Result<(), String> {
+    if result.command.trim().is_empty() || result.command.ends_with(char::is_whitespace) {
+        return Err(format!("'{}' is missing arguments", result.command.trim()));
+    }
+    Ok(())
+}
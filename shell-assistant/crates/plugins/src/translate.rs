@@ -0,0 +1,226 @@
+use crate::traits::{CommandResult, Plugin};
+use core::ShellKind;
+
+/// Common POSIX shell commands and their PowerShell equivalents. When translating,
+/// the longest matching entry wins, with earlier entries winning ties.
+const MAPPINGS: &[(&str, &str)] = &[
+    ("ls", "Get-ChildItem"),
+    ("ls -la", "Get-ChildItem -Force"),
+    ("ls -al", "Get-ChildItem -Force"),
+    ("ls -a", "Get-ChildItem -Force"),
+    ("ls -l", "Get-ChildItem"),
+    ("ls -R", "Get-ChildItem -Recurse"),
+    ("cat", "Get-Content"),
+    ("rm", "Remove-Item"),
+    ("rm -rf", "Remove-Item -Recurse -Force"),
+    ("rm -r", "Remove-Item -Recurse"),
+    ("rm -f", "Remove-Item -Force"),
+    ("cp", "Copy-Item"),
+    ("cp -r", "Copy-Item -Recurse"),
+    ("mv", "Move-Item"),
+    ("mkdir", "New-Item -ItemType Directory"),
+    ("mkdir -p", "New-Item -ItemType Directory -Force"),
+    ("touch", "New-Item -ItemType File"),
+    ("pwd", "Get-Location"),
+    ("cd", "Set-Location"),
+    // grep is case-sensitive by default, Select-String isn't
+    ("grep", "Select-String -CaseSensitive"),
+    ("grep -i", "Select-String"),
+    ("echo", "Write-Output"),
+    ("ps", "Get-Process"),
+    ("kill", "Stop-Process -Id"),
+    ("kill -9", "Stop-Process -Force -Id"),
+    ("clear", "Clear-Host"),
+    ("which", "Get-Command"),
+    ("sort", "Sort-Object"),
+    ("uniq", "Get-Unique"),
+    ("wc -l", "Measure-Object -Line"),
+    ("head -n", "Select-Object -First"),
+    ("tail -n", "Select-Object -Last"),
+];
+
+pub struct ShellTranslatePlugin;
+
+impl Default for ShellTranslatePlugin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShellTranslatePlugin {
+    pub fn new() -> Self {
+        ShellTranslatePlugin
+    }
+}
+
+impl Plugin for ShellTranslatePlugin {
+    fn name(&self) -> &str {
+        "translate"
+    }
+
+    fn description(&self) -> &str {
+        "Translates commands between bash and PowerShell"
+    }
+
+    fn can_handle(&self, input: &str) -> bool {
+        let Some((instruction, _)) = input.split_once(':') else {
+            return false;
+        };
+        let instruction = instruction.to_lowercase();
+        (instruction.contains("convert")
+            || instruction.contains("translate")
+            || instruction.contains("equivalent"))
+            && target_shell(&instruction).is_some()
+    }
+
+    // Commands with parts that aren't in the mapping table are left to the LLM
+    fn handle(&self, input: &str) -> Option<CommandResult> {
+        if !self.can_handle(input) {
+            return None;
+        }
+
+        let (instruction, command) = input.split_once(':')?;
+        let target = target_shell(&instruction.to_lowercase())?;
+        let command = unwrap_quotes(command.trim());
+        if command.is_empty() {
+            return None;
+        }
+
+        let (translated, used) = translate(command, target)?;
+        let (from, to) = match target {
+            ShellKind::PowerShell => ("bash", "PowerShell"),
+            ShellKind::Sh => ("PowerShell", "bash"),
+        };
+        let mappings: Vec<String> =
+            used.iter().map(|(source, target)| format!("`{}` → `{}`", source, target)).collect();
+
+        Some(CommandResult {
+            explanation: format!("Translated from {} to {}: {}.", from, to, mappings.join(", ")),
+            command: translated,
+            ..CommandResult::default()
+        })
+    }
+}
+
+// Strip quotes or backticks around the whole command, as in "convert to bash: `ls -la`"
+fn unwrap_quotes(command: &str) -> &str {
+    for quote in ['`', '"', '\''] {
+        if let Some(inner) = command.strip_prefix(quote).and_then(|c| c.strip_suffix(quote)) {
+            return inner.trim();
+        }
+    }
+    command
+}
+
+// The shell named after "to", "into" or "in", as in "convert this to powershell"
+fn target_shell(instruction: &str) -> Option<ShellKind> {
+    let words: Vec<&str> = instruction
+        .split_whitespace()
+        .map(|w| w.trim_matches(|c: char| !c.is_alphanumeric()))
+        .collect();
+    words.windows(2).find_map(|pair| {
+        if !["to", "into", "in"].contains(&pair[0]) {
+            return None;
+        }
+        match pair[1] {
+            "powershell" | "pwsh" => Some(ShellKind::PowerShell),
+            "bash" | "sh" | "zsh" | "posix" | "linux" | "unix" => Some(ShellKind::Sh),
+            _ => None,
+        }
+    })
+}
+
+// Translate each stage of a pipeline, returning the result and the mappings used.
+// Returns None if any stage has a command or option the table doesn't cover.
+fn translate(command: &str, target: ShellKind) -> Option<(String, Vec<(&str, &str)>)> {
+    let mut stages = Vec::new();
+    let mut used = Vec::new();
+
+    for stage in command.split('|') {
+        let words: Vec<&str> = stage.split_whitespace().collect();
+        let (source, target_command, matched) = MAPPINGS
+            .iter()
+            .map(|&(sh, ps)| match target {
+                ShellKind::PowerShell => (sh, ps),
+                ShellKind::Sh => (ps, sh),
+            })
+            .filter_map(|(source, target_command)| {
+                let source_words: Vec<&str> = source.split_whitespace().collect();
+                let matches = words.len() >= source_words.len()
+                    && words.iter().zip(&source_words).all(|(word, source_word)| match target {
+                        // PowerShell is case-insensitive
+                        ShellKind::Sh => word.eq_ignore_ascii_case(source_word),
+                        ShellKind::PowerShell => word == source_word,
+                    });
+                matches.then_some((source, target_command, source_words.len()))
+            })
+            .fold(None, |best: Option<(&str, &str, usize)>, candidate| match best {
+                Some(best) if best.2 >= candidate.2 => Some(best),
+                _ => Some(candidate),
+            })?;
+
+        let args = &words[matched..];
+        if args.iter().any(|arg| arg.starts_with('-')) {
+            return None;
+        }
+
+        stages.push(
+            std::iter::once(target_command)
+                .chain(args.iter().copied())
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+        used.push((source, target_command));
+    }
+
+    Some((stages.join(" | "), used))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bash_to_powershell() {
+        let plugin = ShellTranslatePlugin::new();
+
+        let result = plugin.handle("convert this bash command to powershell: ls -la").unwrap();
+        assert_eq!(result.command, "Get-ChildItem -Force");
+        assert!(result.explanation.contains("`ls -la` → `Get-ChildItem -Force`"));
+
+        let result = plugin
+            .handle("translate to powershell: cat app.log | grep -i error | head -n 5")
+            .unwrap();
+        assert_eq!(
+            result.command,
+            "Get-Content app.log | Select-String error | Select-Object -First 5"
+        );
+    }
+
+    #[test]
+    fn test_powershell_to_bash() {
+        let plugin = ShellTranslatePlugin::new();
+
+        let result = plugin
+            .handle("what's the equivalent in bash: Remove-Item -Recurse -Force build")
+            .unwrap();
+        assert_eq!(result.command, "rm -rf build");
+
+        let result = plugin.handle("convert to bash: `get-childitem`").unwrap();
+        assert_eq!(result.command, "ls");
+
+        let result = plugin.handle("convert to bash: Write-Output 'hi there'").unwrap();
+        assert_eq!(result.command, "echo 'hi there'");
+    }
+
+    #[test]
+    fn test_complex_commands_are_left_to_the_llm() {
+        let plugin = ShellTranslatePlugin::new();
+
+        // Unknown commands and unmapped options
+        assert!(plugin.handle("convert to powershell: awk '{print $1}' file").is_none());
+        assert!(plugin.handle("convert to powershell: ls -lh").is_none());
+        // No target shell
+        assert!(!plugin.can_handle("convert this: ls -la"));
+    }
+}
@@ -0,0 +1,113 @@
+// Helpers for picking names and values out of requests, shared by the plugins
+
+/// A word of a request. A quoted phrase, such as "my custom image", is one word.
+pub(crate) struct Word<'a> {
+    pub text: &'a str,
+    pub quoted: bool,
+}
+
+impl Word<'_> {
+    /// Whether this is the given keyword. Quoted words are always values, never keywords.
+    pub fn is(&self, keyword: &str) -> bool {
+        !self.quoted && self.text.eq_ignore_ascii_case(keyword)
+    }
+
+    /// The word as a name or value. Quoted words are kept whole; other words lose the
+    /// surrounding characters `keep` rejects, as in "(web)," → "web".
+    pub fn value(&self, keep: impl Fn(char) -> bool) -> String {
+        if self.quoted {
+            return self.text.to_string();
+        }
+        self.text.trim_matches(|c: char| !keep(c)).to_string()
+    }
+}
+
+/// Split a request into words on whitespace, keeping a phrase in double quotes, single
+/// quotes or backticks together. Only a quote at the start of a word opens a phrase, so
+/// apostrophes as in "don't" are left alone.
+pub(crate) fn split_words(input: &str) -> Vec<Word<'_>> {
+    let mut words = Vec::new();
+    let mut rest = input.trim_start();
+
+    while !rest.is_empty() {
+        let phrase = rest
+            .chars()
+            .next()
+            .filter(|c| ['"', '\'', '`'].contains(c))
+            .and_then(|quote| rest[1..].find(quote))
+            .map(|len| &rest[1..1 + len]);
+
+        // A word runs to the next whitespace; anything after a closing quote is dropped
+        let start = phrase.map_or(0, |phrase| phrase.len() + 2);
+        let end = rest[start..].find(char::is_whitespace).map_or(rest.len(), |i| start + i);
+        words.push(match phrase {
+            Some(text) => Word { text, quoted: true },
+            None => Word { text: &rest[..end], quoted: false },
+        });
+        rest = rest[end..].trim_start();
+    }
+    words
+}
+
+/// The word after the first of the keywords, skipping keywords that follow each other as in
+/// "container named web". Unless it's quoted, the word loses the surrounding characters
+/// `keep` rejects. This is how plugins pick names and values out of a request.
+pub(crate) fn extract_after_keywords(
+    input: &str,
+    keywords: &[&str],
+    keep: impl Fn(char) -> bool,
+) -> Option<String> {
+    let words = split_words(input);
+    let is_keyword = |word: &Word| keywords.iter().any(|keyword| word.is(keyword));
+    let idx = words.iter().position(is_keyword)?;
+
+    let value = words[idx + 1..].iter().find(|word| !is_keyword(word))?;
+    Some(value.value(keep))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_name_char(c: char) -> bool {
+        c.is_alphanumeric() || c == '-' || c == '_' || c == '.'
+    }
+
+    #[test]
+    fn test_extract_after_keywords() {
+        // Keywords that follow each other are skipped, whichever comes first
+        let keywords = ["container", "named", "called"];
+        assert_eq!(
+            extract_after_keywords("stop the container named web", &keywords, is_name_char),
+            Some("web".to_string())
+        );
+        assert_eq!(
+            extract_after_keywords("stop container called db-1.", &keywords, is_name_char),
+            Some("db-1.".to_string())
+        );
+
+        // Surrounding punctuation is dropped, quoted phrases are kept whole
+        assert_eq!(
+            extract_after_keywords("remove container (web), please", &keywords, is_name_char),
+            Some("web".to_string())
+        );
+        assert_eq!(
+            extract_after_keywords("run image \"my custom image\"", &["image"], is_name_char),
+            Some("my custom image".to_string())
+        );
+
+        // A quoted word that looks like a keyword is still the value
+        assert_eq!(
+            extract_after_keywords("switch to branch \"to\"", &["branch", "to"], is_name_char),
+            Some("to".to_string())
+        );
+
+        // Keywords match whole words, ignoring case
+        assert_eq!(
+            extract_after_keywords("stage the FILE notes.md", &["file"], is_name_char),
+            Some("notes.md".to_string())
+        );
+        assert_eq!(extract_after_keywords("list files", &["file"], is_name_char), None);
+        assert_eq!(extract_after_keywords("stop the container", &keywords, is_name_char), None);
+    }
+}
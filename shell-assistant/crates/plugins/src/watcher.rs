@@ -0,0 +1,34 @@
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+
+/// Watches a directory of declarative plugins for changes to `*.toml` files
+pub struct PluginWatcher {
+    // Kept alive for as long as events should be delivered
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+}
+
+impl PluginWatcher {
+    /// Start watching `dir`, which must exist
+    pub fn new(dir: &Path) -> notify::Result<Self> {
+        let (sender, events) = channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+        Ok(PluginWatcher { _watcher: watcher, events })
+    }
+
+    /// Returns true if a plugin definition was added, changed or removed since
+    /// the last call. Never blocks.
+    pub fn has_changes(&self) -> bool {
+        let mut changed = false;
+        while let Ok(event) = self.events.try_recv() {
+            if let Ok(event) = event {
+                changed |= !event.kind.is_access()
+                    && event.paths.iter().any(|p| p.extension().is_some_and(|ext| ext == "toml"));
+            }
+        }
+        changed
+    }
+}
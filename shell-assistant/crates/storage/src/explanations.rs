@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// LLM-generated command explanations, kept on disk so each command is only explained once
+#[derive(Debug)]
+pub struct ExplanationCache {
+    path: PathBuf,
+    explanations: HashMap<String, String>,
+}
+
+impl ExplanationCache {
+    /// Load the cache from a JSON file. A missing file is an empty cache.
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let explanations = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(ExplanationCache { path, explanations })
+    }
+
+    /// Create an empty cache that will be saved to `path`
+    pub fn empty(path: impl Into<PathBuf>) -> Self {
+        ExplanationCache { path: path.into(), explanations: HashMap::new() }
+    }
+
+    /// Get the path of the cache file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the cached explanation of a command
+    pub fn get(&self, command: &str) -> Option<&str> {
+        self.explanations.get(command).map(String::as_str)
+    }
+
+    /// Cache the explanation of a command and save the cache
+    pub fn insert(&mut self, command: String, explanation: String) -> io::Result<()> {
+        self.explanations.insert(command, explanation);
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string(&self.explanations)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("explanations.json");
+
+        let mut cache = ExplanationCache::load(&path).unwrap();
+        assert_eq!(cache.get("git status"), None);
+        cache
+            .insert("git status".to_string(), "Shows the working tree status.".to_string())
+            .unwrap();
+
+        let cache = ExplanationCache::load(&path).unwrap();
+        assert_eq!(cache.get("git status"), Some("Shows the working tree status."));
+    }
+}
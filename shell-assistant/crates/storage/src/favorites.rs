@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A command bookmarked under a short name
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Favorite {
+    pub command: String,
+    pub explanation: Option<String>,
+}
+
+/// Commands the user bookmarked, kept on disk by name
+#[derive(Debug)]
+pub struct Favorites {
+    path: PathBuf,
+    favorites: BTreeMap<String, Favorite>,
+}
+
+impl Favorites {
+    /// Load the favorites from a JSON file. A missing file has no favorites.
+    pub fn load(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let favorites = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Favorites { path, favorites })
+    }
+
+    /// Get the path of the favorites file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Save a command under `name`, replacing any favorite of that name, and save the file
+    pub fn add_favorite(
+        &mut self,
+        name: &str,
+        command: String,
+        explanation: Option<String>,
+    ) -> io::Result<()> {
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid favorite name '{}': use a single word", name),
+            ));
+        }
+        self.favorites.insert(name.to_string(), Favorite { command, explanation });
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&self.favorites)?)
+    }
+
+    /// Get all favorites, sorted by name
+    pub fn list_favorites(&self) -> Vec<(&str, &Favorite)> {
+        self.favorites.iter().map(|(name, favorite)| (name.as_str(), favorite)).collect()
+    }
+
+    /// Get the favorite saved under `name`
+    pub fn get_favorite(&self, name: &str) -> Option<&Favorite> {
+        self.favorites.get(name.trim())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_get_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("favorites.json");
+
+        let mut favorites = Favorites::load(&path).unwrap();
+        assert!(favorites.list_favorites().is_empty());
+        favorites
+            .add_favorite(
+                "logs",
+                "tail -n 100 /var/log/syslog".to_string(),
+                Some("Shows the last 100 lines of the system log.".to_string()),
+            )
+            .unwrap();
+        favorites.add_favorite("disk", "df -h".to_string(), None).unwrap();
+        assert!(favorites.add_favorite("two words", "ls".to_string(), None).is_err());
+
+        let favorites = Favorites::load(&path).unwrap();
+        let logs = favorites.get_favorite("logs").unwrap();
+        assert_eq!(logs.command, "tail -n 100 /var/log/syslog");
+        assert_eq!(
+            logs.explanation.as_deref(),
+            Some("Shows the last 100 lines of the system log.")
+        );
+        assert_eq!(favorites.get_favorite("missing"), None);
+
+        let names: Vec<&str> = favorites.list_favorites().iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, ["disk", "logs"]);
+    }
+}
@@ -1,14 +1,148 @@
-use crate::persistence::{CommandEntry, CommandHistory as PersistentHistory, FeedbackType};
-use std::collections::VecDeque;
-use std::io;
+use crate::anonymize::{redact_secrets, Anonymizer};
+use crate::persistence::{
+    new_entry_id, CommandEntry, CommandHistory as PersistentHistory, FeedbackType,
+};
+use chrono::{DateTime, SecondsFormat};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 
 const DEFAULT_HISTORY_SIZE: usize = 100;
+/// Command output longer than this is truncated before it's stored
+pub const MAX_OUTPUT_CHARS: usize = 4000;
+
+/// Weights used to rank history entries in `CommandHistory::suggest`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SuggestionWeights {
+    /// Score for an input that starts with the partial input
+    pub prefix_match: f64,
+    /// Score for an input that contains the partial input's characters in order
+    pub fuzzy_match: f64,
+    /// Score scaled by how recent the entry is (newest gets the full weight)
+    pub recency: f64,
+    /// Score for entries marked helpful (or edited by the user)
+    pub helpful: f64,
+    /// Score for entries marked not helpful (usually negative)
+    pub not_helpful: f64,
+}
+
+impl Default for SuggestionWeights {
+    fn default() -> Self {
+        SuggestionWeights {
+            prefix_match: 2.0,
+            fuzzy_match: 1.0,
+            recency: 1.0,
+            helpful: 0.5,
+            not_helpful: -1.5,
+        }
+    }
+}
+
+/// A history entry as printed by `--history --json`, with an ISO-8601 timestamp and
+/// the feedback as a string
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntryJson {
+    pub id: String,
+    pub input: String,
+    pub command: String,
+    pub explanation: Option<String>,
+    /// UTC, e.g. `2024-05-01T12:30:00Z`
+    pub timestamp: String,
+    /// `helpful`, `not_helpful`, `edited` or `none`
+    pub feedback: String,
+    /// The generated command, when the user edited it into `command`
+    pub original_command: Option<String>,
+    pub output: Option<String>,
+    pub exit_code: Option<i32>,
+}
+
+impl From<&CommandEntry> for HistoryEntryJson {
+    fn from(entry: &CommandEntry) -> Self {
+        let timestamp = DateTime::from_timestamp(entry.timestamp as i64, 0)
+            .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+            .unwrap_or_default();
+        HistoryEntryJson {
+            id: entry.id.clone(),
+            input: entry.input.clone(),
+            command: entry.command.clone(),
+            explanation: entry.explanation.clone(),
+            timestamp,
+            feedback: feedback_name(entry.feedback).to_string(),
+            original_command: entry.original_command.clone(),
+            output: entry.output.clone(),
+            exit_code: entry.exit_code,
+        }
+    }
+}
+
+fn feedback_name(feedback: FeedbackType) -> &'static str {
+    match feedback {
+        FeedbackType::Helpful => "helpful",
+        FeedbackType::NotHelpful => "not_helpful",
+        FeedbackType::Edited => "edited",
+        FeedbackType::None => "none",
+    }
+}
+
+/// The differences between two histories, with entries matched on input and command
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct HistoryDiff {
+    /// Entries only in this history
+    pub only_here: Vec<HistoryEntryJson>,
+    /// Entries only in the other history
+    pub only_there: Vec<HistoryEntryJson>,
+    /// Entries in both histories whose feedback differs
+    pub feedback_differs: Vec<FeedbackDifference>,
+}
+
+impl HistoryDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_here.is_empty() && self.only_there.is_empty() && self.feedback_differs.is_empty()
+    }
+
+    /// The diff as pretty-printed JSON
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// An entry whose feedback differs between two histories, using the latest feedback of each
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FeedbackDifference {
+    pub input: String,
+    pub command: String,
+    /// The feedback in this history, e.g. `helpful`
+    pub here: String,
+    /// The feedback in the other history
+    pub there: String,
+}
+
+// Entries of two histories are the same when they have the same input and command
+fn entry_key(entry: &CommandEntry) -> (&str, &str) {
+    (&entry.input, &entry.command)
+}
+
+/// Whether `text` matches an exclude pattern: the pattern appears anywhere in it, with `*`
+/// standing for any run of characters. Matching is case-sensitive.
+pub fn matches_exclude_pattern(text: &str, pattern: &str) -> bool {
+    let mut rest = text;
+    for part in pattern.split('*').filter(|part| !part.is_empty()) {
+        match rest.find(part) {
+            Some(start) => rest = &rest[start + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
 
 #[derive(Debug, Clone)]
 pub struct CommandHistory {
     history: VecDeque<CommandEntry>,
     max_size: usize,
     file_path: Option<String>,
+    exclude_patterns: Vec<String>,
 }
 
 impl Default for CommandHistory {
@@ -23,6 +157,7 @@ impl CommandHistory {
             history: VecDeque::with_capacity(DEFAULT_HISTORY_SIZE),
             max_size: DEFAULT_HISTORY_SIZE,
             file_path: None,
+            exclude_patterns: Vec::new(),
         }
     }
 
@@ -32,6 +167,7 @@ impl CommandHistory {
             history: VecDeque::with_capacity(DEFAULT_HISTORY_SIZE),
             max_size: DEFAULT_HISTORY_SIZE,
             file_path: Some(file_path),
+            exclude_patterns: Vec::new(),
         };
 
         // Try to load existing history
@@ -45,7 +181,24 @@ impl CommandHistory {
     }
 
     pub fn with_capacity(max_size: usize) -> Self {
-        CommandHistory { history: VecDeque::with_capacity(max_size), max_size, file_path: None }
+        CommandHistory {
+            history: VecDeque::with_capacity(max_size),
+            max_size,
+            file_path: None,
+            exclude_patterns: Vec::new(),
+        }
+    }
+
+    /// Never record requests or commands matching any of these patterns (see
+    /// `matches_exclude_pattern`), e.g. ones touching a secrets folder
+    pub fn with_exclude_patterns(mut self, exclude_patterns: Vec<String>) -> Self {
+        self.exclude_patterns = exclude_patterns;
+        self
+    }
+
+    /// Whether a request or command may be recorded, i.e. it matches no exclude pattern
+    pub fn should_record(&self, text: &str) -> bool {
+        !self.exclude_patterns.iter().any(|pattern| matches_exclude_pattern(text, pattern))
     }
 
     pub fn set_max_size(&mut self, max_size: usize) {
@@ -55,7 +208,17 @@ impl CommandHistory {
         }
     }
 
-    pub fn add_entry(&mut self, input: String, command: String, explanation: Option<String>) {
+    /// Add an entry, unless its request or command is excluded. Returns whether it was added.
+    pub fn add_entry(
+        &mut self,
+        input: String,
+        command: String,
+        explanation: Option<String>,
+    ) -> bool {
+        if !self.should_record(&input) || !self.should_record(&command) {
+            return false;
+        }
+
         let timestamp =
             std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
 
@@ -64,12 +227,15 @@ impl CommandHistory {
         }
 
         self.history.push_back(CommandEntry {
+            id: new_entry_id(),
             input,
             command,
             explanation,
             timestamp,
             feedback: FeedbackType::None,
             original_command: None,
+            output: None,
+            exit_code: None,
         });
 
         // Save to file if persistence is enabled
@@ -78,6 +244,8 @@ impl CommandHistory {
                 eprintln!("Warning: Could not save history to file: {}", e);
             }
         }
+
+        true
     }
 
     /// Add entry with feedback and possibly edited command
@@ -88,7 +256,11 @@ impl CommandHistory {
         explanation: Option<String>,
         feedback: FeedbackType,
         original_command: Option<String>,
-    ) {
+    ) -> bool {
+        if !self.should_record(&input) || !self.should_record(&command) {
+            return false;
+        }
+
         let timestamp =
             std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
 
@@ -97,12 +269,15 @@ impl CommandHistory {
         }
 
         self.history.push_back(CommandEntry {
+            id: new_entry_id(),
             input,
             command,
             explanation,
             timestamp,
             feedback,
             original_command,
+            output: None,
+            exit_code: None,
         });
 
         // Save to file if persistence is enabled
@@ -111,6 +286,8 @@ impl CommandHistory {
                 eprintln!("Warning: Could not save history to file: {}", e);
             }
         }
+
+        true
     }
 
     /// Update the last entry with feedback
@@ -143,10 +320,121 @@ impl CommandHistory {
         }
     }
 
+    /// Get the entry with the given ID, which stays valid as older entries are trimmed
+    pub fn get_by_id(&self, id: &str) -> Option<&CommandEntry> {
+        self.history.iter().find(|entry| entry.id == id)
+    }
+
+    /// Store what the last entry's command printed, with secrets redacted and truncated to
+    /// `MAX_OUTPUT_CHARS`
+    pub fn update_last_entry_output(&mut self, output: &str) -> bool {
+        let Some(last_entry) = self.history.back_mut() else {
+            return false;
+        };
+        last_entry.output = Some(truncate_output(&redact_secrets(output)));
+
+        // Save to file if persistence is enabled
+        if let Some(_file_path) = &self.file_path {
+            if let Err(e) = self.save_to_file() {
+                eprintln!("Warning: Could not save history file after output update: {}", e);
+            }
+        }
+
+        true
+    }
+
+    /// Store the exit code of the last entry's command
+    pub fn update_last_entry_exit_code(&mut self, exit_code: Option<i32>) -> bool {
+        let Some(last_entry) = self.history.back_mut() else {
+            return false;
+        };
+        last_entry.exit_code = exit_code;
+
+        // Save to file if persistence is enabled
+        if let Some(_file_path) = &self.file_path {
+            if let Err(e) = self.save_to_file() {
+                eprintln!("Warning: Could not save history file after exit code update: {}", e);
+            }
+        }
+
+        true
+    }
+
+    /// Write every input→command pair as a line of JSON, with the home directory, username,
+    /// hostname and secrets replaced by placeholders. Returns the number of entries written.
+    pub fn export_anonymized(&self, path: &Path) -> io::Result<usize> {
+        self.export_anonymized_with(path, &Anonymizer::from_env())
+    }
+
+    /// Like `export_anonymized`, with the given anonymizer
+    pub fn export_anonymized_with(
+        &self,
+        path: &Path,
+        anonymizer: &Anonymizer,
+    ) -> io::Result<usize> {
+        #[derive(Serialize)]
+        struct CorpusEntry {
+            input: String,
+            command: String,
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        for entry in &self.history {
+            let line = CorpusEntry {
+                input: anonymizer.anonymize(&entry.input),
+                command: anonymizer.anonymize(&entry.command),
+            };
+            writeln!(writer, "{}", serde_json::to_string(&line)?)?;
+        }
+        writer.flush()?;
+
+        Ok(self.history.len())
+    }
+
     pub fn get_history(&self) -> Vec<CommandEntry> {
         self.history.iter().cloned().collect()
     }
 
+    /// The whole history as a pretty-printed JSON array of `HistoryEntryJson`, oldest first
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        let entries: Vec<HistoryEntryJson> = self.history.iter().map(Into::into).collect();
+        serde_json::to_string_pretty(&entries)
+    }
+
+    /// Compare with the entries of another history, e.g. one from another machine
+    pub fn diff(&self, other: &[CommandEntry]) -> HistoryDiff {
+        // Later entries overwrite earlier ones, so each key maps to its latest feedback
+        let feedback_here: HashMap<(&str, &str), FeedbackType> =
+            self.history.iter().map(|entry| (entry_key(entry), entry.feedback)).collect();
+        let feedback_there: HashMap<(&str, &str), FeedbackType> =
+            other.iter().map(|entry| (entry_key(entry), entry.feedback)).collect();
+
+        let mut diff = HistoryDiff::default();
+        let mut compared = HashSet::new();
+        for entry in &self.history {
+            let key = entry_key(entry);
+            match feedback_there.get(&key) {
+                None => diff.only_here.push(entry.into()),
+                Some(&there) if compared.insert(key) && feedback_here[&key] != there => {
+                    diff.feedback_differs.push(FeedbackDifference {
+                        input: entry.input.clone(),
+                        command: entry.command.clone(),
+                        here: feedback_name(feedback_here[&key]).to_string(),
+                        there: feedback_name(there).to_string(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        diff.only_there = other
+            .iter()
+            .filter(|entry| !feedback_here.contains_key(&entry_key(entry)))
+            .map(Into::into)
+            .collect();
+
+        diff
+    }
+
     /// Get a reference to the internal history entries
     pub fn entries(&self) -> &VecDeque<CommandEntry> {
         &self.history
@@ -165,6 +453,81 @@ impl CommandHistory {
         self.history.iter().rev().take(count).cloned().collect()
     }
 
+    /// Suggest past entries whose input matches the partial input, best first.
+    /// Entries with the same command are only suggested once.
+    pub fn suggest(&self, partial_input: &str, limit: usize) -> Vec<CommandEntry> {
+        self.suggest_with_weights(partial_input, limit, &SuggestionWeights::default())
+    }
+
+    /// Suggest past entries using custom ranking weights
+    pub fn suggest_with_weights(
+        &self,
+        partial_input: &str,
+        limit: usize,
+        weights: &SuggestionWeights,
+    ) -> Vec<CommandEntry> {
+        let partial = partial_input.trim().to_lowercase();
+        let newest = self.history.len().saturating_sub(1).max(1) as f64;
+
+        let mut scored: Vec<(f64, &CommandEntry)> = Vec::new();
+        for (i, entry) in self.history.iter().enumerate() {
+            let input = entry.input.to_lowercase();
+            let match_score = if input.starts_with(&partial) {
+                weights.prefix_match
+            } else if is_subsequence(&partial, &input) {
+                weights.fuzzy_match
+            } else {
+                continue;
+            };
+
+            let feedback_score = match entry.feedback {
+                FeedbackType::Helpful | FeedbackType::Edited => weights.helpful,
+                FeedbackType::NotHelpful => weights.not_helpful,
+                FeedbackType::None => 0.0,
+            };
+
+            let recency_score = weights.recency * (i as f64 / newest);
+            scored.push((match_score + feedback_score + recency_score, entry));
+        }
+
+        // Highest score first; newer entries win ties since the sort is stable
+        scored.reverse();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut suggestions: Vec<CommandEntry> = Vec::new();
+        for (_, entry) in scored {
+            if suggestions.len() >= limit {
+                break;
+            }
+            if !suggestions.iter().any(|s| s.command == entry.command) {
+                suggestions.push(entry.clone());
+            }
+        }
+
+        suggestions
+    }
+
+    /// Get past entries the user corrected (edited) whose input is similar to `input`,
+    /// most similar first. Used to feed prior corrections back into the prompt.
+    pub fn corrections_for(&self, input: &str, limit: usize) -> Vec<CommandEntry> {
+        const MIN_SIMILARITY: f64 = 0.5;
+
+        let mut matches: Vec<(f64, &CommandEntry)> = self
+            .history
+            .iter()
+            .rev()
+            .filter(|entry| {
+                entry.feedback == FeedbackType::Edited && entry.original_command.is_some()
+            })
+            .map(|entry| (word_similarity(input, &entry.input), entry))
+            .filter(|(similarity, _)| *similarity >= MIN_SIMILARITY)
+            .collect();
+
+        // Most similar first; newer entries win ties since the sort is stable
+        matches.sort_by(|a, b| b.0.total_cmp(&a.0));
+        matches.into_iter().take(limit).map(|(_, entry)| entry.clone()).collect()
+    }
+
     /// Set the file path for history persistence
     pub fn set_file_path(&mut self, file_path: String) {
         self.file_path = Some(file_path);
@@ -200,3 +563,294 @@ impl CommandHistory {
         PersistentHistory::default_history_path()
     }
 }
+
+// Keep the start of long output, noting how much was dropped
+fn truncate_output(output: &str) -> String {
+    let total = output.chars().count();
+    if total <= MAX_OUTPUT_CHARS {
+        return output.to_string();
+    }
+
+    let head: String = output.chars().take(MAX_OUTPUT_CHARS).collect();
+    format!("{}\n[truncated {} more characters]", head, total - MAX_OUTPUT_CHARS)
+}
+
+// Check whether all characters of `needle` appear in `haystack` in order
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+// Jaccard similarity of the lowercase word sets of two inputs (0.0 to 1.0)
+fn word_similarity(a: &str, b: &str) -> f64 {
+    let words = |s: &str| -> std::collections::HashSet<String> {
+        s.split_whitespace()
+            .map(|w| w.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase())
+            .filter(|w| !w.is_empty())
+            .collect()
+    };
+
+    let (a, b) = (words(a), words(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history_with(entries: &[(&str, &str, FeedbackType)]) -> CommandHistory {
+        let mut history = CommandHistory::new();
+        for (input, command, feedback) in entries {
+            history.add_entry_with_feedback(
+                input.to_string(),
+                command.to_string(),
+                None,
+                *feedback,
+                None,
+            );
+        }
+        history
+    }
+
+    #[test]
+    fn test_suggest_matches_prefix_and_fuzzy() {
+        let history = history_with(&[
+            ("list files", "ls", FeedbackType::None),
+            ("show disk usage", "df -h", FeedbackType::None),
+            ("list all files", "ls -a", FeedbackType::None),
+        ]);
+
+        let suggestions = history.suggest("list", 10);
+        let commands: Vec<&str> = suggestions.iter().map(|e| e.command.as_str()).collect();
+        assert_eq!(commands, vec!["ls -a", "ls"]);
+
+        // "dsk" is a fuzzy match for "show disk usage"
+        let suggestions = history.suggest("dsk", 10);
+        assert_eq!(suggestions.len(), 1);
+        assert_eq!(suggestions[0].command, "df -h");
+    }
+
+    #[test]
+    fn test_suggest_deduplicates_and_limits() {
+        let history = history_with(&[
+            ("list files", "ls", FeedbackType::None),
+            ("list files please", "ls", FeedbackType::None),
+            ("list hidden files", "ls -a", FeedbackType::None),
+        ]);
+
+        assert_eq!(history.suggest("list", 10).len(), 2);
+        assert_eq!(history.suggest("list", 1).len(), 1);
+    }
+
+    #[test]
+    fn test_helpful_older_entry_outranks_neutral_newer_entry() {
+        let history = history_with(&[
+            ("find big files", "du -ah . | sort -rh | head", FeedbackType::Helpful),
+            ("find big files", "find . -size +100M", FeedbackType::None),
+        ]);
+
+        let weights = SuggestionWeights { recency: 0.5, helpful: 1.0, ..Default::default() };
+        let suggestions = history.suggest_with_weights("find", 10, &weights);
+        assert_eq!(suggestions[0].command, "du -ah . | sort -rh | head");
+
+        // When recency dominates, the newer entry wins instead
+        let weights = SuggestionWeights { recency: 2.0, helpful: 1.0, ..Default::default() };
+        let suggestions = history.suggest_with_weights("find", 10, &weights);
+        assert_eq!(suggestions[0].command, "find . -size +100M");
+    }
+
+    #[test]
+    fn test_corrections_for_similar_inputs() {
+        let mut history = CommandHistory::new();
+        history.add_entry_with_feedback(
+            "show disk usage of home".to_string(),
+            "du -sh ~".to_string(),
+            None,
+            FeedbackType::Edited,
+            Some("df -h ~".to_string()),
+        );
+        history.add_entry_with_feedback(
+            "list files".to_string(),
+            "ls -la".to_string(),
+            None,
+            FeedbackType::Edited,
+            Some("ls".to_string()),
+        );
+        history.add_entry("show disk usage of home".to_string(), "du -sh ~".to_string(), None);
+
+        let corrections = history.corrections_for("show the disk usage of my home", 5);
+        assert_eq!(corrections.len(), 1);
+        assert_eq!(corrections[0].command, "du -sh ~");
+        assert_eq!(corrections[0].original_command.as_deref(), Some("df -h ~"));
+
+        assert!(history.corrections_for("restart nginx", 5).is_empty());
+    }
+
+    #[test]
+    fn test_not_helpful_entries_rank_last() {
+        let history = history_with(&[
+            ("show processes", "ps aux", FeedbackType::None),
+            ("show processes", "top", FeedbackType::NotHelpful),
+        ]);
+
+        let suggestions = history.suggest("show", 10);
+        assert_eq!(suggestions[0].command, "ps aux");
+        assert_eq!(suggestions[1].command, "top");
+    }
+
+    #[test]
+    fn test_get_by_id_survives_trimming() {
+        let mut history = CommandHistory::with_capacity(2);
+        history.add_entry("list files".to_string(), "ls".to_string(), None);
+        history.add_entry("show disk usage".to_string(), "df -h".to_string(), None);
+        let id = history.entries()[1].id.clone();
+        assert_ne!(id, history.entries()[0].id);
+
+        history.add_entry("print directory".to_string(), "pwd".to_string(), None);
+        assert_eq!(history.get_by_id(&id).unwrap().command, "df -h");
+        assert!(history.get_by_id("missing").is_none());
+    }
+
+    #[test]
+    fn test_entries_without_id_get_one_on_load() {
+        let json = r#"{"entries":[{"input":"list files","command":"ls","explanation":null,"timestamp":0,"feedback":"None","original_command":null}]}"#;
+        let persistent: PersistentHistory = serde_json::from_str(json).unwrap();
+        assert!(!persistent.entries[0].id.is_empty());
+    }
+
+    #[test]
+    fn test_excluded_commands_are_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json").to_string_lossy().into_owned();
+        let patterns = vec!["~/secrets".to_string(), "vault * read".to_string()];
+
+        let mut history =
+            CommandHistory::with_persistence(path.clone()).with_exclude_patterns(patterns);
+        assert!(!history.add_entry("show the keys".into(), "cat ~/secrets/keys".into(), None));
+        assert!(!history.add_entry("read it".into(), "vault kv read prod/db".into(), None));
+        assert!(!history.add_entry("list ~/secrets".into(), "ls".into(), None));
+        assert!(history.add_entry("list files".into(), "ls -la".into(), None));
+        assert!(history.should_record("vault kv list"));
+
+        let saved = CommandHistory::with_persistence(path.clone());
+        let commands: Vec<&str> = saved.entries().iter().map(|e| e.command.as_str()).collect();
+        assert_eq!(commands, ["ls -la"]);
+
+        assert!(matches_exclude_pattern("cat a.pem", "*.pem"));
+        assert!(!matches_exclude_pattern("vault read kv", "vault * read"));
+    }
+
+    #[test]
+    fn test_exit_code_is_stored() {
+        // Entries saved before exit codes were recorded still load, without one
+        let json = r#"{"entries":[{"id":"1","input":"list files","command":"ls","explanation":null,"timestamp":0,"feedback":"None","original_command":null,"output":"a.txt"}]}"#;
+        let persistent: PersistentHistory = serde_json::from_str(json).unwrap();
+        assert_eq!(persistent.entries[0].exit_code, None);
+
+        let mut history = CommandHistory::new();
+        assert!(!history.update_last_entry_exit_code(Some(0)));
+        history.add_entry("missing file".to_string(), "cat nope.txt".to_string(), None);
+        assert!(history.update_last_entry_exit_code(Some(1)));
+        assert_eq!(history.entries()[0].exit_code, Some(1));
+
+        let json: serde_json::Value = serde_json::from_str(&history.to_json().unwrap()).unwrap();
+        assert_eq!(json[0]["exit_code"], 1);
+    }
+
+    #[test]
+    fn test_last_entry_output_is_truncated() {
+        let mut history = CommandHistory::new();
+        assert!(!history.update_last_entry_output("nothing to attach to"));
+
+        history.add_entry("list files".to_string(), "ls".to_string(), None);
+        assert!(history.update_last_entry_output("a.txt\nb.txt"));
+        assert_eq!(history.entries()[0].output.as_deref(), Some("a.txt\nb.txt"));
+
+        history.update_last_entry_output("GITHUB_TOKEN=ghp_abc123");
+        assert_eq!(history.entries()[0].output.as_deref(), Some("GITHUB_TOKEN=<SECRET>"));
+
+        let long = "x".repeat(MAX_OUTPUT_CHARS + 10);
+        history.update_last_entry_output(&long);
+        let stored = history.entries()[0].output.clone().unwrap();
+        assert!(stored.starts_with(&"x".repeat(MAX_OUTPUT_CHARS)));
+        assert!(stored.ends_with("[truncated 10 more characters]"));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mut history = CommandHistory::new();
+        history.add_entry_with_feedback(
+            "list files".to_string(),
+            "ls -la".to_string(),
+            None,
+            FeedbackType::Edited,
+            Some("ls".to_string()),
+        );
+        history.history[0].timestamp = 1_714_566_600;
+
+        let json: serde_json::Value = serde_json::from_str(&history.to_json().unwrap()).unwrap();
+        let entry = &json[0];
+        assert_eq!(entry["timestamp"], "2024-05-01T12:30:00Z");
+        assert_eq!(entry["feedback"], "edited");
+        assert_eq!(entry["command"], "ls -la");
+        assert_eq!(entry["original_command"], "ls");
+        assert_eq!(entry["id"], history.history[0].id.as_str());
+    }
+
+    #[test]
+    fn test_export_anonymized() {
+        let mut history = CommandHistory::new();
+        history.add_entry(
+            "list alice's projects".to_string(),
+            "ls /home/alice/projects".to_string(),
+            None,
+        );
+        let anonymizer =
+            Anonymizer::new(Some("/home/alice".to_string()), Some("alice".to_string()), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("corpus.jsonl");
+        assert_eq!(history.export_anonymized_with(&path, &anonymizer).unwrap(), 1);
+
+        let exported = std::fs::read_to_string(&path).unwrap();
+        assert!(!exported.contains("alice"));
+        let line: serde_json::Value = serde_json::from_str(exported.trim()).unwrap();
+        assert_eq!(line["input"], "list <USER>'s projects");
+        assert_eq!(line["command"], "ls <HOME>/projects");
+    }
+
+    #[test]
+    fn test_diff() {
+        let here = history_with(&[
+            ("list files", "ls", FeedbackType::Helpful),
+            ("show disk usage", "df -h", FeedbackType::None),
+            ("git status", "git status", FeedbackType::None),
+        ]);
+        let there = history_with(&[
+            ("list files", "ls", FeedbackType::NotHelpful),
+            ("git status", "git status", FeedbackType::None),
+            ("show memory", "free -h", FeedbackType::None),
+        ]);
+
+        let diff = here.diff(&there.get_history());
+        let inputs = |entries: &[HistoryEntryJson]| {
+            entries.iter().map(|entry| entry.input.clone()).collect::<Vec<String>>()
+        };
+        assert_eq!(inputs(&diff.only_here), ["show disk usage"]);
+        assert_eq!(inputs(&diff.only_there), ["show memory"]);
+        assert_eq!(
+            diff.feedback_differs,
+            [FeedbackDifference {
+                input: "list files".to_string(),
+                command: "ls".to_string(),
+                here: "helpful".to_string(),
+                there: "not_helpful".to_string(),
+            }]
+        );
+        assert!(here.diff(&here.get_history()).is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::anonymize::redact_secrets;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The most recent command that failed when run through the assistant, kept between
+/// invocations so it can be looked at again with `--last-error`. Secrets are redacted from
+/// its error output, which is also sent to the LLM when asking for a fix.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LastError {
+    pub command: String,
+    /// The command's error output, with secrets redacted
+    pub stderr: String,
+    /// None if the command couldn't be started or was stopped by a signal
+    pub exit_code: Option<i32>,
+    /// Seconds since the Unix epoch of the failure
+    pub timestamp: u64,
+}
+
+impl LastError {
+    pub fn new(command: &str, stderr: &str, exit_code: Option<i32>) -> Self {
+        let timestamp =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        LastError {
+            command: command.to_string(),
+            stderr: redact_secrets(stderr),
+            exit_code,
+            timestamp,
+        }
+    }
+
+    /// Load the failure stored at `path`, if there is one
+    pub fn load(path: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    /// Replace the failure stored at `path` with this one
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self).map_err(io::Error::other)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("last_error.json");
+        assert_eq!(LastError::load(&path), None);
+
+        let error = LastError::new("cat missing.txt", "cat: missing.txt: No such file", Some(1));
+        error.save(&path).unwrap();
+        assert_eq!(LastError::load(&path), Some(error.clone()));
+
+        // Only the latest failure is kept
+        let newer = LastError::new("false", "Command exited with exit status: 1", Some(1));
+        newer.save(&path).unwrap();
+        assert_eq!(LastError::load(&path), Some(newer));
+    }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let error = LastError::new(
+            "curl api.example.com",
+            "curl: (22) 401 for Authorization: Bearer abc.def",
+            Some(22),
+        );
+        assert!(!error.stderr.contains("abc.def"), "{}", error.stderr);
+        assert!(error.stderr.starts_with("curl: (22) 401"));
+    }
+}
@@ -1,5 +1,16 @@
+pub mod anonymize;
+pub mod explanations;
+pub mod favorites;
 pub mod history;
+pub mod last_error;
 pub mod persistence;
 
-pub use history::CommandHistory;
+pub use anonymize::Anonymizer;
+pub use explanations::ExplanationCache;
+pub use favorites::{Favorite, Favorites};
+pub use history::{
+    matches_exclude_pattern, CommandHistory, FeedbackDifference, HistoryDiff, HistoryEntryJson,
+    SuggestionWeights,
+};
+pub use last_error::LastError;
 pub use persistence::{CommandEntry, CommandHistory as PersistentHistory};
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// Feedback type for command execution
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
@@ -18,6 +18,9 @@ pub enum FeedbackType {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CommandEntry {
+    /// Stable unique ID, assigned on creation (or on load for entries saved without one)
+    #[serde(default = "new_entry_id")]
+    pub id: String,
     /// Natural language input
     pub input: String,
     /// Generated or edited command
@@ -30,6 +33,13 @@ pub struct CommandEntry {
     pub feedback: FeedbackType,
     /// Original command if edited
     pub original_command: Option<String>,
+    /// What the command printed when it was run, truncated
+    #[serde(default)]
+    pub output: Option<String>,
+    /// The command's exit code, if it ran to completion. None for entries that were only
+    /// copied or dry-run, and for those saved before exit codes were recorded.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -37,6 +47,14 @@ pub struct CommandHistory {
     pub entries: Vec<CommandEntry>,
 }
 
+// The user's home directory (`HOME`, or `USERPROFILE` on Windows), looked up the same way
+// as `core::home_dir`, which this crate can't depend on
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os(if cfg!(windows) { "USERPROFILE" } else { "HOME" })
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
 impl Default for CommandHistory {
     fn default() -> Self {
         Self::new()
@@ -50,38 +68,32 @@ impl CommandHistory {
 
     /// Get the default history file path, platform-independent
     pub fn default_history_path() -> io::Result<String> {
-        let home = if cfg!(windows) {
-            std::env::var("USERPROFILE").map_err(|_| {
-                io::Error::new(
-                    io::ErrorKind::NotFound,
-                    "Could not find USERPROFILE environment variable",
-                )
-            })?
-        } else {
-            std::env::var("HOME").map_err(|_| {
-                io::Error::new(io::ErrorKind::NotFound, "Could not find HOME environment variable")
-            })?
-        };
-
-        let app_dir = Path::new(&home).join(".shell-assistant");
-        let history_file = app_dir.join("history.json");
+        let home = home_dir().ok_or_else(|| {
+            let var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Could not find {} environment variable", var),
+            )
+        })?;
+        let history_file = home.join(".shell-assistant").join("history.json");
 
         Ok(history_file.to_string_lossy().into_owned())
     }
 
     pub fn add_entry(&mut self, input: String, command: String, explanation: Option<String>) {
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let timestamp =
+            std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
 
         self.entries.push(CommandEntry {
+            id: new_entry_id(),
             input,
             command,
             explanation,
             timestamp,
             feedback: FeedbackType::None,
             original_command: None,
+            output: None,
+            exit_code: None,
         });
     }
 
@@ -94,18 +106,19 @@ impl CommandHistory {
         feedback: FeedbackType,
         original_command: Option<String>,
     ) {
-        let timestamp = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let timestamp =
+            std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
 
         self.entries.push(CommandEntry {
+            id: new_entry_id(),
             input,
             command,
             explanation,
             timestamp,
             feedback,
             original_command,
+            output: None,
+            exit_code: None,
         });
     }
 
@@ -139,9 +152,41 @@ impl CommandHistory {
         Ok(())
     }
 
+    /// Get the entry with the given ID
+    pub fn get_by_id(&self, id: &str) -> Option<&CommandEntry> {
+        self.entries.iter().find(|entry| entry.id == id)
+    }
+
     pub fn load_from_file(file_path: &str) -> io::Result<Self> {
         let file = File::open(file_path)?;
         let history: CommandHistory = serde_json::from_reader(file)?;
         Ok(history)
     }
+
+    /// Parse a history file entry by entry without stopping at bad entries. Each entry's
+    /// number (from 1) comes with its timestamp, or why it couldn't be read. Fails if the
+    /// file isn't a history file at all, e.g. when it was cut off.
+    pub fn check_file(file_path: &str) -> io::Result<Vec<(usize, Result<u64, String>)>> {
+        #[derive(Deserialize)]
+        struct RawHistory {
+            entries: Vec<serde_json::Value>,
+        }
+
+        let file = File::open(file_path)?;
+        let raw: RawHistory = serde_json::from_reader(file)?;
+        Ok(raw
+            .entries
+            .into_iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let entry = serde_json::from_value::<CommandEntry>(entry);
+                (i + 1, entry.map(|entry| entry.timestamp).map_err(|e| e.to_string()))
+            })
+            .collect())
+    }
+}
+
+/// Generate a new unique history entry ID
+pub(crate) fn new_entry_id() -> String {
+    uuid::Uuid::new_v4().to_string()
 }